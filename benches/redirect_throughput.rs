@@ -0,0 +1,173 @@
+//! Redirect throughput under concurrent load.
+//!
+//! Starts the server binary against a fresh SQLite database, creates one short link and
+//! hammers `/link/{code}` from many concurrent clients, then prints requests per second
+//! and latency percentiles.
+//!
+//! ```bash
+//! cargo bench --bench redirect_throughput
+//! BENCH_CONCURRENCY=256 BENCH_SECONDS=10 cargo bench --bench redirect_throughput
+//! ```
+//! `BENCH_DATABASE_URL` runs it against another storage, e.g. `memory` or a `postgres://` url.
+
+use std::net::TcpListener;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper::{Method, Request};
+use hyper_util::client::legacy::{connect::HttpConnector, Client};
+use hyper_util::rt::TokioExecutor;
+
+type HttpClient = Client<HttpConnector, Full<Bytes>>;
+
+struct Server {
+    child: Child,
+    base: String,
+    dir: std::path::PathBuf,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name).ok().and_then(|value| value.parse().ok()).unwrap_or(default)
+}
+
+fn start_server() -> Server {
+    let dir = std::env::temp_dir().join(format!("url-shortener-bench-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let database_url = std::env::var("BENCH_DATABASE_URL")
+        .unwrap_or_else(|_| dir.join("bench.db").to_string_lossy().to_string());
+
+    // let the OS pick a free port
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let child = Command::new(env!("CARGO_BIN_EXE_url-shortener"))
+        .current_dir(&dir)
+        .env("URL_SHORTENER_ADDRESS", "127.0.0.1")
+        .env("URL_SHORTENER_PORT", port.to_string())
+        .env("URL_SHORTENER_DATABASE_URL", database_url)
+        .env("URL_SHORTENER_JWT_SECRET", "redirect-throughput-bench-signing-key")
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("Failed to start the server");
+
+    Server { child, base: format!("http://127.0.0.1:{}", port), dir }
+}
+
+async fn send(client: &HttpClient, method: Method, uri: &str, token: Option<&str>, body: &str) -> (u16, String) {
+    let mut request = Request::builder().method(method).uri(uri).header("content-type", "application/json");
+    if let Some(token) = token {
+        request = request.header("authorization", format!("Bearer {}", token));
+    }
+    let response = client.request(request.body(Full::new(Bytes::from(body.to_string()))).unwrap()).await.unwrap();
+    let status = response.status().as_u16();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (status, String::from_utf8_lossy(&body).to_string())
+}
+
+async fn wait_until_ready(client: &HttpClient, base: &str) {
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while Instant::now() < deadline {
+        let request = Request::get(format!("{}/test", base)).body(Full::new(Bytes::new())).unwrap();
+        if client.request(request).await.is_ok() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("Server did not start within 30 seconds");
+}
+
+/// Creates a user and a link, returns the link's short code.
+async fn create_link(client: &HttpClient, base: &str) -> String {
+    let credentials = r#"{"username":"bench","password":"bench"}"#;
+    send(client, Method::POST, &format!("{}/create_user", base), None, credentials).await;
+    let (_, body) = send(client, Method::POST, &format!("{}/login", base), None, credentials).await;
+    let login: serde_json::Value = serde_json::from_str(&body).unwrap();
+    let token = login["data"].as_str().expect("Login failed").to_string();
+
+    let (status, body) = send(
+        client,
+        Method::POST,
+        &format!("{}/shorten-link", base),
+        Some(&token),
+        r#"{"url":"https://example.com/bench","code":"bench"}"#,
+    ).await;
+    assert_eq!(status, 200, "Failed to create the link: {}", body);
+    "bench".to_string()
+}
+
+fn percentile(sorted: &[Duration], percentile: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    sorted[((sorted.len() - 1) as f64 * percentile) as usize]
+}
+
+#[tokio::main]
+async fn main() {
+    let concurrency: usize = env_or("BENCH_CONCURRENCY", 64);
+    let seconds: u64 = env_or("BENCH_SECONDS", 5);
+
+    let server = start_server();
+    let mut connector = HttpConnector::new();
+    connector.set_nodelay(true);
+    let client: HttpClient = Client::builder(TokioExecutor::new())
+        .pool_max_idle_per_host(concurrency)
+        .build(connector);
+
+    wait_until_ready(&client, &server.base).await;
+    let code = create_link(&client, &server.base).await;
+    let uri = format!("{}/link/{}", server.base, code);
+
+    let started = Instant::now();
+    let deadline = started + Duration::from_secs(seconds);
+    let mut workers = Vec::new();
+    for _ in 0..concurrency {
+        let client = client.clone();
+        let uri = uri.clone();
+        workers.push(tokio::spawn(async move {
+            let mut latencies = Vec::new();
+            let mut errors = 0u64;
+            while Instant::now() < deadline {
+                let request_started = Instant::now();
+                let request = Request::get(&uri).body(Full::new(Bytes::new())).unwrap();
+                match client.request(request).await {
+                    Ok(response) if response.status().is_redirection() => {
+                        let _ = response.into_body().collect().await;
+                        latencies.push(request_started.elapsed());
+                    }
+                    _ => errors += 1,
+                }
+            }
+            (latencies, errors)
+        }));
+    }
+
+    let mut latencies = Vec::new();
+    let mut errors = 0;
+    for worker in workers {
+        let (worker_latencies, worker_errors) = worker.await.unwrap();
+        latencies.extend(worker_latencies);
+        errors += worker_errors;
+    }
+    let elapsed = started.elapsed().as_secs_f64();
+    latencies.sort();
+
+    println!("redirect throughput: {} clients for {}s", concurrency, seconds);
+    println!("  redirects: {} ({} errors)", latencies.len(), errors);
+    println!("  throughput: {:.0} req/s", latencies.len() as f64 / elapsed);
+    println!(
+        "  latency: p50 {:.2?}  p90 {:.2?}  p99 {:.2?}  max {:.2?}",
+        percentile(&latencies, 0.5),
+        percentile(&latencies, 0.9),
+        percentile(&latencies, 0.99),
+        latencies.last().copied().unwrap_or_default(),
+    );
+}
@@ -0,0 +1,280 @@
+use std::io::{BufRead, IsTerminal};
+use std::process::Command;
+
+use clap::Subcommand;
+
+use crate::model::Role;
+use crate::storage::{LinkFilter, Storage, UserError};
+use crate::validation;
+
+/// Maintenance commands, they work directly on the configured database.
+#[derive(Subcommand, Debug)]
+pub enum AdminCommand {
+    /// Manage user accounts
+    #[command(subcommand)]
+    User(UserCommand),
+    /// Inspect and change the links of every user
+    #[command(subcommand)]
+    Link(LinkCommand),
+    /// Show system wide numbers, or the daily clicks of one link
+    Stats {
+        code: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum UserCommand {
+    /// List all users
+    List,
+    /// Create a user, the password is asked for or read from stdin
+    Create {
+        username: String,
+    },
+    /// Stop a user from logging in or using their API keys, ends their sessions
+    Disable {
+        username: String,
+    },
+    /// Let a disabled user back in
+    Enable {
+        username: String,
+    },
+    /// Set a new password, asked for or read from stdin, and end the user's sessions
+    ResetPassword {
+        username: String,
+    },
+    /// Make a user an admin or take the rights away, applies to their next request
+    SetRole {
+        username: String,
+        #[arg(value_parser = ["user", "admin"])]
+        role: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum LinkCommand {
+    /// List links, newest first
+    List {
+        /// Only links of this user
+        #[arg(long)]
+        user: Option<String>,
+        /// Only links whose code or destination contain this text
+        #[arg(long)]
+        search: Option<String>,
+        /// Only archived links
+        #[arg(long, conflicts_with = "active")]
+        archived: bool,
+        /// Only links that aren't archived
+        #[arg(long)]
+        active: bool,
+        #[arg(long, default_value_t = 50)]
+        limit: u32,
+        #[arg(long, default_value_t = 0)]
+        offset: u32,
+    },
+    /// Point a link at a new destination
+    Edit {
+        code: String,
+        url: String,
+    },
+    /// Take a link down, its code stays reserved so it never points somewhere else
+    Delete {
+        code: String,
+        /// Delete the link and its click history for good, the code can be claimed again afterwards
+        #[arg(long)]
+        release_code: bool,
+    },
+}
+
+pub async fn run(db: &dyn Storage, command: AdminCommand) -> Result<(), String> {
+    match command {
+        AdminCommand::User(command) => user(db, command).await,
+        AdminCommand::Link(command) => link(db, command).await,
+        AdminCommand::Stats { code } => stats(db, code).await,
+    }
+}
+
+async fn user(db: &dyn Storage, command: UserCommand) -> Result<(), String> {
+    match command {
+        UserCommand::List => {
+            let users = db.list_users().await.map_err(|e| e.to_string())?;
+            let rows = users.into_iter().map(|user| vec![
+                user.id.to_string(),
+                user.username,
+                user.role.as_str().to_string(),
+                user.links.to_string(),
+                if user.disabled { "disabled" } else { "active" }.to_string(),
+            ]).collect();
+            print_table(&["ID", "USERNAME", "ROLE", "LINKS", "STATE"], rows);
+        },
+        UserCommand::Create { username } => {
+            let password = read_password()?;
+            match db.create_user(&username, &password).await {
+                Ok(_) => println!("User {} created", username),
+                Err(UserError::UserAlreadyExists) => return Err(format!("User {} already exists", username)),
+                Err(_) => return Err("Failed to create the user".to_string()),
+            }
+        },
+        UserCommand::Disable { username } => {
+            set_user_disabled(db, &username, true).await?;
+            println!("User {} disabled", username);
+        },
+        UserCommand::Enable { username } => {
+            set_user_disabled(db, &username, false).await?;
+            println!("User {} enabled", username);
+        },
+        UserCommand::ResetPassword { username } => {
+            let password = read_password()?;
+            match db.reset_password(&username, &password).await.map_err(|e| e.to_string())? {
+                true => println!("Password of {} changed", username),
+                false => return Err(format!("User {} doesn't exist", username)),
+            }
+        },
+        UserCommand::SetRole { username, role } => {
+            let role = Role::parse(&role).ok_or(format!("Unknown role {}", role))?;
+            match db.set_user_role(&username, role).await.map_err(|e| e.to_string())? {
+                true => println!("{} is now {}", username, role.as_str()),
+                false => return Err(format!("User {} doesn't exist", username)),
+            }
+        },
+    }
+    Ok(())
+}
+
+async fn set_user_disabled(db: &dyn Storage, username: &str, disabled: bool) -> Result<(), String> {
+    match db.set_user_disabled(username, disabled).await.map_err(|e| e.to_string())? {
+        true => Ok(()),
+        false => Err(format!("User {} doesn't exist", username)),
+    }
+}
+
+async fn link(db: &dyn Storage, command: LinkCommand) -> Result<(), String> {
+    match command {
+        LinkCommand::List { user, search, archived, active, limit, offset } => {
+            let filter = LinkFilter {
+                owner: user,
+                search,
+                archived: match (archived, active) {
+                    (true, _) => Some(true),
+                    (_, true) => Some(false),
+                    _ => None,
+                },
+                limit,
+                offset,
+            };
+            let links = db.find_links(&filter).await.map_err(|e| e.to_string())?;
+            let rows = links.into_iter().map(|link| {
+                let state = match (link.taken_down, link.archived, link.is_expired()) {
+                    (true, _, _) => "taken down",
+                    (_, true, _) => "archived",
+                    (_, _, true) => "expired",
+                    _ => "active",
+                };
+                let clicks = match link.max_clicks {
+                    Some(max_clicks) => format!("{}/{}", link.clicks, max_clicks),
+                    None => link.clicks.to_string(),
+                };
+                vec![link.short, link.owner.unwrap_or_default(), clicks, state.to_string(), link.long]
+            }).collect();
+            print_table(&["CODE", "OWNER", "CLICKS", "STATE", "DESTINATION"], rows);
+        },
+        LinkCommand::Edit { code, url } => {
+            let code = validation::code_rules().normalize(&code);
+            let url = validation::normalize_url(&url).map_err(|e| e.message)?;
+            if !db.url_exists(&code).await.map_err(|e| e.to_string())? {
+                return Err(format!("Link {} doesn't exist", code));
+            }
+            db.update_url(&code, &url).await.map_err(|e| e.to_string())?;
+            println!("Link {} now points to {}", code, url);
+        },
+        LinkCommand::Delete { code, release_code: false } => {
+            let code = validation::code_rules().normalize(&code);
+            match db.set_url_taken_down(&code, true).await.map_err(|e| e.to_string())? {
+                true => println!("Link {} taken down, its code stays reserved (--release-code deletes it for good)", code),
+                false => return Err(format!("Link {} doesn't exist", code)),
+            }
+        },
+        LinkCommand::Delete { code, release_code: true } => {
+            let code = validation::code_rules().normalize(&code);
+            match db.delete_url(&code).await.map_err(|e| e.to_string())? {
+                true => println!("Link {} deleted, its code can be claimed again", code),
+                false => return Err(format!("Link {} doesn't exist", code)),
+            }
+        },
+    }
+    Ok(())
+}
+
+async fn stats(db: &dyn Storage, code: Option<String>) -> Result<(), String> {
+    match code {
+        Some(code) => {
+            let code = validation::code_rules().normalize(&code);
+            if !db.url_exists(&code).await.map_err(|e| e.to_string())? {
+                return Err(format!("Link {} doesn't exist", code));
+            }
+            let stats = db.get_link_stats(&code).await.map_err(|e| e.to_string())?;
+            println!("Total clicks: {}", stats.total);
+            let rows = stats.per_day.into_iter().map(|day| vec![day.day, day.clicks.to_string()]).collect();
+            print_table(&["DAY", "CLICKS"], rows);
+        },
+        None => {
+            let stats = db.system_stats().await.map_err(|e| e.to_string())?;
+            println!("Users:               {} ({} disabled)", stats.users, stats.disabled_users);
+            println!("Links:               {} ({} archived, {} taken down)", stats.links, stats.archived_links, stats.taken_down_links);
+            println!("Clicks:              {}", stats.clicks);
+            println!("Clicks in last 24h:  {}", stats.clicks_last_day);
+        },
+    }
+    Ok(())
+}
+
+/// Prompts for the password on stderr so the command can also be fed from a pipe.
+/// Turns echoing of typed characters on or off for the terminal on stdin.
+fn set_terminal_echo(echo: bool) {
+    let _ = Command::new("stty").arg(if echo { "echo" } else { "-echo" }).status();
+}
+
+/// Passwords never come from the command line, where other users can see them in the process list.
+/// At a terminal the password is typed without echo, otherwise it's the first line of stdin.
+fn read_password() -> Result<String, String> {
+    let stdin = std::io::stdin();
+    let terminal = stdin.is_terminal();
+    if terminal {
+        eprint!("Password: ");
+        set_terminal_echo(false);
+    }
+    let mut line = String::new();
+    let read = stdin.lock().read_line(&mut line);
+    if terminal {
+        set_terminal_echo(true);
+        eprintln!();
+    }
+    read.map_err(|e| format!("Failed to read the password: {}", e))?;
+
+    let password = line.trim_end_matches(['\r', '\n']).to_string();
+    match password.is_empty() {
+        true => Err("Password must not be empty".to_string()),
+        false => Ok(password),
+    }
+}
+
+fn print_table(header: &[&str], rows: Vec<Vec<String>>) {
+    let mut widths: Vec<usize> = header.iter().map(|title| title.len()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let format_row = |cells: Vec<&str>| -> String {
+        cells.iter().zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+    println!("{}", format_row(header.to_vec()));
+    for row in &rows {
+        println!("{}", format_row(row.iter().map(String::as_str).collect()));
+    }
+}
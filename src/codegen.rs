@@ -0,0 +1,175 @@
+use std::sync::OnceLock;
+
+use rand::{thread_rng, Rng};
+use serde::Deserialize;
+
+use crate::config::{config, CodesConfig};
+
+const BASE62: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+const ADJECTIVES: &[&str] = &[
+    "amber", "bold", "brave", "bright", "calm", "clever", "cosy", "crisp",
+    "eager", "fancy", "gentle", "golden", "happy", "jolly", "keen", "lucky",
+    "mellow", "merry", "misty", "noble", "plucky", "proud", "quick", "quiet",
+    "rapid", "shiny", "silent", "smart", "sunny", "swift", "vivid", "witty",
+];
+
+const NOUNS: &[&str] = &[
+    "badger", "beacon", "breeze", "canyon", "cedar", "comet", "falcon", "fern",
+    "forest", "harbor", "island", "lagoon", "lantern", "maple", "meadow", "meteor",
+    "orchid", "otter", "panda", "pebble", "pine", "planet", "raven", "river",
+    "rocket", "sparrow", "summit", "thunder", "tiger", "valley", "willow", "zebra",
+];
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CodeStrategy {
+    /// Prefix of the md5 hash of the destination url.
+    #[serde(alias = "md5")]
+    Hash,
+    /// Random base62 string.
+    Random,
+    /// Row id encoded as base62 using an alphabet shuffled by the salt.
+    Sequential,
+    /// Human readable `adjective-noun-number` codes.
+    Words,
+}
+
+impl CodeStrategy {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "hash" | "md5" => Some(CodeStrategy::Hash),
+            "random" => Some(CodeStrategy::Random),
+            "sequential" => Some(CodeStrategy::Sequential),
+            "words" => Some(CodeStrategy::Words),
+            _ => None,
+        }
+    }
+}
+
+/// Deployment wide code generation settings.
+pub struct CodeSettings {
+    pub strategy: CodeStrategy,
+    pub length: usize,
+    pub salt: String,
+}
+
+impl CodeSettings {
+    fn from_config(codes: &CodesConfig) -> Self {
+        CodeSettings { strategy: codes.strategy, length: codes.length, salt: codes.salt.clone() }
+    }
+}
+
+static CODE_SETTINGS: OnceLock<CodeSettings> = OnceLock::new();
+
+pub fn code_settings() -> &'static CodeSettings {
+    CODE_SETTINGS.get_or_init(|| CodeSettings::from_config(&config().codes))
+}
+
+pub trait CodeGenerator: Send + Sync {
+    /// Produces a candidate short code. `seq` is the id the new row is expected to get,
+    /// `attempt` counts how many candidates were already rejected because of collisions.
+    fn generate(&self, url: &str, seq: u64, attempt: u32) -> String;
+}
+
+pub struct HashGenerator;
+
+impl CodeGenerator for HashGenerator {
+    fn generate(&self, url: &str, _seq: u64, attempt: u32) -> String {
+        // later attempts salt the hash and grow the code so that collisions become less likely
+        let digest = match attempt {
+            0 => format!("{:x}", md5::compute(url)),
+            _ => format!("{:x}", md5::compute(format!("{}#{}", url, attempt))),
+        };
+        let length = (6 + attempt as usize / 2).min(digest.len());
+        digest[..length].to_string()
+    }
+}
+
+pub struct RandomGenerator {
+    pub length: usize,
+}
+
+impl CodeGenerator for RandomGenerator {
+    fn generate(&self, _url: &str, _seq: u64, attempt: u32) -> String {
+        let mut rng = thread_rng();
+        let length = self.length + attempt as usize / 2;
+        (0..length)
+            .map(|_| BASE62[rng.gen_range(0..BASE62.len())] as char)
+            .collect()
+    }
+}
+
+pub struct SequentialGenerator {
+    alphabet: Vec<u8>,
+}
+
+impl SequentialGenerator {
+    pub fn new(salt: &str) -> Self {
+        SequentialGenerator { alphabet: shuffle_alphabet(BASE62, salt) }
+    }
+}
+
+impl CodeGenerator for SequentialGenerator {
+    fn generate(&self, _url: &str, seq: u64, attempt: u32) -> String {
+        encode_base(seq + attempt as u64, &self.alphabet)
+    }
+}
+
+pub struct WordsGenerator;
+
+impl CodeGenerator for WordsGenerator {
+    fn generate(&self, _url: &str, _seq: u64, attempt: u32) -> String {
+        let mut rng = thread_rng();
+        let adjective = ADJECTIVES[rng.gen_range(0..ADJECTIVES.len())];
+        let noun = NOUNS[rng.gen_range(0..NOUNS.len())];
+        // widen the number range when we keep hitting taken codes
+        let max = 100u32.saturating_mul(10u32.saturating_pow(attempt / 3));
+        format!("{}-{}-{}", adjective, noun, rng.gen_range(0..max))
+    }
+}
+
+pub fn generator(strategy: CodeStrategy) -> Box<dyn CodeGenerator> {
+    let settings = code_settings();
+    match strategy {
+        CodeStrategy::Hash => Box::new(HashGenerator),
+        CodeStrategy::Random => Box::new(RandomGenerator { length: settings.length }),
+        CodeStrategy::Sequential => Box::new(SequentialGenerator::new(&settings.salt)),
+        CodeStrategy::Words => Box::new(WordsGenerator),
+    }
+}
+
+fn encode_base(mut value: u64, alphabet: &[u8]) -> String {
+    let base = alphabet.len() as u64;
+    let mut encoded = Vec::new();
+    loop {
+        encoded.push(alphabet[(value % base) as usize]);
+        value /= base;
+        if value == 0 {
+            break;
+        }
+    }
+    encoded.reverse();
+    String::from_utf8(encoded).unwrap()
+}
+
+/// Deterministic shuffle (the same one hashids uses) so that sequential ids don't look sequential.
+fn shuffle_alphabet(alphabet: &[u8], salt: &str) -> Vec<u8> {
+    let mut alphabet = alphabet.to_vec();
+    let salt = salt.as_bytes();
+    if salt.is_empty() {
+        return alphabet;
+    }
+
+    let mut v = 0;
+    let mut p = 0;
+    for i in (1..alphabet.len()).rev() {
+        v %= salt.len();
+        let n = salt[v] as usize;
+        p += n;
+        let j = (n + v + p) % i;
+        alphabet.swap(i, j);
+        v += 1;
+    }
+    alphabet
+}
@@ -0,0 +1,439 @@
+use std::fmt::Display;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use clap::{Parser, Subcommand};
+use serde::Deserialize;
+use url::Url;
+
+use crate::admin::AdminCommand;
+use crate::codegen::CodeStrategy;
+use crate::proxy::IpRange;
+
+/// Read when neither --config nor URL_SHORTENER_CONFIG is given, it's fine for it not to exist.
+const DEFAULT_CONFIG_FILE: &str = "url-shortener.toml";
+/// HS256 keys shorter than the 256-bit hash output are easier to brute force than the hash itself.
+const MIN_JWT_SECRET_LENGTH: usize = 32;
+
+#[derive(Parser, Debug)]
+#[command(version, about = "Url shortener server")]
+pub struct Cli {
+    /// TOML config file [default: url-shortener.toml if it exists]
+    #[arg(long, global = true, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+    /// Address to listen on
+    #[arg(long, global = true)]
+    pub address: Option<String>,
+    /// Port to listen on
+    #[arg(long, global = true)]
+    pub port: Option<u16>,
+    /// SQLite file, `memory` or a postgres:// url
+    #[arg(long, global = true, value_name = "URL")]
+    pub database_url: Option<String>,
+    /// Directory with the built frontend
+    #[arg(long, global = true, value_name = "DIR")]
+    pub static_dir: Option<PathBuf>,
+    /// Public address of the backend, e.g. https://sho.rt
+    #[arg(long, global = true, value_name = "URL")]
+    pub public_url: Option<String>,
+    /// Origin allowed to call the API from a browser, can be repeated
+    #[arg(long = "cors-origin", global = true, value_name = "ORIGIN")]
+    pub cors_origins: Vec<String>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Apply pending database migrations and exit
+    Migrate,
+    /// Generate a new JWT signing key, the previous one keeps verifying old tokens
+    RotateJwtKey,
+    #[command(flatten)]
+    Admin(AdminCommand),
+}
+
+/// Settings of the whole service. Every value has a default which is overridden, in this order,
+/// by the config file, URL_SHORTENER_* environment variables and command line flags.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub cache: CacheConfig,
+    pub auth: AuthConfig,
+    pub codes: CodesConfig,
+    pub urls: UrlsConfig,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub address: String,
+    pub port: u16,
+    /// Used to build full short links, the Host header is used when it's missing.
+    pub public_url: Option<String>,
+    pub static_dir: PathBuf,
+    /// `*` allows any origin.
+    pub cors_origins: Vec<String>,
+    /// HTML shown for unknown codes instead of the built-in page.
+    pub not_found_page: Option<PathBuf>,
+    /// Addresses or CIDR ranges of reverse proxies whose X-Forwarded-For header is believed.
+    pub trusted_proxies: Vec<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            address: "127.0.0.1".to_string(),
+            port: 2222,
+            public_url: None,
+            static_dir: PathBuf::from("./public/www"),
+            cors_origins: vec!["*".to_string()],
+            not_found_page: None,
+            trusted_proxies: Vec::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub url: String,
+    /// SQLite read connections, one per cpu when missing.
+    pub readers: Option<usize>,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig { url: "urls.db".to_string(), readers: None }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// Number of cached links, 0 turns the cache off.
+    pub size: usize,
+    /// Seconds.
+    pub ttl: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig { size: 10_000, ttl: 60 }
+    }
+}
+
+/// Token lifetimes are in seconds.
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    pub jwt_keys_file: PathBuf,
+    /// Takes precedence over the key file.
+    pub jwt_secret: Option<String>,
+    pub access_token_ttl: i64,
+    pub refresh_token_ttl: i64,
+    /// Refresh token lifetime for "remember me" logins.
+    pub persistent_refresh_token_ttl: i64,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            jwt_keys_file: PathBuf::from("jwt_keys.json"),
+            jwt_secret: None,
+            access_token_ttl: 60 * 60,
+            refresh_token_ttl: 60 * 60,
+            persistent_refresh_token_ttl: 30 * 24 * 60 * 60,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct CodesConfig {
+    pub strategy: CodeStrategy,
+    /// Length of `random` codes.
+    pub length: usize,
+    /// Shuffles the alphabet of `sequential` codes.
+    pub salt: String,
+    /// Characters allowed in custom codes.
+    pub alphabet: String,
+    pub min_length: usize,
+    pub max_length: usize,
+    pub case_sensitive: bool,
+    /// Added to the built-in reserved codes.
+    pub reserved: Vec<String>,
+}
+
+impl Default for CodesConfig {
+    fn default() -> Self {
+        CodesConfig {
+            strategy: CodeStrategy::Hash,
+            length: 7,
+            salt: String::new(),
+            alphabet: "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789-_".to_string(),
+            min_length: 3,
+            max_length: 32,
+            case_sensitive: true,
+            reserved: Vec::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct UrlsConfig {
+    pub allowed_schemes: Vec<String>,
+    pub max_length: usize,
+    /// Most links a single `/links/bulk` request may create.
+    pub max_batch_size: usize,
+}
+
+impl Default for UrlsConfig {
+    fn default() -> Self {
+        UrlsConfig { allowed_schemes: vec!["http".to_string(), "https".to_string()], max_length: 2048, max_batch_size: 1000 }
+    }
+}
+
+fn env_value<T: FromStr>(name: &str, target: &mut T, errors: &mut Vec<String>)
+where
+    T::Err: Display,
+{
+    if let Ok(value) = std::env::var(name) {
+        match value.trim().parse() {
+            Ok(value) => *target = value,
+            Err(e) => errors.push(format!("{}={:?}: {}", name, value, e)),
+        }
+    }
+}
+
+fn env_option<T: FromStr>(name: &str, target: &mut Option<T>, errors: &mut Vec<String>)
+where
+    T::Err: Display,
+{
+    if let Ok(value) = std::env::var(name) {
+        match value.trim().parse() {
+            Ok(value) => *target = Some(value),
+            Err(e) => errors.push(format!("{}={:?}: {}", name, value, e)),
+        }
+    }
+}
+
+/// Comma separated list.
+fn env_list(name: &str, target: &mut Vec<String>) {
+    if let Ok(value) = std::env::var(name) {
+        *target = value.split(',').map(|item| item.trim().to_string()).filter(|item| !item.is_empty()).collect();
+    }
+}
+
+impl Config {
+    /// Builds the configuration from the defaults, the config file, the environment and the flags,
+    /// and checks it. All problems are reported at once.
+    pub fn load(cli: &Cli) -> Result<Config, Vec<String>> {
+        let mut config = Config::from_file(cli).map_err(|e| vec![e])?;
+        let mut errors = Vec::new();
+        config.apply_env(&mut errors);
+        config.apply_cli(cli);
+        config.validate(&mut errors);
+        match errors.is_empty() {
+            true => Ok(config),
+            false => Err(errors),
+        }
+    }
+
+    fn from_file(cli: &Cli) -> Result<Config, String> {
+        let (path, required) = match (&cli.config, std::env::var("URL_SHORTENER_CONFIG")) {
+            (Some(path), _) => (path.clone(), true),
+            (None, Ok(path)) => (PathBuf::from(path), true),
+            (None, Err(_)) => (PathBuf::from(DEFAULT_CONFIG_FILE), false),
+        };
+        if !required && !path.exists() {
+            return Ok(Config::default());
+        }
+        let content = std::fs::read_to_string(&path)
+            .map_err(|e| format!("Cannot read config file {}: {}", path.display(), e))?;
+        toml::from_str(&content).map_err(|e| format!("Invalid config file {}: {}", path.display(), e))
+    }
+
+    /// The variable names predate the config file and are kept so existing .env files keep working.
+    fn apply_env(&mut self, errors: &mut Vec<String>) {
+        env_value("URL_SHORTENER_ADDRESS", &mut self.server.address, errors);
+        env_value("URL_SHORTENER_PORT", &mut self.server.port, errors);
+        env_option("URL_SHORTENER_PUBLIC_URL", &mut self.server.public_url, errors);
+        env_value("URL_SHORTENER_STATIC_DIR", &mut self.server.static_dir, errors);
+        env_list("URL_SHORTENER_CORS_ORIGINS", &mut self.server.cors_origins);
+        env_option("URL_SHORTENER_NOT_FOUND_PAGE", &mut self.server.not_found_page, errors);
+        env_list("URL_SHORTENER_TRUSTED_PROXIES", &mut self.server.trusted_proxies);
+
+        env_value("URL_SHORTENER_DATABASE_URL", &mut self.database.url, errors);
+        env_option("URL_SHORTENER_DB_READERS", &mut self.database.readers, errors);
+
+        env_value("URL_SHORTENER_CACHE_SIZE", &mut self.cache.size, errors);
+        env_value("URL_SHORTENER_CACHE_TTL", &mut self.cache.ttl, errors);
+
+        env_value("URL_SHORTENER_JWT_KEYS_FILE", &mut self.auth.jwt_keys_file, errors);
+        env_option("URL_SHORTENER_JWT_SECRET", &mut self.auth.jwt_secret, errors);
+        env_value("URL_SHORTENER_ACCESS_TOKEN_TTL", &mut self.auth.access_token_ttl, errors);
+        env_value("URL_SHORTENER_REFRESH_TOKEN_TTL", &mut self.auth.refresh_token_ttl, errors);
+        env_value("URL_SHORTENER_PERSISTENT_REFRESH_TOKEN_TTL", &mut self.auth.persistent_refresh_token_ttl, errors);
+
+        if let Ok(name) = std::env::var("URL_SHORTENER_CODE_STRATEGY") {
+            match CodeStrategy::parse(&name) {
+                Some(strategy) => self.codes.strategy = strategy,
+                None => errors.push(format!("URL_SHORTENER_CODE_STRATEGY={:?}: unknown strategy, use hash, random, sequential or words", name)),
+            }
+        }
+        env_value("URL_SHORTENER_CODE_LENGTH", &mut self.codes.length, errors);
+        env_value("URL_SHORTENER_CODE_SALT", &mut self.codes.salt, errors);
+        env_value("URL_SHORTENER_CUSTOM_CODE_ALPHABET", &mut self.codes.alphabet, errors);
+        env_value("URL_SHORTENER_CUSTOM_CODE_MIN_LENGTH", &mut self.codes.min_length, errors);
+        env_value("URL_SHORTENER_CUSTOM_CODE_MAX_LENGTH", &mut self.codes.max_length, errors);
+        env_value("URL_SHORTENER_CODE_CASE_SENSITIVE", &mut self.codes.case_sensitive, errors);
+        env_list("URL_SHORTENER_RESERVED_CODES", &mut self.codes.reserved);
+
+        env_list("URL_SHORTENER_ALLOWED_SCHEMES", &mut self.urls.allowed_schemes);
+        env_value("URL_SHORTENER_MAX_URL_LENGTH", &mut self.urls.max_length, errors);
+        env_value("URL_SHORTENER_MAX_BATCH_SIZE", &mut self.urls.max_batch_size, errors);
+    }
+
+    fn apply_cli(&mut self, cli: &Cli) {
+        if let Some(address) = &cli.address {
+            self.server.address = address.clone();
+        }
+        if let Some(port) = cli.port {
+            self.server.port = port;
+        }
+        if let Some(url) = &cli.database_url {
+            self.database.url = url.clone();
+        }
+        if let Some(dir) = &cli.static_dir {
+            self.server.static_dir = dir.clone();
+        }
+        if let Some(url) = &cli.public_url {
+            self.server.public_url = Some(url.clone());
+        }
+        if !cli.cors_origins.is_empty() {
+            self.server.cors_origins = cli.cors_origins.clone();
+        }
+    }
+
+    fn validate(&mut self, errors: &mut Vec<String>) {
+        if let Err(e) = self.listen_address() {
+            errors.push(format!("server.address: {}", e));
+        }
+        if let Some(url) = &self.server.public_url {
+            match Url::parse(url) {
+                Ok(parsed) if matches!(parsed.scheme(), "http" | "https") && parsed.has_host() => {
+                    self.server.public_url = Some(url.trim_end_matches('/').to_string());
+                },
+                _ => errors.push(format!("server.public_url: {:?} is not an absolute http(s) URL", url)),
+            }
+        }
+        if self.server.static_dir.exists() && !self.server.static_dir.is_dir() {
+            errors.push(format!("server.static_dir: {} is not a directory", self.server.static_dir.display()));
+        }
+        if self.server.cors_origins.iter().any(|origin| origin == "*") {
+            if self.server.cors_origins.len() > 1 {
+                errors.push("server.cors_origins: * can't be combined with other origins".to_string());
+            }
+        } else {
+            for origin in &self.server.cors_origins {
+                if !is_origin(origin) {
+                    errors.push(format!("server.cors_origins: {:?} is not an origin like https://example.com", origin));
+                }
+            }
+        }
+        if let Some(path) = &self.server.not_found_page {
+            if !path.is_file() {
+                errors.push(format!("server.not_found_page: {} is not a readable file", path.display()));
+            }
+        }
+        for proxy in &self.server.trusted_proxies {
+            if IpRange::parse(proxy).is_none() {
+                errors.push(format!("server.trusted_proxies: {:?} is not an IP address or CIDR range", proxy));
+            }
+        }
+
+        if self.database.url.trim().is_empty() {
+            errors.push("database.url: must not be empty".to_string());
+        }
+        if self.database.readers == Some(0) {
+            errors.push("database.readers: must be at least 1".to_string());
+        }
+
+        for (name, ttl) in [
+            ("auth.access_token_ttl", self.auth.access_token_ttl),
+            ("auth.refresh_token_ttl", self.auth.refresh_token_ttl),
+            ("auth.persistent_refresh_token_ttl", self.auth.persistent_refresh_token_ttl),
+        ] {
+            if ttl <= 0 {
+                errors.push(format!("{}: must be a positive number of seconds", name));
+            }
+        }
+        if self.auth.jwt_secret.as_ref().is_some_and(|secret| secret.len() < MIN_JWT_SECRET_LENGTH) {
+            errors.push(format!("auth.jwt_secret: must be at least {} bytes long", MIN_JWT_SECRET_LENGTH));
+        }
+
+        if self.codes.length == 0 {
+            errors.push("codes.length: must be at least 1".to_string());
+        }
+        if self.codes.alphabet.is_empty() {
+            errors.push("codes.alphabet: must not be empty".to_string());
+        }
+        // codes are a path segment of the short link, these would end it early
+        for reserved in ['/', '?', '#'] {
+            if self.codes.alphabet.contains(reserved) {
+                errors.push(format!("codes.alphabet: can't contain {}", reserved));
+            }
+        }
+        if self.codes.min_length == 0 || self.codes.min_length > self.codes.max_length {
+            errors.push(format!(
+                "codes.min_length, codes.max_length: must satisfy 0 < min <= max, got {} and {}",
+                self.codes.min_length, self.codes.max_length,
+            ));
+        }
+
+        if self.urls.allowed_schemes.is_empty() {
+            errors.push("urls.allowed_schemes: at least one scheme is required".to_string());
+        }
+        if self.urls.max_length == 0 {
+            errors.push("urls.max_length: must be at least 1".to_string());
+        }
+        if self.urls.max_batch_size == 0 {
+            errors.push("urls.max_batch_size: must be at least 1".to_string());
+        }
+    }
+
+    pub fn listen_address(&self) -> Result<SocketAddr, String> {
+        // resolving supports both IPv4 and IPv6 as well as host names
+        (self.server.address.as_str(), self.server.port).to_socket_addrs()
+            .map_err(|e| format!("cannot resolve {}: {}", self.server.address, e))?
+            .next()
+            .ok_or_else(|| format!("{} doesn't resolve to any address", self.server.address))
+    }
+}
+
+fn is_origin(origin: &str) -> bool {
+    match Url::parse(origin) {
+        Ok(url) => matches!(url.scheme(), "http" | "https")
+            && url.has_host()
+            && url.path() == "/"
+            && url.query().is_none()
+            && !origin.ends_with('/'),
+        Err(_) => false,
+    }
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+pub fn init(config: Config) -> &'static Config {
+    CONFIG.get_or_init(|| config)
+}
+
+pub fn config() -> &'static Config {
+    CONFIG.get().expect("Configuration is not loaded")
+}
@@ -1,20 +1,21 @@
-use rusqlite::{params, Connection, Result};
-use std::sync::Mutex;
+use async_trait::async_trait;
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension, Result};
+use std::ops::Deref;
+use std::sync::{Arc, Condvar, Mutex};
 
-
-use bcrypt::{hash, DEFAULT_COST};
+use crate::config::config;
+use crate::migrations;
+use crate::model::{RedirectStatus, Role, Scope, WorkspaceRole};
+use crate::storage::{hash_password, like_pattern, scopes_from_string, scopes_to_string, verify_password, ApiKeyInfo, ClickEvent, DailyClicks, LinkFilter, LinkInfo, LinkOwner, LinkStats, NewLink, LinkTarget, RefreshTokenStatus, Storage, StorageError, StorageResult, SystemStats, UserError, UserInfo, UserStatus, WorkspaceInfo, WorkspaceMember};
 
 
+/// SQLite storage. The database runs in WAL mode so that reads never wait for writes:
+/// every write goes through one connection (SQLite only allows one writer at a time anyway)
+/// and reads are spread over a pool of read-only connections. Queries are blocking,
+/// so they run on tokio's blocking thread pool instead of the async workers.
 pub struct DbConn {
-    pub conn: Mutex<Connection>, // Thread-safe shared connection
-}
-
-#[derive(Debug)]
-pub enum UserError {
-    UserAlreadyExists,
-    InvalidCredentials,
-    // DatabaseError{err: rusqlite::Error},
-    DatabaseError,
+    writer: Arc<Mutex<Connection>>,
+    readers: Arc<ReaderPool>,
 }
 
 impl From<rusqlite::Error> for UserError {
@@ -23,125 +24,689 @@ impl From<rusqlite::Error> for UserError {
     }
 }
 
+/// Fixed set of read-only connections, a reader that finds them all busy waits for one to be returned.
+struct ReaderPool {
+    idle: Mutex<Vec<Connection>>,
+    returned: Condvar,
+}
+
+struct PooledReader<'a> {
+    pool: &'a ReaderPool,
+    conn: Option<Connection>,
+}
+
+impl ReaderPool {
+    fn get(&self) -> PooledReader<'_> {
+        let mut idle = self.idle.lock().unwrap();
+        loop {
+            if let Some(conn) = idle.pop() {
+                return PooledReader { pool: self, conn: Some(conn) };
+            }
+            idle = self.returned.wait(idle).unwrap();
+        }
+    }
+}
+
+impl Deref for PooledReader<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.conn.as_ref().unwrap()
+    }
+}
+
+impl Drop for PooledReader<'_> {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            self.pool.idle.lock().unwrap().push(conn);
+            self.pool.returned.notify_one();
+        }
+    }
+}
+
+/// Number of read connections, database.readers or one per cpu.
+fn reader_count() -> usize {
+    match config().database.readers {
+        Some(count) => count,
+        None => std::thread::available_parallelism().map(|count| count.get()).unwrap_or(4),
+    }
+}
+
 impl DbConn {
     pub fn new(db_path: &str) -> Result<Self> {
-        let conn = Connection::open(db_path)?;
+        let writer = Connection::open(db_path)?;
+        writer.pragma_update(None, "journal_mode", "WAL")?;
+        writer.pragma_update(None, "synchronous", "NORMAL")?;
+        writer.busy_timeout(std::time::Duration::from_secs(5))?;
+
+        let mut readers = Vec::new();
+        for _ in 0..reader_count().max(1) {
+            let reader = Connection::open_with_flags(
+                db_path,
+                OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_URI | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+            )?;
+            reader.busy_timeout(std::time::Duration::from_secs(5))?;
+            readers.push(reader);
+        }
+
         Ok(Self {
-            conn: Mutex::new(conn),
+            writer: Arc::new(Mutex::new(writer)),
+            readers: Arc::new(ReaderPool { idle: Mutex::new(readers), returned: Condvar::new() }),
         })
     }
 
-    pub fn init_db(&self) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
-        
-        //initiate users table
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS users (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                username TEXT UNIQUE NOT NULL,
-                password TEXT NOT NULL
-                )",
-                [],
-            )?;
+    /// Runs a query on one of the read connections.
+    async fn read<T, F>(&self, query: F) -> StorageResult<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> Result<T> + Send + 'static,
+    {
+        let readers = self.readers.clone();
+        tokio::task::spawn_blocking(move || query(&readers.get()))
+            .await
+            .map_err(|e| StorageError::Database(e.to_string()))?
+            .map_err(StorageError::from)
+    }
 
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS urls (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                user_id INTEGER,
-                short TEXT UNIQUE NOT NULL,
-                long TEXT NOT NULL,
-                FOREIGN KEY(user_id) REFERENCES users(id)
-            )",
-            [],
-        )?;
+    /// Runs a statement on the write connection, writes are serialised by its lock.
+    async fn write<T, F>(&self, statement: F) -> StorageResult<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T> + Send + 'static,
+    {
+        let writer = self.writer.clone();
+        tokio::task::spawn_blocking(move || statement(&mut writer.lock().unwrap()))
+            .await
+            .map_err(|e| StorageError::Database(e.to_string()))?
+            .map_err(StorageError::from)
+    }
+}
 
+/// Maps `id, username, role, disabled, link count` columns.
+fn user_info_from_row(row: &rusqlite::Row) -> Result<UserInfo> {
+    Ok(UserInfo {
+        id: row.get(0)?,
+        username: row.get(1)?,
+        role: Role::parse(&row.get::<_, String>(2)?).unwrap_or_default(),
+        disabled: row.get(3)?,
+        links: row.get(4)?,
+    })
+}
 
-            Ok(())
-        }
+#[async_trait]
+impl Storage for DbConn {
+    async fn init_db(&self) -> StorageResult<Vec<(u32, &'static str)>> {
+        let applied = self.write(migrations::run).await?;
+        Ok(applied.iter().map(|migration| (migration.version, migration.description)).collect())
+    }
 
-    pub fn insert_url(&self, short: &str, long: &str, userid: u32) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
-        conn.execute(
-            "INSERT INTO urls (short, long, user_id) VALUES (?1, ?2, ?3) 
+    async fn insert_url(&self, short: &str, long: &str, owner: LinkOwner, expires_at: Option<i64>, max_clicks: Option<u32>, redirect_status: RedirectStatus) -> StorageResult<bool> {
+        let (short, long) = (short.to_string(), long.to_string());
+        let affected_rows = self.write(move |conn| conn.execute(
+            "INSERT INTO urls (short, long, user_id, workspace_id, expires_at, max_clicks, redirect_status) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
              ON CONFLICT(short) DO NOTHING",
-            params![short, long, userid],
-        )?;
-        Ok(())
+            params![short, long, owner.user_id, owner.workspace_id, expires_at, max_clicks, redirect_status.code()],
+        )).await?;
+        Ok(affected_rows > 0)
     }
 
-    pub fn get_long_url(&self, short: String) -> Option<String> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare("SELECT long FROM urls WHERE short = ?1").ok()?;
-        let mut rows = stmt.query(params![short]).ok()?;
+    async fn insert_urls(&self, links: Vec<NewLink>) -> StorageResult<Vec<Option<String>>> {
+        self.write(move |conn| {
+            let tx = conn.transaction()?;
+            let mut claimed = Vec::with_capacity(links.len());
+            {
+                let mut stmt = tx.prepare(
+                    "INSERT INTO urls (short, long, user_id, workspace_id, expires_at, max_clicks, redirect_status) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                     ON CONFLICT(short) DO NOTHING",
+                )?;
+                for link in &links {
+                    let mut code = None;
+                    for short in &link.codes {
+                        let params = params![short, link.long, link.owner.user_id, link.owner.workspace_id, link.expires_at, link.max_clicks, link.redirect_status.code()];
+                        if stmt.execute(params)? > 0 {
+                            code = Some(short.clone());
+                            break;
+                        }
+                    }
+                    claimed.push(code);
+                }
+            }
+            tx.commit()?;
+            Ok(claimed)
+        }).await
+    }
 
-        if let Some(row) = rows.next().ok()? {
-            let long_url: String = row.get(0).ok()?;
-            Some(long_url)
-        } else {
-            None
-        }
+    async fn get_link_target(&self, short: &str) -> StorageResult<Option<LinkTarget>> {
+        let short = short.to_string();
+        self.read(move |conn| {
+            conn.prepare_cached("SELECT long, expires_at, max_clicks, clicks, redirect_status FROM urls WHERE short = ?1 AND archived = 0 AND taken_down = 0")?
+                .query_row(params![short], |row| Ok(LinkTarget {
+                    long: row.get(0)?,
+                    expires_at: row.get(1)?,
+                    max_clicks: row.get(2)?,
+                    clicks: row.get(3)?,
+                    redirect_status: RedirectStatus::from_code(row.get(4)?).unwrap_or_default(),
+                }))
+                .optional()
+        }).await
+    }
+
+    async fn record_click(&self, click: ClickEvent) -> StorageResult<bool> {
+        // one transaction, so every click takes the writer lock and syncs the WAL only once
+        self.write(move |conn| {
+            let tx = conn.transaction()?;
+            // the check and the increment happen in one statement so concurrent hits can't overshoot
+            let counted = tx.execute(
+                "UPDATE urls SET clicks = clicks + 1
+                 WHERE short = ?1 AND (max_clicks IS NULL OR clicks < max_clicks)",
+                params![click.short],
+            )? > 0;
+            if counted {
+                tx.execute(
+                    "INSERT INTO clicks (short, clicked_at, referrer, user_agent, ip) VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![click.short, chrono::Utc::now().timestamp(), click.referrer, click.user_agent, click.ip],
+                )?;
+            }
+            tx.commit()?;
+            Ok(counted)
+        }).await
+    }
+
+    async fn get_link_stats(&self, short: &str) -> StorageResult<LinkStats> {
+        let short = short.to_string();
+        let per_day = self.read(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT date(clicked_at, 'unixepoch') AS day, COUNT(*) FROM clicks
+                 WHERE short = ?1 GROUP BY day ORDER BY day",
+            )?;
+            let rows = stmt.query_map(params![short], |row| {
+                Ok(DailyClicks { day: row.get(0)?, clicks: row.get(1)? })
+            })?;
+            rows.collect::<Result<Vec<_>>>()
+        }).await?;
+
+        let total = per_day.iter().map(|day| day.clicks).sum();
+        Ok(LinkStats { total, per_day })
+    }
+
+    async fn next_url_id(&self) -> StorageResult<u64> {
+        self.read(|conn| conn.query_row("SELECT COALESCE(MAX(id), 0) + 1 FROM urls", [], |row| row.get(0))).await
+    }
+
+    async fn url_exists(&self, short: &str) -> StorageResult<bool> {
+        let short = short.to_string();
+        let count: u32 = self.read(move |conn| conn.query_row(
+            "SELECT COUNT(*) FROM urls WHERE short = ?1",
+            params![short],
+            |row| row.get(0),
+        )).await?;
+        Ok(count > 0)
+    }
+
+    async fn get_url_owner(&self, short: &str) -> StorageResult<Option<LinkOwner>> {
+        let short = short.to_string();
+        let owner: Option<(Option<u32>, Option<u32>)> = self.read(move |conn| conn.query_row(
+            "SELECT user_id, workspace_id FROM urls WHERE short = ?1",
+            params![short],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        ).optional()).await?;
+        Ok(owner.and_then(|(user_id, workspace_id)| user_id.map(|user_id| LinkOwner { user_id, workspace_id })))
+    }
+
+    async fn update_url(&self, short: &str, long: &str) -> StorageResult<()> {
+        let (short, long) = (short.to_string(), long.to_string());
+        self.write(move |conn| conn.execute(
+            "UPDATE urls SET long = ?1 WHERE short = ?2",
+            params![long, short],
+        )).await?;
+        Ok(())
+    }
+
+    async fn set_url_archived(&self, short: &str, archived: bool) -> StorageResult<()> {
+        let short = short.to_string();
+        self.write(move |conn| conn.execute(
+            "UPDATE urls SET archived = ?1 WHERE short = ?2",
+            params![archived, short],
+        )).await?;
+        Ok(())
     }
 
 
-    pub fn create_user(&self, username: &str, password: &str) -> Result<String, UserError> {
-        let conn = self.conn.lock().unwrap();
-        let password = hash(password, DEFAULT_COST).unwrap_or_else(|_| {
-            panic!("Failed to hash password")
-        });
-    
-        let affected_rows = conn.execute(
-            "INSERT INTO users (username, password) VALUES (?1, ?2) 
+    async fn create_user(&self, username: &str, password: &str) -> Result<String, UserError> {
+        // hashing happens before taking the write lock, it takes far longer than the insert
+        let password = hash_password(password).await;
+        let username = username.to_string();
+
+        let affected_rows = self.write(move |conn| conn.execute(
+            "INSERT INTO users (username, password) VALUES (?1, ?2)
              ON CONFLICT(username) DO NOTHING",
             params![username, password],
-        )?;
-    
+        )).await?;
+
         if affected_rows == 0 {
             return Err(UserError::UserAlreadyExists);
         }
-    
+
         Ok("User created".to_string())
     }
 
-    pub fn login(&self, username: &str, password: &str) -> Result<String, UserError> {
-        let conn = self.conn.lock().unwrap();
-        let db_password: String = conn.query_row(
-            "SELECT password FROM users WHERE username = ?1",
+    async fn login(&self, username: &str, password: &str) -> Result<String, UserError> {
+        let username = username.to_string();
+        let user: Option<(String, bool)> = self.read(move |conn| conn.query_row(
+            "SELECT password, disabled FROM users WHERE username = ?1",
             params![username],
-            |row| row.get(0),
-        ).map_err(|_| UserError::InvalidCredentials)?;
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        ).optional()).await?;
+        let Some((db_password, disabled)) = user else {
+            return Err(UserError::InvalidCredentials);
+        };
 
-        if bcrypt::verify(password, &db_password).unwrap_or(false) {
-            Ok("Login successful".to_string())
-        } else {
+        if !verify_password(password, &db_password).await {
             Err(UserError::InvalidCredentials)
+        } else if disabled {
+            Err(UserError::AccountDisabled)
+        } else {
+            Ok("Login successful".to_string())
         }
     }
 
-    pub fn get_user_id(&self, username: &str) -> Result<Option<u32>> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare("SELECT id FROM users WHERE username = ?1")?;
-        let mut rows = stmt.query(params![username])?;
+    async fn get_user_id(&self, username: &str) -> StorageResult<Option<u32>> {
+        let username = username.to_string();
+        self.read(move |conn| conn.query_row(
+            "SELECT id FROM users WHERE username = ?1",
+            params![username],
+            |row| row.get(0),
+        ).optional()).await
+    }
 
-        if let Some(row) = rows.next()? {
-            let user_id: u32 = row.get(0)?;
-            Ok(Some(user_id))
-        } else {
-            Ok(None)
-        }
+    async fn get_user_links(&self, user_id: u32, archived: bool) -> StorageResult<Vec<(String, String)>> {
+        self.read(move |conn| {
+            let mut stmt = conn.prepare("SELECT short, long FROM urls WHERE user_id = ?1 AND workspace_id IS NULL AND archived = ?2 ORDER BY id")?;
+            let rows = stmt.query_map(params![user_id, archived], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?;
+            rows.collect()
+        }).await
     }
 
-    pub fn get_user_links(&self, user_id: u32) -> Result<Vec<(String, String)>> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare("SELECT short, long FROM urls WHERE user_id = ?1")?;
-        let rows = stmt.query_map(params![user_id], |row| {
-            Ok((row.get(0)?, row.get(1)?))
-        })?;
+    async fn get_workspace_links(&self, workspace_id: u32, archived: bool) -> StorageResult<Vec<(String, String)>> {
+        self.read(move |conn| {
+            let mut stmt = conn.prepare("SELECT short, long FROM urls WHERE workspace_id = ?1 AND archived = ?2 ORDER BY id")?;
+            let rows = stmt.query_map(params![workspace_id, archived], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?;
+            rows.collect()
+        }).await
+    }
 
-        let mut links = Vec::new();
-        for link in rows {
-            links.push(link?);
-        }
-        Ok(links)
+    async fn set_url_workspace(&self, short: &str, workspace_id: u32) -> StorageResult<()> {
+        let short = short.to_string();
+        self.write(move |conn| conn.execute(
+            "UPDATE urls SET workspace_id = ?1 WHERE short = ?2",
+            params![workspace_id, short],
+        )).await?;
+        Ok(())
+    }
+
+    async fn store_refresh_token(&self, token_hash: &str, family_id: &str, user_id: u32, expires_at: i64) -> StorageResult<()> {
+        let (token_hash, family_id) = (token_hash.to_string(), family_id.to_string());
+        self.write(move |conn| {
+            // expired tokens are useless, so clean them up whenever a new one is issued
+            conn.execute(
+                "DELETE FROM refresh_tokens WHERE expires_at < ?1",
+                params![chrono::Utc::now().timestamp()],
+            )?;
+            conn.execute(
+                "INSERT INTO refresh_tokens (token_hash, family_id, user_id, expires_at) VALUES (?1, ?2, ?3, ?4)",
+                params![token_hash, family_id, user_id, expires_at],
+            )
+        }).await?;
+        Ok(())
+    }
+
+    async fn use_refresh_token(&self, token_hash: &str) -> StorageResult<RefreshTokenStatus> {
+        let token_hash = token_hash.to_string();
+        self.write(move |conn| {
+            let token = conn.query_row(
+                "SELECT family_id, user_id, expires_at, used, revoked FROM refresh_tokens WHERE token_hash = ?1",
+                params![token_hash],
+                |row| Ok((row.get::<_, String>(0)?, row.get::<_, u32>(1)?, row.get::<_, i64>(2)?, row.get::<_, bool>(3)?, row.get::<_, bool>(4)?)),
+            ).optional()?;
+
+            let Some((family_id, user_id, expires_at, used, revoked)) = token else {
+                return Ok(RefreshTokenStatus::Unknown);
+            };
+
+            if used || revoked {
+                conn.execute(
+                    "UPDATE refresh_tokens SET revoked = 1 WHERE family_id = ?1",
+                    params![family_id],
+                )?;
+                return Ok(RefreshTokenStatus::Reused);
+            }
+            if expires_at < chrono::Utc::now().timestamp() {
+                return Ok(RefreshTokenStatus::Unknown);
+            }
+
+            conn.execute(
+                "UPDATE refresh_tokens SET used = 1 WHERE token_hash = ?1",
+                params![token_hash],
+            )?;
+            Ok(RefreshTokenStatus::Valid { family_id, user_id })
+        }).await
+    }
+
+    async fn revoke_refresh_token_family(&self, token_hash: &str) -> StorageResult<()> {
+        let token_hash = token_hash.to_string();
+        self.write(move |conn| conn.execute(
+            "UPDATE refresh_tokens SET revoked = 1
+             WHERE family_id = (SELECT family_id FROM refresh_tokens WHERE token_hash = ?1)",
+            params![token_hash],
+        )).await?;
+        Ok(())
+    }
+
+    async fn create_workspace(&self, name: &str, owner_id: u32) -> StorageResult<WorkspaceInfo> {
+        let name = name.to_string();
+        let created_at = chrono::Utc::now().timestamp();
+        self.write(move |conn| {
+            let tx = conn.transaction()?;
+            tx.execute("INSERT INTO workspaces (name, created_at) VALUES (?1, ?2)", params![name, created_at])?;
+            let id = tx.last_insert_rowid() as u32;
+            tx.execute(
+                "INSERT INTO workspace_members (workspace_id, user_id, role) VALUES (?1, ?2, ?3)",
+                params![id, owner_id, WorkspaceRole::Owner.as_str()],
+            )?;
+            tx.commit()?;
+            Ok(WorkspaceInfo { id, name, role: WorkspaceRole::Owner, created_at })
+        }).await
+    }
+
+    async fn get_workspaces(&self, user_id: u32) -> StorageResult<Vec<WorkspaceInfo>> {
+        self.read(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT workspaces.id, workspaces.name, workspace_members.role, workspaces.created_at FROM workspaces
+                 JOIN workspace_members ON workspace_members.workspace_id = workspaces.id
+                 WHERE workspace_members.user_id = ?1 ORDER BY workspaces.id",
+            )?;
+            let rows = stmt.query_map(params![user_id], |row| Ok(WorkspaceInfo {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                role: WorkspaceRole::parse(&row.get::<_, String>(2)?).unwrap_or(WorkspaceRole::Viewer),
+                created_at: row.get(3)?,
+            }))?;
+            rows.collect()
+        }).await
+    }
+
+    async fn get_workspace_role(&self, workspace_id: u32, user_id: u32) -> StorageResult<Option<WorkspaceRole>> {
+        let role: Option<String> = self.read(move |conn| conn.query_row(
+            "SELECT role FROM workspace_members WHERE workspace_id = ?1 AND user_id = ?2",
+            params![workspace_id, user_id],
+            |row| row.get(0),
+        ).optional()).await?;
+        Ok(role.and_then(|role| WorkspaceRole::parse(&role)))
+    }
+
+    async fn get_workspace_members(&self, workspace_id: u32) -> StorageResult<Vec<WorkspaceMember>> {
+        self.read(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT users.username, workspace_members.role FROM workspace_members
+                 JOIN users ON users.id = workspace_members.user_id
+                 WHERE workspace_members.workspace_id = ?1 ORDER BY users.username",
+            )?;
+            let rows = stmt.query_map(params![workspace_id], |row| Ok(WorkspaceMember {
+                username: row.get(0)?,
+                role: WorkspaceRole::parse(&row.get::<_, String>(1)?).unwrap_or(WorkspaceRole::Viewer),
+            }))?;
+            rows.collect()
+        }).await
+    }
+
+    async fn set_workspace_member(&self, workspace_id: u32, user_id: u32, role: WorkspaceRole) -> StorageResult<()> {
+        self.write(move |conn| conn.execute(
+            "INSERT INTO workspace_members (workspace_id, user_id, role) VALUES (?1, ?2, ?3)
+             ON CONFLICT(workspace_id, user_id) DO UPDATE SET role = excluded.role",
+            params![workspace_id, user_id, role.as_str()],
+        )).await?;
+        Ok(())
+    }
+
+    async fn remove_workspace_member(&self, workspace_id: u32, user_id: u32) -> StorageResult<bool> {
+        let affected_rows = self.write(move |conn| conn.execute(
+            "DELETE FROM workspace_members WHERE workspace_id = ?1 AND user_id = ?2",
+            params![workspace_id, user_id],
+        )).await?;
+        Ok(affected_rows > 0)
+    }
+
+    async fn create_api_key(&self, user_id: u32, name: &str, prefix: &str, key_hash: &str, scopes: &[Scope]) -> StorageResult<ApiKeyInfo> {
+        let created_at = chrono::Utc::now().timestamp();
+        let (db_name, db_prefix, key_hash, db_scopes) = (name.to_string(), prefix.to_string(), key_hash.to_string(), scopes_to_string(scopes));
+        let id = self.write(move |conn| {
+            conn.execute(
+                "INSERT INTO api_keys (user_id, name, prefix, key_hash, scopes, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![user_id, db_name, db_prefix, key_hash, db_scopes, created_at],
+            )?;
+            Ok(conn.last_insert_rowid())
+        }).await?;
+
+        Ok(ApiKeyInfo {
+            id: id as u32,
+            name: name.to_string(),
+            prefix: prefix.to_string(),
+            scopes: scopes.to_vec(),
+            created_at,
+            last_used_at: None,
+            revoked: false,
+        })
+    }
+
+    async fn get_api_keys(&self, user_id: u32) -> StorageResult<Vec<ApiKeyInfo>> {
+        self.read(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT id, name, prefix, scopes, created_at, last_used_at, revoked FROM api_keys WHERE user_id = ?1 ORDER BY id",
+            )?;
+            let rows = stmt.query_map(params![user_id], |row| {
+                Ok(ApiKeyInfo {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    prefix: row.get(2)?,
+                    scopes: scopes_from_string(&row.get::<_, String>(3)?),
+                    created_at: row.get(4)?,
+                    last_used_at: row.get(5)?,
+                    revoked: row.get(6)?,
+                })
+            })?;
+            rows.collect()
+        }).await
+    }
+
+    async fn revoke_api_key(&self, user_id: u32, key_id: u32) -> StorageResult<bool> {
+        let affected_rows = self.write(move |conn| conn.execute(
+            "UPDATE api_keys SET revoked = 1 WHERE id = ?1 AND user_id = ?2",
+            params![key_id, user_id],
+        )).await?;
+        Ok(affected_rows > 0)
+    }
+
+    async fn use_api_key(&self, key_hash: &str) -> StorageResult<Option<(String, Vec<Scope>)>> {
+        let key_hash = key_hash.to_string();
+        self.write(move |conn| {
+            let key = conn.query_row(
+                "SELECT api_keys.id, users.username, api_keys.scopes FROM api_keys
+                 JOIN users ON users.id = api_keys.user_id
+                 WHERE api_keys.key_hash = ?1 AND api_keys.revoked = 0 AND users.disabled = 0",
+                params![key_hash],
+                |row| Ok((row.get::<_, u32>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?)),
+            ).optional()?;
+
+            let Some((key_id, username, scopes)) = key else {
+                return Ok(None);
+            };
+
+            conn.execute(
+                "UPDATE api_keys SET last_used_at = ?1 WHERE id = ?2",
+                params![chrono::Utc::now().timestamp(), key_id],
+            )?;
+            Ok(Some((username, scopes_from_string(&scopes))))
+        }).await
+    }
+
+    async fn set_user_disabled(&self, username: &str, disabled: bool) -> StorageResult<bool> {
+        let username = username.to_string();
+        self.write(move |conn| {
+            let tx = conn.transaction()?;
+            let affected_rows = tx.execute(
+                "UPDATE users SET disabled = ?1 WHERE username = ?2",
+                params![disabled, username],
+            )?;
+            if disabled {
+                tx.execute(
+                    "DELETE FROM refresh_tokens WHERE user_id = (SELECT id FROM users WHERE username = ?1)",
+                    params![username],
+                )?;
+            }
+            tx.commit()?;
+            Ok(affected_rows > 0)
+        }).await
+    }
+
+    async fn reset_password(&self, username: &str, password: &str) -> StorageResult<bool> {
+        let password = hash_password(password).await;
+        let username = username.to_string();
+        self.write(move |conn| {
+            let tx = conn.transaction()?;
+            let affected_rows = tx.execute(
+                "UPDATE users SET password = ?1 WHERE username = ?2",
+                params![password, username],
+            )?;
+            tx.execute(
+                "DELETE FROM refresh_tokens WHERE user_id = (SELECT id FROM users WHERE username = ?1)",
+                params![username],
+            )?;
+            tx.commit()?;
+            Ok(affected_rows > 0)
+        }).await
+    }
+
+    async fn set_user_role(&self, username: &str, role: Role) -> StorageResult<bool> {
+        let username = username.to_string();
+        let affected_rows = self.write(move |conn| conn.execute(
+            "UPDATE users SET role = ?1 WHERE username = ?2",
+            params![role.as_str(), username],
+        )).await?;
+        Ok(affected_rows > 0)
+    }
+
+    async fn get_user(&self, username: &str) -> StorageResult<Option<UserInfo>> {
+        let username = username.to_string();
+        self.read(move |conn| conn.query_row(
+            "SELECT users.id, users.username, users.role, users.disabled, COUNT(urls.id) FROM users
+             LEFT JOIN urls ON urls.user_id = users.id
+             WHERE users.username = ?1
+             GROUP BY users.id",
+            params![username],
+            user_info_from_row,
+        ).optional()).await
+    }
+
+    async fn get_user_status(&self, username: &str) -> StorageResult<Option<UserStatus>> {
+        let username = username.to_string();
+        self.read(move |conn| conn.query_row(
+            "SELECT id, role, disabled FROM users WHERE username = ?1",
+            params![username],
+            |row| Ok(UserStatus {
+                id: row.get(0)?,
+                role: Role::parse(&row.get::<_, String>(1)?).unwrap_or_default(),
+                disabled: row.get(2)?,
+            }),
+        ).optional()).await
+    }
+
+    async fn list_users(&self) -> StorageResult<Vec<UserInfo>> {
+        self.read(|conn| {
+            let mut stmt = conn.prepare(
+                "SELECT users.id, users.username, users.role, users.disabled, COUNT(urls.id) FROM users
+                 LEFT JOIN urls ON urls.user_id = users.id
+                 GROUP BY users.id ORDER BY users.id",
+            )?;
+            let rows = stmt.query_map([], user_info_from_row)?;
+            rows.collect()
+        }).await
+    }
+
+    async fn find_links(&self, filter: &LinkFilter) -> StorageResult<Vec<LinkInfo>> {
+        let (owner, search, archived) = (filter.owner.clone(), filter.search.as_deref().map(like_pattern), filter.archived);
+        let (limit, offset) = (filter.limit, filter.offset);
+        self.read(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT urls.short, urls.long, users.username, urls.archived, urls.taken_down, urls.clicks, urls.max_clicks, urls.expires_at, urls.redirect_status
+                 FROM urls LEFT JOIN users ON users.id = urls.user_id
+                 WHERE (?1 IS NULL OR users.username = ?1)
+                   AND (?2 IS NULL OR urls.short LIKE ?2 ESCAPE '\\' OR urls.long LIKE ?2 ESCAPE '\\')
+                   AND (?3 IS NULL OR urls.archived = ?3)
+                 ORDER BY urls.id DESC LIMIT ?4 OFFSET ?5",
+            )?;
+            let rows = stmt.query_map(params![owner, search, archived, limit, offset], |row| {
+                Ok(LinkInfo {
+                    short: row.get(0)?,
+                    long: row.get(1)?,
+                    owner: row.get(2)?,
+                    archived: row.get(3)?,
+                    taken_down: row.get(4)?,
+                    clicks: row.get(5)?,
+                    max_clicks: row.get(6)?,
+                    expires_at: row.get(7)?,
+                    redirect_status: row.get(8)?,
+                })
+            })?;
+            rows.collect()
+        }).await
+    }
+
+    async fn delete_url(&self, short: &str) -> StorageResult<bool> {
+        let short = short.to_string();
+        self.write(move |conn| {
+            let tx = conn.transaction()?;
+            tx.execute("DELETE FROM clicks WHERE short = ?1", params![short])?;
+            let affected_rows = tx.execute("DELETE FROM urls WHERE short = ?1", params![short])?;
+            tx.commit()?;
+            Ok(affected_rows > 0)
+        }).await
+    }
+
+    async fn set_url_taken_down(&self, short: &str, taken_down: bool) -> StorageResult<bool> {
+        let short = short.to_string();
+        let affected_rows = self.write(move |conn| conn.execute(
+            "UPDATE urls SET taken_down = ?1 WHERE short = ?2",
+            params![taken_down, short],
+        )).await?;
+        Ok(affected_rows > 0)
+    }
+
+    async fn system_stats(&self) -> StorageResult<SystemStats> {
+        let day_ago = chrono::Utc::now().timestamp() - 24 * 60 * 60;
+        self.read(move |conn| conn.query_row(
+            "SELECT
+                (SELECT COUNT(*) FROM users),
+                (SELECT COUNT(*) FROM users WHERE disabled = 1),
+                (SELECT COUNT(*) FROM urls),
+                (SELECT COUNT(*) FROM urls WHERE archived = 1),
+                (SELECT COUNT(*) FROM urls WHERE taken_down = 1),
+                (SELECT COUNT(*) FROM clicks),
+                (SELECT COUNT(*) FROM clicks WHERE clicked_at >= ?1)",
+            params![day_ago],
+            |row| Ok(SystemStats {
+                users: row.get(0)?,
+                disabled_users: row.get(1)?,
+                links: row.get(2)?,
+                archived_links: row.get(3)?,
+                taken_down_links: row.get(4)?,
+                clicks: row.get(5)?,
+                clicks_last_day: row.get(6)?,
+            }),
+        )).await
     }
 
 }
@@ -0,0 +1,197 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use jsonwebtoken::{DecodingKey, EncodingKey};
+use rand::{thread_rng, RngCore};
+use serde::{Deserialize, Serialize};
+
+use crate::config::config;
+
+/// Retired keys are kept around long enough for the longest lived refresh token to expire.
+fn retired_key_lifetime() -> chrono::Duration {
+    let auth = &config().auth;
+    chrono::Duration::seconds(auth.persistent_refresh_token_ttl.max(auth.refresh_token_ttl))
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct JwtKey {
+    pub kid: String,
+    /// Hex encoded 256-bit HS256 secret.
+    pub secret: String,
+    pub created_at: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retired_at: Option<String>,
+}
+
+/// Contents of the key file. `active` is the kid used to sign new tokens,
+/// every other key is only used to verify tokens signed before a rotation.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct JwtKeySet {
+    pub active: String,
+    pub keys: Vec<JwtKey>,
+}
+
+impl JwtKeySet {
+    fn from_secret(kid: &str, secret: &[u8]) -> Self {
+        JwtKeySet {
+            active: kid.to_string(),
+            keys: vec![JwtKey {
+                kid: kid.to_string(),
+                secret: hex::encode(secret),
+                created_at: chrono::Utc::now().to_rfc3339(),
+                retired_at: None,
+            }],
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+        let key_set: JwtKeySet = serde_json::from_str(&content).map_err(|e| format!("Invalid key file {}: {}", path.display(), e))?;
+        if key_set.get(&key_set.active).is_none() {
+            return Err(format!("Active key {} is missing from {}", key_set.active, path.display()));
+        }
+        Ok(key_set)
+    }
+
+    /// Writes the keys to a temporary file only the owner can read and moves it over the old one,
+    /// so the secrets are never readable by others and a crash can't leave a half written file.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let content = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+        let temp_path = PathBuf::from(temp_path);
+
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let write = |file: &mut fs::File| file.write_all(content.as_bytes()).and_then(|_| file.sync_all());
+        options.open(&temp_path).and_then(|mut file| write(&mut file))
+            .and_then(|_| fs::rename(&temp_path, path))
+            .map_err(|e| {
+                let _ = fs::remove_file(&temp_path);
+                format!("Cannot write {}: {}", path.display(), e)
+            })
+    }
+
+    pub fn get(&self, kid: &str) -> Option<&JwtKey> {
+        self.keys.iter().find(|key| key.kid == kid)
+    }
+
+    /// Adds a fresh signing key, retires the current one and drops keys retired long enough ago.
+    pub fn rotate(&mut self) -> &JwtKey {
+        let now = chrono::Utc::now();
+        let lifetime = retired_key_lifetime();
+        for key in self.keys.iter_mut() {
+            if key.retired_at.is_none() {
+                key.retired_at = Some(now.to_rfc3339());
+            }
+        }
+        self.keys.retain(|key| {
+            let retired_at = key.retired_at.as_deref().and_then(|date| chrono::DateTime::parse_from_rfc3339(date).ok());
+            match retired_at {
+                Some(date) => now.signed_duration_since(date) < lifetime,
+                None => true,
+            }
+        });
+
+        let new_key = JwtKeySet::from_secret(&generate_kid(), &generate_secret_key()).keys.remove(0);
+        self.active = new_key.kid.clone();
+        self.keys.push(new_key);
+        self.keys.last().unwrap()
+    }
+}
+
+pub struct JwtKeys {
+    pub active_kid: String,
+    encoding_key: EncodingKey,
+    decoding_keys: Vec<(String, DecodingKey)>,
+}
+
+impl JwtKeys {
+    fn from_key_set(key_set: &JwtKeySet) -> Result<Self, String> {
+        let mut decoding_keys = Vec::new();
+        let mut encoding_key = None;
+        for key in &key_set.keys {
+            let secret = hex::decode(&key.secret).map_err(|_| format!("Key {} is not valid hex", key.kid))?;
+            if key.kid == key_set.active {
+                encoding_key = Some(EncodingKey::from_secret(&secret));
+            }
+            decoding_keys.push((key.kid.clone(), DecodingKey::from_secret(&secret)));
+        }
+
+        Ok(JwtKeys {
+            active_kid: key_set.active.clone(),
+            encoding_key: encoding_key.ok_or(format!("Active key {} is missing", key_set.active))?,
+            decoding_keys,
+        })
+    }
+
+    pub fn encoding_key(&self) -> &EncodingKey {
+        &self.encoding_key
+    }
+
+    /// Every token names the key it was signed with, one without a kid or with an unknown kid is never valid.
+    pub fn decoding_key(&self, kid: &str) -> Option<&DecodingKey> {
+        self.decoding_keys.iter().find(|(key_id, _)| key_id == kid).map(|(_, key)| key)
+    }
+}
+
+static JWT_KEYS: OnceLock<JwtKeys> = OnceLock::new();
+
+fn generate_secret_key() -> [u8; 32] {
+    let mut key = [0u8; 32]; // 256-bit key for HS256
+    thread_rng().fill_bytes(&mut key);
+    key
+}
+
+fn generate_kid() -> String {
+    format!("{}-{:08x}", chrono::Utc::now().format("%Y%m%d"), thread_rng().next_u32())
+}
+
+pub fn key_file_path() -> &'static Path {
+    &config().auth.jwt_keys_file
+}
+
+/// Loads the signing keys. auth.jwt_secret takes precedence over the key file,
+/// and a missing key file is created with a fresh key so restarts don't log users out.
+pub fn init_jwt_keys() -> Result<&'static JwtKeys, String> {
+    let key_set = match &config().auth.jwt_secret {
+        Some(secret) => JwtKeySet::from_secret("env", secret.as_bytes()),
+        None => {
+            let path = key_file_path();
+            if path.exists() {
+                JwtKeySet::load(path)?
+            } else {
+                let key_set = JwtKeySet::from_secret(&generate_kid(), &generate_secret_key());
+                key_set.save(path)?;
+                println!("Generated a new JWT signing key in {}", path.display());
+                key_set
+            }
+        }
+    };
+
+    let keys = JwtKeys::from_key_set(&key_set)?;
+    Ok(JWT_KEYS.get_or_init(|| keys))
+}
+
+pub fn get_jwt_keys() -> &'static JwtKeys {
+    JWT_KEYS.get().expect("JWT keys are not initialized")
+}
+
+/// Rotates the key file in place. Running servers pick up the new key after a restart.
+pub fn rotate_key_file(path: &Path) -> Result<String, String> {
+    let mut key_set = if path.exists() {
+        JwtKeySet::load(path)?
+    } else {
+        JwtKeySet { active: String::new(), keys: Vec::new() }
+    };
+    let kid = key_set.rotate().kid.clone();
+    key_set.save(path)?;
+    Ok(kid)
+}
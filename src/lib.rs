@@ -0,0 +1,15 @@
+//! The server as a library, so the binary and the integration tests in `tests/` share it.
+
+pub mod responses;
+pub mod routes;
+pub mod model;
+pub mod db;
+pub mod storage;
+pub mod migrations;
+pub mod jwt_keys;
+pub mod codegen;
+pub mod qr;
+pub mod proxy;
+pub mod validation;
+pub mod config;
+pub mod admin;
@@ -1,44 +1,90 @@
-mod responses;
-mod routes;
-mod model;
-mod db;
-use routes::routes;
+use url_shortener::{admin, codegen, config, jwt_keys, migrations, proxy, responses, storage, validation};
+use url_shortener::routes::routes;
 use tower_cookies::CookieManagerLayer;
-use tower_http::cors::CorsLayer;
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, CorsLayer};
 use tower_http::trace::TraceLayer;
-use std::net::ToSocketAddrs;
-use std::sync::Arc;
+use std::net::SocketAddr;
+use axum::http::HeaderValue;
 use axum::Router;
-use db::DbConn;
+use clap::Parser;
+use url_shortener::config::{Cli, Command, Config};
 use tower_http::services::ServeDir;
 
+/// `*` keeps the old behaviour of accepting any origin, otherwise only the listed origins
+/// may call the API with the user's cookies.
+fn cors_layer(origins: &[String]) -> CorsLayer {
+    if origins.iter().any(|origin| origin == "*") {
+        return CorsLayer::very_permissive();
+    }
+    let origins: Vec<HeaderValue> = origins.iter()
+        .map(|origin| HeaderValue::from_str(origin).expect("CORS origins are validated when the config is loaded"))
+        .collect();
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::list(origins))
+        .allow_methods(AllowMethods::mirror_request())
+        .allow_headers(AllowHeaders::mirror_request())
+        .allow_credentials(true)
+}
+
 #[tokio::main]
 async fn main() {
     dotenv::dotenv().ok();
 
-    let db = Arc::new(DbConn::new("urls.db").expect("Failed to connect to database"));
-    db.init_db().expect("Failed to initialize database");
+    let cli = Cli::parse();
+    let config = match Config::load(&cli) {
+        Ok(config) => config::init(config),
+        Err(errors) => {
+            eprintln!("Invalid configuration:");
+            for error in errors {
+                eprintln!("  {}", error);
+            }
+            std::process::exit(1);
+        }
+    };
 
-    let server_port: u16 = std::env::var("URL_SHORTENER_PORT")
-        .expect("You must set URL_SHORTENER_PORT in .env file")
-        .parse()
-        .expect("PORT must be a valid number");
+    if let Some(Command::RotateJwtKey) = cli.command {
+        let path = jwt_keys::key_file_path();
+        match jwt_keys::rotate_key_file(path) {
+            Ok(kid) => println!("New signing key {} written to {}, restart the server to start using it", kid, path.display()),
+            Err(e) => eprintln!("Failed to rotate JWT key: {}", e),
+        }
+        return;
+    }
 
-    let server_address = std::env::var("URL_SHORTENER_ADDRESS")
-        .expect("You must set URL_SHORTENER_ADDRESS in .env file");
+    let db = storage::connect(&config.database.url).unwrap_or_else(|e| panic!("Failed to connect to database: {}", e));
+    let applied = db.init_db().await.unwrap_or_else(|e| panic!("Failed to initialize database: {}", e));
+    for (version, description) in &applied {
+        println!("Applied migration {}: {}", version, description);
+    }
+    match cli.command {
+        Some(Command::Migrate) => {
+            println!("Database schema is at version {}", migrations::latest_version());
+            return;
+        },
+        Some(Command::Admin(command)) => {
+            if let Err(e) = admin::run(db.as_ref(), command).await {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            return;
+        },
+        _ => {},
+    }
+    codegen::code_settings();
+    validation::url_rules();
+    validation::code_rules();
+    proxy::trusted_proxies();
+    responses::not_found_page();
+    jwt_keys::init_jwt_keys().expect("Failed to load JWT signing keys");
 
-    // Resolve the address to support both IPv4 and IPv6
-    let listener_address = format!("{}:{}", server_address, server_port).to_socket_addrs()
-        .expect("Invalid server address")
-        .next()
-        .expect("Could not resolve address");
+    let listener_address = config.listen_address().expect("Listen address is validated when the config is loaded");
 
     let main_router: Router = Router::new()
-        .fallback_service(ServeDir::new("./public/www"))
+        .fallback_service(ServeDir::new(&config.server.static_dir))
         .route("/test", axum::routing::get(|| async { "Hello, world!" }))
         .merge(routes())
         .layer(CookieManagerLayer::new())
-        .layer(CorsLayer::very_permissive())
+        .layer(cors_layer(&config.server.cors_origins))
         .layer(TraceLayer::new_for_http())
         .with_state(db)
         ;
@@ -46,5 +92,5 @@ async fn main() {
     println!("Started server at http://{}", &listener_address);
 
     let listener = tokio::net::TcpListener::bind(&listener_address).await.unwrap();
-    axum::serve(listener, main_router).await.unwrap();
-}
\ No newline at end of file
+    axum::serve(listener, main_router.into_make_service_with_connect_info::<SocketAddr>()).await.unwrap();
+}
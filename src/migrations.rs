@@ -0,0 +1,306 @@
+use rusqlite::{Connection, Result, Transaction};
+
+/// A numbered schema change. Migrations run in order, SQLite stores the number of the last
+/// applied one in its `user_version` pragma and PostgreSQL in the `schema_version` table.
+/// Every migration has to be written for both backends so that their schemas stay the same.
+pub struct Migration {
+    pub version: u32,
+    pub description: &'static str,
+    up: fn(&Transaction) -> Result<()>,
+    pub postgres: &'static str,
+}
+
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "create users and urls tables",
+        up: create_users_and_urls,
+        postgres: "CREATE TABLE users (
+                id SERIAL PRIMARY KEY,
+                username TEXT UNIQUE NOT NULL,
+                password TEXT NOT NULL
+            );
+            CREATE TABLE urls (
+                id SERIAL PRIMARY KEY,
+                user_id INTEGER REFERENCES users(id),
+                short TEXT UNIQUE NOT NULL,
+                long TEXT NOT NULL
+            );",
+    },
+    Migration {
+        version: 2,
+        description: "add archived flag to urls",
+        up: add_archived_flag,
+        postgres: "ALTER TABLE urls ADD COLUMN archived BOOLEAN NOT NULL DEFAULT FALSE;",
+    },
+    Migration {
+        version: 3,
+        description: "add expiration limits to urls",
+        up: add_expiration_limits,
+        postgres: "ALTER TABLE urls ADD COLUMN expires_at BIGINT;
+            ALTER TABLE urls ADD COLUMN max_clicks INTEGER;
+            ALTER TABLE urls ADD COLUMN clicks INTEGER NOT NULL DEFAULT 0;",
+    },
+    Migration {
+        version: 4,
+        description: "create clicks table",
+        up: create_clicks,
+        postgres: "CREATE TABLE clicks (
+                id BIGSERIAL PRIMARY KEY,
+                short TEXT NOT NULL,
+                clicked_at BIGINT NOT NULL,
+                referrer TEXT,
+                user_agent TEXT,
+                ip TEXT
+            );
+            CREATE INDEX clicks_short_clicked_at ON clicks (short, clicked_at);",
+    },
+    Migration {
+        version: 5,
+        description: "create refresh_tokens table",
+        up: create_refresh_tokens,
+        postgres: "CREATE TABLE refresh_tokens (
+                id SERIAL PRIMARY KEY,
+                token_hash TEXT UNIQUE NOT NULL,
+                family_id TEXT NOT NULL,
+                user_id INTEGER NOT NULL REFERENCES users(id),
+                expires_at BIGINT NOT NULL,
+                used BOOLEAN NOT NULL DEFAULT FALSE,
+                revoked BOOLEAN NOT NULL DEFAULT FALSE
+            );",
+    },
+    Migration {
+        version: 6,
+        description: "create api_keys table",
+        up: create_api_keys,
+        postgres: "CREATE TABLE api_keys (
+                id SERIAL PRIMARY KEY,
+                user_id INTEGER NOT NULL REFERENCES users(id),
+                name TEXT NOT NULL,
+                prefix TEXT NOT NULL,
+                key_hash TEXT UNIQUE NOT NULL,
+                scopes TEXT NOT NULL,
+                created_at BIGINT NOT NULL,
+                last_used_at BIGINT,
+                revoked BOOLEAN NOT NULL DEFAULT FALSE
+            );",
+    },
+    Migration {
+        version: 7,
+        description: "add redirect status to urls",
+        up: add_redirect_status,
+        postgres: "ALTER TABLE urls ADD COLUMN redirect_status INTEGER NOT NULL DEFAULT 307;",
+    },
+    Migration {
+        version: 8,
+        description: "add disabled flag to users",
+        up: add_user_disabled_flag,
+        postgres: "ALTER TABLE users ADD COLUMN disabled BOOLEAN NOT NULL DEFAULT FALSE;",
+    },
+    Migration {
+        version: 9,
+        description: "add role to users",
+        up: add_user_role,
+        postgres: "ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'user';",
+    },
+    Migration {
+        version: 10,
+        description: "add takedown flag to urls",
+        up: add_taken_down_flag,
+        postgres: "ALTER TABLE urls ADD COLUMN taken_down BOOLEAN NOT NULL DEFAULT FALSE;",
+    },
+    Migration {
+        version: 11,
+        description: "create workspaces and workspace_members tables",
+        up: create_workspaces,
+        postgres: "CREATE TABLE workspaces (
+                id SERIAL PRIMARY KEY,
+                name TEXT NOT NULL,
+                created_at BIGINT NOT NULL
+            );
+            CREATE TABLE workspace_members (
+                workspace_id INTEGER NOT NULL REFERENCES workspaces(id),
+                user_id INTEGER NOT NULL REFERENCES users(id),
+                role TEXT NOT NULL,
+                PRIMARY KEY (workspace_id, user_id)
+            );
+            ALTER TABLE urls ADD COLUMN workspace_id INTEGER REFERENCES workspaces(id);
+            CREATE INDEX urls_workspace_id ON urls (workspace_id);",
+    },
+];
+
+pub fn current_version(conn: &Connection) -> Result<u32> {
+    conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+}
+
+pub fn latest_version() -> u32 {
+    MIGRATIONS.last().map(|migration| migration.version).unwrap_or(0)
+}
+
+/// Applies every migration newer than the database, each one in its own transaction.
+/// Returns the migrations that were applied.
+pub fn run(conn: &mut Connection) -> Result<Vec<&'static Migration>> {
+    let mut applied = Vec::new();
+    let version = current_version(conn)?;
+
+    for migration in MIGRATIONS.iter().filter(|migration| migration.version > version) {
+        let tx = conn.transaction()?;
+        (migration.up)(&tx)?;
+        tx.pragma_update(None, "user_version", migration.version)?;
+        tx.commit()?;
+        applied.push(migration);
+    }
+    Ok(applied)
+}
+
+fn has_column(tx: &Transaction, table: &str, column: &str) -> Result<bool> {
+    let mut stmt = tx.prepare(&format!("PRAGMA table_info({})", table))?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        if row.get::<_, String>(1)? == column {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Databases created before migrations existed may already have some of the columns,
+/// so adding a column is skipped when it's there.
+fn add_column(tx: &Transaction, table: &str, column: &str, definition: &str) -> Result<()> {
+    if !has_column(tx, table, column)? {
+        tx.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition), [])?;
+    }
+    Ok(())
+}
+
+fn create_users_and_urls(tx: &Transaction) -> Result<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS users (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            username TEXT UNIQUE NOT NULL,
+            password TEXT NOT NULL
+            )",
+        [],
+    )?;
+
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS urls (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER,
+            short TEXT UNIQUE NOT NULL,
+            long TEXT NOT NULL,
+            FOREIGN KEY(user_id) REFERENCES users(id)
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+fn add_archived_flag(tx: &Transaction) -> Result<()> {
+    add_column(tx, "urls", "archived", "INTEGER NOT NULL DEFAULT 0")
+}
+
+fn add_expiration_limits(tx: &Transaction) -> Result<()> {
+    add_column(tx, "urls", "expires_at", "INTEGER")?;
+    add_column(tx, "urls", "max_clicks", "INTEGER")?;
+    add_column(tx, "urls", "clicks", "INTEGER NOT NULL DEFAULT 0")
+}
+
+fn create_clicks(tx: &Transaction) -> Result<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS clicks (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            short TEXT NOT NULL,
+            clicked_at INTEGER NOT NULL,
+            referrer TEXT,
+            user_agent TEXT,
+            ip TEXT
+        )",
+        [],
+    )?;
+
+    tx.execute(
+        "CREATE INDEX IF NOT EXISTS clicks_short_clicked_at ON clicks (short, clicked_at)",
+        [],
+    )?;
+    Ok(())
+}
+
+fn create_refresh_tokens(tx: &Transaction) -> Result<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS refresh_tokens (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            token_hash TEXT UNIQUE NOT NULL,
+            family_id TEXT NOT NULL,
+            user_id INTEGER NOT NULL,
+            expires_at INTEGER NOT NULL,
+            used INTEGER NOT NULL DEFAULT 0,
+            revoked INTEGER NOT NULL DEFAULT 0,
+            FOREIGN KEY(user_id) REFERENCES users(id)
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+fn create_api_keys(tx: &Transaction) -> Result<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS api_keys (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL,
+            name TEXT NOT NULL,
+            prefix TEXT NOT NULL,
+            key_hash TEXT UNIQUE NOT NULL,
+            scopes TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            last_used_at INTEGER,
+            revoked INTEGER NOT NULL DEFAULT 0,
+            FOREIGN KEY(user_id) REFERENCES users(id)
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+fn add_redirect_status(tx: &Transaction) -> Result<()> {
+    add_column(tx, "urls", "redirect_status", "INTEGER NOT NULL DEFAULT 307")
+}
+
+fn add_user_disabled_flag(tx: &Transaction) -> Result<()> {
+    add_column(tx, "users", "disabled", "INTEGER NOT NULL DEFAULT 0")
+}
+
+fn add_user_role(tx: &Transaction) -> Result<()> {
+    add_column(tx, "users", "role", "TEXT NOT NULL DEFAULT 'user'")
+}
+
+fn add_taken_down_flag(tx: &Transaction) -> Result<()> {
+    add_column(tx, "urls", "taken_down", "INTEGER NOT NULL DEFAULT 0")
+}
+
+fn create_workspaces(tx: &Transaction) -> Result<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS workspaces (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL,
+            created_at INTEGER NOT NULL
+        )",
+        [],
+    )?;
+
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS workspace_members (
+            workspace_id INTEGER NOT NULL,
+            user_id INTEGER NOT NULL,
+            role TEXT NOT NULL,
+            PRIMARY KEY (workspace_id, user_id),
+            FOREIGN KEY(workspace_id) REFERENCES workspaces(id),
+            FOREIGN KEY(user_id) REFERENCES users(id)
+        )",
+        [],
+    )?;
+
+    add_column(tx, "urls", "workspace_id", "INTEGER REFERENCES workspaces(id)")?;
+    tx.execute("CREATE INDEX IF NOT EXISTS urls_workspace_id ON urls (workspace_id)", [])?;
+    Ok(())
+}
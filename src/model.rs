@@ -1,13 +1,143 @@
-use axum::extract::FromRequestParts;
+use std::sync::Arc;
+
+use axum::extract::{FromRef, FromRequestParts};
 use axum::http::request::Parts;
+use crate::storage::Storage;
 use crate::responses::ApiError;
-use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
-use rand::{thread_rng, RngCore};
+use crate::jwt_keys::get_jwt_keys;
+use jsonwebtoken::{decode, decode_header, encode, Algorithm, Header, Validation};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Scope {
+    #[serde(rename = "links:read")]
+    LinksRead,
+    #[serde(rename = "links:write")]
+    LinksWrite,
+    #[serde(rename = "stats:read")]
+    StatsRead,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::LinksRead => "links:read",
+            Scope::LinksWrite => "links:write",
+            Scope::StatsRead => "stats:read",
+        }
+    }
+
+    pub fn parse(scope: &str) -> Option<Scope> {
+        match scope {
+            "links:read" => Some(Scope::LinksRead),
+            "links:write" => Some(Scope::LinksWrite),
+            "stats:read" => Some(Scope::StatsRead),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    User,
+    /// Can see and moderate every user and link.
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn parse(role: &str) -> Option<Role> {
+        match role {
+            "user" => Some(Role::User),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+}
+
+/// What a member may do in a workspace, each role includes the rights of the ones before it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum WorkspaceRole {
+    /// Sees the links of the workspace and their stats.
+    Viewer,
+    /// Also creates, edits and archives links.
+    Editor,
+    /// Also manages the members.
+    Owner,
+}
+
+impl WorkspaceRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WorkspaceRole::Viewer => "viewer",
+            WorkspaceRole::Editor => "editor",
+            WorkspaceRole::Owner => "owner",
+        }
+    }
+
+    pub fn parse(role: &str) -> Option<WorkspaceRole> {
+        match role {
+            "viewer" => Some(WorkspaceRole::Viewer),
+            "editor" => Some(WorkspaceRole::Editor),
+            "owner" => Some(WorkspaceRole::Owner),
+            _ => None,
+        }
+    }
+}
+
+/// Status code a short link answers with. Permanent redirects are cached by browsers,
+/// so later edits of the destination never reach people who already followed the link.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RedirectStatus {
+    MovedPermanently,
+    Found,
+    #[default]
+    TemporaryRedirect,
+    PermanentRedirect,
+}
+
+impl RedirectStatus {
+    pub fn code(&self) -> u16 {
+        match self {
+            RedirectStatus::MovedPermanently => 301,
+            RedirectStatus::Found => 302,
+            RedirectStatus::TemporaryRedirect => 307,
+            RedirectStatus::PermanentRedirect => 308,
+        }
+    }
+
+    pub fn from_code(code: u16) -> Option<RedirectStatus> {
+        match code {
+            301 => Some(RedirectStatus::MovedPermanently),
+            302 => Some(RedirectStatus::Found),
+            307 => Some(RedirectStatus::TemporaryRedirect),
+            308 => Some(RedirectStatus::PermanentRedirect),
+            _ => None,
+        }
+    }
+
+    pub fn is_permanent(&self) -> bool {
+        matches!(self, RedirectStatus::MovedPermanently | RedirectStatus::PermanentRedirect)
+    }
+}
 
-#[derive(Serialize)]
-pub struct LoginResponse{
-    pub token: String,
+/// Refresh tokens are signed with the same keys as access tokens, the type keeps one from being used as the other.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenType {
+    #[default]
+    Access,
+    Refresh,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -15,57 +145,150 @@ pub struct Claims{
     pub sub: String,
     pub exp: usize,
     pub persistent: bool,
+    /// Unique id of refresh tokens, so that two tokens issued in the same second never match.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
+    /// Set only for requests authenticated with an API key, sessions are allowed to do everything.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scopes: Option<Vec<Scope>>,
+    /// Tokens issued before roles existed don't have it and belong to regular users.
+    #[serde(default)]
+    pub role: Role,
+    #[serde(default)]
+    pub typ: TokenType,
+}
+
+impl Claims {
+    /// Refresh tokens issued before the type claim existed are recognised by their jti.
+    pub fn is_refresh_token(&self) -> bool {
+        self.typ == TokenType::Refresh || self.jti.is_some()
+    }
+}
+
+/// Prefix of personal API keys, used to tell them apart from JWTs in the Authorization header.
+pub const API_KEY_PREFIX: &str = "usk_";
+
+pub fn hash_api_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
 }
 
 
 #[derive(Debug)]
 pub struct AuthenticatedUser(pub Claims);
 
+impl AuthenticatedUser {
+    pub fn require_scope(&self, scope: Scope) -> Result<(), ApiError> {
+        match &self.0.scopes {
+            Some(scopes) if !scopes.contains(&scope) => Err(ApiError::Forbidden),
+            _ => Ok(()),
+        }
+    }
+
+    /// Some actions, like managing API keys, are only allowed with a regular login session.
+    pub fn require_session(&self) -> Result<(), ApiError> {
+        match self.0.scopes {
+            Some(_) => Err(ApiError::Forbidden),
+            None => Ok(()),
+        }
+    }
+}
+
 impl<S> FromRequestParts<S> for AuthenticatedUser
-where S: Send + Sync
+where
+    S: Send + Sync,
+    Arc<dyn Storage>: FromRef<S>,
 {
     type Rejection = ApiError;
 
-    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
         // Extract the "Authorization" header
-        if let Some(auth_header) = parts.headers.get("Authorization") {
-            match auth_header.to_str() {
-                Ok(auth_str) => match validate_jwt_token(auth_str) {
-                    Ok(claims) => Ok(AuthenticatedUser(claims)),
-                    Err(_) => Err(ApiError::AuthError),
-                },
-                Err(_) => Err(ApiError::AuthError),
-            }
-        } else {
-            // Outcome::Error((Status::Forbidden, AuthError()))
-            Err(ApiError::AuthError)
+        let Some(auth_header) = parts.headers.get("Authorization") else {
+            return Err(ApiError::AuthError)
+        };
+        let Ok(auth_str) = auth_header.to_str() else {
+            return Err(ApiError::AuthError)
+        };
+        let token = auth_str.strip_prefix("Bearer ").unwrap_or(auth_str);
+
+        if token.starts_with(API_KEY_PREFIX) {
+            let db = Arc::<dyn Storage>::from_ref(state);
+            return match db.use_api_key(&hash_api_key(token)).await {
+                Ok(Some((username, scopes))) => Ok(AuthenticatedUser(Claims {
+                    sub: username,
+                    exp: 0,
+                    persistent: false,
+                    jti: None,
+                    scopes: Some(scopes),
+                    role: Role::User,
+                    typ: TokenType::Access,
+                })),
+                Ok(None) => Err(ApiError::AuthError),
+                Err(_) => Err(ApiError::InternalServerError),
+            };
+        }
+
+        let mut claims = match validate_jwt_token(token) {
+            // a refresh token lives much longer and survives logout, it must never stand in for an access token
+            Ok(claims) if claims.is_refresh_token() => return Err(ApiError::AuthError),
+            Ok(claims) => claims,
+            Err(_) => return Err(ApiError::AuthError),
+        };
+
+        // the account is checked on every request, so disabling a user or changing their role
+        // applies right away instead of when their access token expires
+        let db = Arc::<dyn Storage>::from_ref(state);
+        match db.get_user_status(&claims.sub).await {
+            Ok(Some(user)) if user.disabled => Err(ApiError::AccountDisabled),
+            Ok(Some(user)) => {
+                claims.role = user.role;
+                Ok(AuthenticatedUser(claims))
+            },
+            Ok(None) => Err(ApiError::AuthError),
+            Err(_) => Err(ApiError::InternalServerError),
         }
     }
 }
 
-use std::sync::OnceLock;
+/// A logged in administrator. The role comes from the database, so demoted or disabled
+/// admins lose access right away instead of when their token expires.
+#[derive(Debug)]
+pub struct AdminUser(pub Claims);
 
-// Create a static global key
-static JWT_ENCODING_KEY: OnceLock<[u8; 32]> = OnceLock::new();
+impl<S> FromRequestParts<S> for AdminUser
+where
+    S: Send + Sync,
+    Arc<dyn Storage>: FromRef<S>,
+{
+    type Rejection = ApiError;
 
-fn generate_secret_key() -> [u8; 32] {
-    let mut key = [0u8; 32]; // 256-bit key for HS256
-    thread_rng().fill_bytes(&mut key);
-    key
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let user = AuthenticatedUser::from_request_parts(parts, state).await?;
+        // API keys never grant admin rights
+        user.require_session()?;
+        match user.0.role {
+            Role::Admin => Ok(AdminUser(user.0)),
+            Role::User => Err(ApiError::Forbidden),
+        }
+    }
 }
 
-// Function to get the key (it will be initialized once)
-pub fn get_jwt_encoding_key() -> &'static [u8; 32] {
-    JWT_ENCODING_KEY.get_or_init(|| { generate_secret_key() } )
+pub fn encode_jwt(claims: &Claims) -> Result<String, jsonwebtoken::errors::Error> {
+    let keys = get_jwt_keys();
+    let header = Header {
+        kid: Some(keys.active_kid.clone()),
+        ..Header::new(Algorithm::HS256)
+    };
+    encode(&header, claims, keys.encoding_key())
 }
 
-
 pub fn validate_jwt_token(token: &str) -> Result<Claims, ApiError> {
-    let decoding_key = DecodingKey::from_secret(get_jwt_encoding_key()); 
+    let header = decode_header(token).map_err(|_| ApiError::AuthError)?;
+    let kid = header.kid.ok_or(ApiError::AuthError)?;
+    let decoding_key = get_jwt_keys().decoding_key(&kid).ok_or(ApiError::AuthError)?;
     
     let validation = Validation::new(Algorithm::HS256);
     
-    match decode::<Claims>(token, &decoding_key, &validation) {
+    match decode::<Claims>(token, decoding_key, &validation) {
         Ok(token_data) => Ok(token_data.claims),
         Err(_) => Err(ApiError::AuthError),
     }
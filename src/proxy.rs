@@ -0,0 +1,86 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::OnceLock;
+
+use axum::http::HeaderMap;
+
+use crate::config::config;
+
+/// A single address or a CIDR range like `10.0.0.0/8`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IpRange {
+    network: IpAddr,
+    prefix: u32,
+}
+
+impl IpRange {
+    pub fn parse(range: &str) -> Option<IpRange> {
+        let (address, prefix) = match range.trim().split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (range.trim(), None),
+        };
+        let network: IpAddr = address.parse().ok()?;
+        let bits = match network {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse().ok().filter(|prefix| *prefix <= bits)?,
+            None => bits,
+        };
+        Some(IpRange { network, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // IPv4 clients of a dual stack listener show up as ::ffff:a.b.c.d
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                (u32::from(network) ^ u32::from(ip)).checked_shr(32 - self.prefix).unwrap_or(0) == 0
+            },
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                (u128::from(network) ^ u128::from(ip)).checked_shr(128 - self.prefix).unwrap_or(0) == 0
+            },
+            _ => false,
+        }
+    }
+}
+
+static TRUSTED_PROXIES: OnceLock<Vec<IpRange>> = OnceLock::new();
+
+pub fn trusted_proxies() -> &'static [IpRange] {
+    TRUSTED_PROXIES.get_or_init(|| config().server.trusted_proxies.iter()
+        .map(|range| IpRange::parse(range).expect("Trusted proxies are validated when the config is loaded"))
+        .collect())
+}
+
+/// Address of a X-Forwarded-For entry, which some proxies send with a port.
+fn parse_forwarded(entry: &str) -> Option<IpAddr> {
+    let entry = entry.trim();
+    entry.parse().ok().or_else(|| entry.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+}
+
+/// Client address of the request. Anyone can send X-Forwarded-For, so it's only read when the
+/// request comes from one of our proxies, and then from the right: every trusted proxy appends
+/// the address it got the request from, the first one that isn't a proxy is the client.
+pub fn client_ip(headers: &HeaderMap, peer: SocketAddr, proxies: &[IpRange]) -> IpAddr {
+    let is_trusted = |ip: IpAddr| proxies.iter().any(|range| range.contains(ip));
+    let mut client = peer.ip().to_canonical();
+    if !is_trusted(client) {
+        return client;
+    }
+
+    let forwarded: Vec<&str> = headers.get_all("X-Forwarded-For").iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(','))
+        .collect();
+    for entry in forwarded.into_iter().rev() {
+        // what's left of a garbled entry can't be trusted, the last proxy is as far as we know
+        let Some(ip) = parse_forwarded(entry) else {
+            break;
+        };
+        client = ip.to_canonical();
+        if !is_trusted(client) {
+            break;
+        }
+    }
+    client
+}
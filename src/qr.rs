@@ -0,0 +1,135 @@
+use std::io::Cursor;
+
+use image::{ImageFormat, Rgba, RgbaImage};
+use qrcode::{Color, EcLevel, QrCode};
+use serde::Deserialize;
+
+use crate::responses::ApiError;
+
+const DEFAULT_SIZE: u32 = 256;
+/// Plenty for print, and keeps a single request from making us encode a huge PNG.
+const MAX_SIZE: u32 = 1024;
+const DEFAULT_MARGIN: u32 = 4;
+const MAX_MARGIN: u32 = 32;
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum QrFormat {
+    #[default]
+    Png,
+    Svg,
+}
+
+/// Query parameters accepted by the QR endpoint. Colours are hex strings
+/// (`000000`, `#000000` or with an alpha channel, `000000ff`).
+#[derive(Deserialize, Default)]
+pub struct QrOptions {
+    pub format: Option<QrFormat>,
+    pub size: Option<u32>,
+    pub margin: Option<u32>,
+    pub ec: Option<String>,
+    pub fg: Option<String>,
+    pub bg: Option<String>,
+}
+
+pub struct QrImage {
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+fn parse_ec_level(level: &str) -> Result<EcLevel, ApiError> {
+    match level.to_ascii_uppercase().as_str() {
+        "L" => Ok(EcLevel::L),
+        "M" => Ok(EcLevel::M),
+        "Q" => Ok(EcLevel::Q),
+        "H" => Ok(EcLevel::H),
+        _ => Err(ApiError::InvalidParameters),
+    }
+}
+
+fn parse_color(color: &str) -> Result<[u8; 4], ApiError> {
+    let hex = color.strip_prefix('#').unwrap_or(color);
+    if (hex.len() != 6 && hex.len() != 8) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(ApiError::InvalidParameters);
+    }
+
+    let mut rgba = [0, 0, 0, 255];
+    for (i, channel) in rgba.iter_mut().enumerate().take(hex.len() / 2) {
+        *channel = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| ApiError::InvalidParameters)?;
+    }
+    Ok(rgba)
+}
+
+fn svg_color(rgba: [u8; 4]) -> String {
+    format!("#{:02x}{:02x}{:02x}{:02x}", rgba[0], rgba[1], rgba[2], rgba[3])
+}
+
+pub fn render(data: &str, options: &QrOptions) -> Result<QrImage, ApiError> {
+    let ec_level = match &options.ec {
+        Some(level) => parse_ec_level(level)?,
+        None => EcLevel::M,
+    };
+    let fg = parse_color(options.fg.as_deref().unwrap_or("000000"))?;
+    let bg = parse_color(options.bg.as_deref().unwrap_or("ffffff"))?;
+    let size = options.size.unwrap_or(DEFAULT_SIZE);
+    let margin = options.margin.unwrap_or(DEFAULT_MARGIN);
+    if size == 0 || size > MAX_SIZE || margin > MAX_MARGIN {
+        return Err(ApiError::InvalidParameters);
+    }
+
+    let code = QrCode::with_error_correction_level(data, ec_level).map_err(|_| ApiError::InvalidParameters)?;
+    let width = code.width() as u32;
+    let modules = code.to_colors();
+    let total = width + 2 * margin;
+
+    match options.format.unwrap_or_default() {
+        QrFormat::Png => {
+            // every module is drawn as a whole number of pixels so the code stays sharp, what's left
+            // of the requested size is spread around it as extra quiet zone
+            let scale = size / total;
+            if scale == 0 {
+                return Err(ApiError::InvalidParameters);
+            }
+            let offset = (size - total * scale) / 2;
+            let mut img = RgbaImage::from_pixel(size, size, Rgba(bg));
+            for (i, module) in modules.iter().enumerate() {
+                if *module != Color::Dark {
+                    continue;
+                }
+                let x0 = offset + (i as u32 % width + margin) * scale;
+                let y0 = offset + (i as u32 / width + margin) * scale;
+                for y in y0..y0 + scale {
+                    for x in x0..x0 + scale {
+                        img.put_pixel(x, y, Rgba(fg));
+                    }
+                }
+            }
+
+            let mut body = Vec::new();
+            img.write_to(&mut Cursor::new(&mut body), ImageFormat::Png)
+                .map_err(|_| ApiError::InternalServerError)?;
+            Ok(QrImage { content_type: "image/png", body })
+        },
+        QrFormat::Svg => {
+            let mut path = String::new();
+            for (i, module) in modules.iter().enumerate() {
+                if *module == Color::Dark {
+                    let x = i as u32 % width + margin;
+                    let y = i as u32 / width + margin;
+                    path.push_str(&format!("M{x} {y}h1v1h-1z"));
+                }
+            }
+
+            let svg = format!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                <svg xmlns=\"http://www.w3.org/2000/svg\" version=\"1.1\" width=\"{size}\" height=\"{size}\" viewBox=\"0 0 {total} {total}\" shape-rendering=\"crispEdges\">\
+                <rect width=\"100%\" height=\"100%\" fill=\"{}\"/>\
+                <path fill=\"{}\" d=\"{path}\"/>\
+                </svg>\n",
+                svg_color(bg),
+                svg_color(fg),
+            );
+            Ok(QrImage { content_type: "image/svg+xml", body: svg.into_bytes() })
+        },
+    }
+}
@@ -1,10 +1,13 @@
 use axum::{http::StatusCode, response::{IntoResponse, Response}, Json};
 use serde::Serialize;
-use chrono;
+
+use crate::validation::ValidationError;
 
 #[derive(Serialize)]
 pub struct ErrResponseBody {
     pub error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<ValidationError>,
     pub timestamp: String,
 }
 
@@ -13,50 +16,72 @@ pub struct ErrResponseBody {
 pub enum ApiError{
     AuthError,
     Forbidden,
-    // NotFound,
+    NotFound,
     CannotGenerateToken,
     InvalidCredentials,
+    AccountDisabled,
     InternalServerError,
     UserAlreadyExists,
     Conflict,
+    InvalidParameters,
+    LinkExpired,
+    Validation(ValidationError),
 }
 
 impl ApiError{
     pub fn message(&self) -> &'static str {
         match self {
             ApiError::AuthError => "User not authenticated",
-            // ApiError::NotFound => "Data not found",
+            ApiError::NotFound => "Data not found",
             ApiError::CannotGenerateToken => "Could not generate access token",
             ApiError::InvalidCredentials => "Invalid Credentials",
+            ApiError::AccountDisabled => "Account disabled",
             ApiError::InternalServerError => "Internal server error",
             ApiError::UserAlreadyExists => "User already exists",
             ApiError::Conflict => "Data already exists",
             ApiError::Forbidden => "Forbidden",
+            ApiError::InvalidParameters => "Invalid parameters",
+            ApiError::LinkExpired => "Link expired",
+            ApiError::Validation(_) => "Validation failed",
 
 
         }
     }
 
     pub fn to_response_body(&self) -> ErrResponseBody {
-        ErrResponseBody { error: self.message().to_string(), timestamp: chrono::Utc::now().to_rfc3339() }
+        let details = match self {
+            ApiError::Validation(error) => Some(error.clone()),
+            _ => None,
+        };
+        ErrResponseBody { error: self.message().to_string(), details, timestamp: chrono::Utc::now().to_rfc3339() }
     }
 
     fn status_code(&self) -> StatusCode{
         match self {
             ApiError::AuthError => StatusCode::UNAUTHORIZED,
-            // ApiError::NotFound => StatusCode::NOT_FOUND,
+            ApiError::NotFound => StatusCode::NOT_FOUND,
             ApiError::CannotGenerateToken => StatusCode::INTERNAL_SERVER_ERROR,
             ApiError::InvalidCredentials => StatusCode::UNAUTHORIZED,
+            ApiError::AccountDisabled => StatusCode::FORBIDDEN,
             ApiError::InternalServerError => StatusCode::INTERNAL_SERVER_ERROR,
             ApiError::UserAlreadyExists => StatusCode::CONFLICT,
             ApiError::Conflict => StatusCode::CONFLICT,
             ApiError::Forbidden => StatusCode::FORBIDDEN,            
+            ApiError::InvalidParameters => StatusCode::BAD_REQUEST,
+            ApiError::LinkExpired => StatusCode::GONE,
+            ApiError::Validation(_) => StatusCode::BAD_REQUEST,
 
 
         }
     }
 }
 
+impl From<ValidationError> for ApiError {
+    fn from(error: ValidationError) -> Self {
+        ApiError::Validation(error)
+    }
+}
+
 impl IntoResponse for ApiError{
     fn into_response(self) -> Response{
         let status = self.status_code();
@@ -0,0 +1,32 @@
+use axum::{http::{header, StatusCode}, response::{Html, IntoResponse, Response}};
+
+const LINK_EXPIRED_PAGE: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <meta name="viewport" content="width=device-width, initial-scale=1">
+  <title>Link expired</title>
+  <style>
+    body { font-family: system-ui, sans-serif; display: flex; align-items: center; justify-content: center; min-height: 100vh; margin: 0; color: #222; }
+    main { text-align: center; }
+    a { color: #2563eb; }
+  </style>
+</head>
+<body>
+  <main>
+    <h1>Link expired</h1>
+    <p>This short link has expired or reached its click limit.</p>
+    <p><a href="/">Go to the homepage</a></p>
+  </main>
+</body>
+</html>
+"#;
+
+/// HTML 410 for browsers following a short link that expired or ran out of clicks.
+pub struct LinkExpiredPage;
+
+impl IntoResponse for LinkExpiredPage {
+    fn into_response(self) -> Response {
+        (StatusCode::GONE, [(header::CACHE_CONTROL, "no-store")], Html(LINK_EXPIRED_PAGE)).into_response()
+    }
+}
@@ -1,5 +1,9 @@
 mod err_response;
 mod ok_response;
+mod not_found_page;
+mod link_expired_page;
 
 pub use err_response::ApiError;
-pub use ok_response::OkResponse;
\ No newline at end of file
+pub use ok_response::OkResponse;
+pub use not_found_page::{not_found_page, NotFoundPage};
+pub use link_expired_page::LinkExpiredPage;
\ No newline at end of file
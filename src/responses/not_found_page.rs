@@ -0,0 +1,48 @@
+use std::sync::OnceLock;
+
+use axum::{http::{header, StatusCode}, response::{Html, IntoResponse, Response}};
+
+use crate::config::config;
+
+const DEFAULT_NOT_FOUND_PAGE: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <meta name="viewport" content="width=device-width, initial-scale=1">
+  <title>Link not found</title>
+  <style>
+    body { font-family: system-ui, sans-serif; display: flex; align-items: center; justify-content: center; min-height: 100vh; margin: 0; color: #222; }
+    main { text-align: center; }
+    a { color: #2563eb; }
+  </style>
+</head>
+<body>
+  <main>
+    <h1>Link not found</h1>
+    <p>This short link doesn't exist or has been removed.</p>
+    <p><a href="/">Go to the homepage</a></p>
+  </main>
+</body>
+</html>
+"#;
+
+static NOT_FOUND_PAGE: OnceLock<String> = OnceLock::new();
+
+/// Page shown for unknown short codes, read from server.not_found_page when it's set.
+pub fn not_found_page() -> &'static str {
+    NOT_FOUND_PAGE.get_or_init(|| match &config().server.not_found_page {
+        Some(path) => std::fs::read_to_string(path)
+            .unwrap_or_else(|e| panic!("Cannot read not found page {}: {}", path.display(), e)),
+        None => DEFAULT_NOT_FOUND_PAGE.to_string(),
+    })
+}
+
+/// HTML 404 for browsers following a short link that doesn't exist.
+pub struct NotFoundPage;
+
+impl IntoResponse for NotFoundPage {
+    fn into_response(self) -> Response {
+        // not cached, the code may be taken by a new link later
+        (StatusCode::NOT_FOUND, [(header::CACHE_CONTROL, "no-store")], Html(not_found_page())).into_response()
+    }
+}
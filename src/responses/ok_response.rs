@@ -1,6 +1,5 @@
 use axum::{http::StatusCode, response::{IntoResponse, Response}, Json};
 use serde::Serialize;
-use chrono;
 
 
 #[derive(Serialize, Debug)]
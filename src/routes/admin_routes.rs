@@ -0,0 +1,107 @@
+use std::sync::Arc;
+
+use axum::{extract::{self, State}, routing::{get, post}, Router};
+use serde::{Deserialize, Serialize};
+
+use crate::{storage::{CacheStats, LinkFilter, LinkInfo, Storage, SystemStats, UserInfo}, model::AdminUser, responses::{ApiError, OkResponse}, validation};
+
+/// Page size of `/admin/links` when the request doesn't say, and the most it may ask for.
+const DEFAULT_PAGE_SIZE: u32 = 50;
+const MAX_PAGE_SIZE: u32 = 500;
+
+#[derive(Deserialize)]
+struct LinkQuery {
+    user: Option<String>,
+    search: Option<String>,
+    archived: Option<bool>,
+    limit: Option<u32>,
+    offset: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct AdminStats {
+    #[serde(flatten)]
+    system: SystemStats,
+    /// Missing when the link cache is turned off.
+    cache: Option<CacheStats>,
+}
+
+async fn list_users(_admin: AdminUser, State(db): State<Arc<dyn Storage>>) -> Result<OkResponse<Vec<UserInfo>>, ApiError> {
+    match db.list_users().await {
+        Ok(users) => Ok(OkResponse::new(users)),
+        Err(_) => Err(ApiError::InternalServerError),
+    }
+}
+
+async fn set_user_disabled(db: &dyn Storage, admin: &AdminUser, username: String, disabled: bool) -> Result<OkResponse<String>, ApiError> {
+    // an admin locking themselves out would need someone with shell access to undo it
+    if username == admin.0.sub {
+        return Err(ApiError::InvalidParameters);
+    }
+    match db.set_user_disabled(&username, disabled).await {
+        Ok(true) => Ok(OkResponse::new(username)),
+        Ok(false) => Err(ApiError::NotFound),
+        Err(_) => Err(ApiError::InternalServerError),
+    }
+}
+
+async fn disable_user(admin: AdminUser, State(db): State<Arc<dyn Storage>>, extract::Path(username): extract::Path<String>) -> Result<OkResponse<String>, ApiError> {
+    set_user_disabled(db.as_ref(), &admin, username, true).await
+}
+
+async fn enable_user(admin: AdminUser, State(db): State<Arc<dyn Storage>>, extract::Path(username): extract::Path<String>) -> Result<OkResponse<String>, ApiError> {
+    set_user_disabled(db.as_ref(), &admin, username, false).await
+}
+
+async fn list_links(_admin: AdminUser, State(db): State<Arc<dyn Storage>>, extract::Query(query): extract::Query<LinkQuery>) -> Result<OkResponse<Vec<LinkInfo>>, ApiError> {
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if limit == 0 || limit > MAX_PAGE_SIZE {
+        return Err(ApiError::InvalidParameters);
+    }
+    let filter = LinkFilter {
+        owner: query.user,
+        search: query.search.filter(|search| !search.is_empty()),
+        archived: query.archived,
+        limit,
+        offset: query.offset.unwrap_or(0),
+    };
+    match db.find_links(&filter).await {
+        Ok(links) => Ok(OkResponse::new(links)),
+        Err(_) => Err(ApiError::InternalServerError),
+    }
+}
+
+async fn set_link_taken_down(db: &dyn Storage, short: String, taken_down: bool) -> Result<OkResponse<String>, ApiError> {
+    let short = validation::code_rules().normalize(&short);
+    match db.set_url_taken_down(&short, taken_down).await {
+        Ok(true) => Ok(OkResponse::new(short)),
+        Ok(false) => Err(ApiError::NotFound),
+        Err(_) => Err(ApiError::InternalServerError),
+    }
+}
+
+async fn take_down_link(_admin: AdminUser, State(db): State<Arc<dyn Storage>>, extract::Path(short): extract::Path<String>) -> Result<OkResponse<String>, ApiError> {
+    set_link_taken_down(db.as_ref(), short, true).await
+}
+
+async fn restore_link(_admin: AdminUser, State(db): State<Arc<dyn Storage>>, extract::Path(short): extract::Path<String>) -> Result<OkResponse<String>, ApiError> {
+    set_link_taken_down(db.as_ref(), short, false).await
+}
+
+async fn system_stats(_admin: AdminUser, State(db): State<Arc<dyn Storage>>) -> Result<OkResponse<AdminStats>, ApiError> {
+    match db.system_stats().await {
+        Ok(system) => Ok(OkResponse::new(AdminStats { system, cache: db.cache_stats() })),
+        Err(_) => Err(ApiError::InternalServerError),
+    }
+}
+
+pub fn admin_router() -> Router<Arc<dyn Storage>> {
+    Router::new()
+        .route("/admin/users", get(list_users))
+        .route("/admin/users/{username}/disable", post(disable_user))
+        .route("/admin/users/{username}/enable", post(enable_user))
+        .route("/admin/links", get(list_links))
+        .route("/admin/links/{short}/take-down", post(take_down_link))
+        .route("/admin/links/{short}/restore", post(restore_link))
+        .route("/admin/stats", get(system_stats))
+}
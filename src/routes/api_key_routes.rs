@@ -0,0 +1,65 @@
+use std::sync::Arc;
+
+use axum::{extract::{self, State}, routing::{delete, get}, Router};
+use rand::{thread_rng, RngCore};
+use serde::{Deserialize, Serialize};
+
+use crate::{storage::{ApiKeyInfo, Storage}, model::{hash_api_key, AuthenticatedUser, Scope, API_KEY_PREFIX}, responses::{ApiError, OkResponse}};
+use super::user_id;
+
+#[derive(Deserialize)]
+struct NewApiKeyData {
+    name: String,
+    scopes: Vec<Scope>,
+}
+
+#[derive(Serialize)]
+struct NewApiKey {
+    /// The plain key is only returned once, we store just its hash.
+    key: String,
+    #[serde(flatten)]
+    info: ApiKeyInfo,
+}
+
+async fn create_api_key(State(db): State<Arc<dyn Storage>>, user: AuthenticatedUser, extract::Json(data): extract::Json<NewApiKeyData>) -> Result<OkResponse<NewApiKey>, ApiError> {
+    user.require_session()?;
+    let user_id = user_id(db.as_ref(), &user).await?;
+    if data.name.trim().is_empty() || data.scopes.is_empty() {
+        return Err(ApiError::InvalidParameters);
+    }
+
+    let mut secret = [0u8; 24];
+    thread_rng().fill_bytes(&mut secret);
+    let key = format!("{}{}", API_KEY_PREFIX, hex::encode(secret));
+    let prefix = key[..API_KEY_PREFIX.len() + 8].to_string();
+
+    match db.create_api_key(user_id, data.name.trim(), &prefix, &hash_api_key(&key), &data.scopes).await {
+        Ok(info) => Ok(OkResponse::new(NewApiKey { key, info })),
+        Err(_) => Err(ApiError::InternalServerError),
+    }
+}
+
+async fn list_api_keys(State(db): State<Arc<dyn Storage>>, user: AuthenticatedUser) -> Result<OkResponse<Vec<ApiKeyInfo>>, ApiError> {
+    user.require_session()?;
+    let user_id = user_id(db.as_ref(), &user).await?;
+    match db.get_api_keys(user_id).await {
+        Ok(keys) => Ok(OkResponse::new(keys)),
+        Err(_) => Err(ApiError::InternalServerError),
+    }
+}
+
+async fn revoke_api_key(State(db): State<Arc<dyn Storage>>, user: AuthenticatedUser, extract::Path(key_id): extract::Path<u32>) -> Result<OkResponse<u32>, ApiError> {
+    user.require_session()?;
+    let user_id = user_id(db.as_ref(), &user).await?;
+    match db.revoke_api_key(user_id, key_id).await {
+        Ok(true) => Ok(OkResponse::new(key_id)),
+        Ok(false) => Err(ApiError::NotFound),
+        Err(_) => Err(ApiError::InternalServerError),
+    }
+}
+
+pub fn api_key_router() -> Router<Arc<dyn Storage>> {
+    Router::new()
+        .route("/api-keys", get(list_api_keys).post(create_api_key))
+        .route("/api-keys/{id}", delete(revoke_api_key))
+}
@@ -1,11 +1,12 @@
 use std::sync::Arc;
 
 use axum::{extract::{self, State}, response::Redirect, routing::{get, post}, Router};
-use jsonwebtoken::{encode, EncodingKey, Header};
+use rand::{thread_rng, RngCore};
 use serde::Deserialize;
+use sha2::{Digest, Sha256};
 use tower_cookies::{cookie::{self, time::{Duration, OffsetDateTime}}, Cookie, Cookies};
 
-use crate::{db::{DbConn, UserError}, model::{get_jwt_encoding_key, validate_jwt_token, AuthenticatedUser, Claims}, responses::{ApiError, OkResponse}};
+use crate::{config::config, storage::{RefreshTokenStatus, Storage, UserError}, model::{encode_jwt, validate_jwt_token, AuthenticatedUser, Claims, TokenType}, responses::{ApiError, OkResponse}};
 
 
 #[derive(Deserialize)]
@@ -15,66 +16,109 @@ struct LoginFormData {
     persistent: Option<bool>,
 }
 
-async fn refresh(cookies: Cookies) -> Result<OkResponse<String>, ApiError> {
-    if let Some(refresh_cookie) = cookies.get("refresh_token") {
-        let token = refresh_cookie.value();
-        match validate_jwt_token(token) {
-            Ok(claims) => {
-                let new_claims = Claims{
-                    sub: claims.sub.clone(),
-                    exp: (chrono::Utc::now() + chrono::Duration::hours(1)).timestamp() as usize,
-                    persistent: claims.persistent,
-                };
-                let new_token = match encode(&Header::default(), &new_claims, &EncodingKey::from_secret(get_jwt_encoding_key())) {
-                    Ok(token) => token,
-                    // Err(_) => return Err(status::Custom(Status::InternalServerError, Json(ErrorResponse {error:"Nie udało się wygenerować tokena".to_string()}))),
-                    Err(_) => { return Err(ApiError::InternalServerError) }
-                };
-
-                //before returning the new token, also make a new refresh token
-                let refresh_claims = Claims {
-                    exp: match claims.persistent {
-                        true => (chrono::Utc::now() + chrono::Duration::days(30)).timestamp() as usize,
-                        false => (chrono::Utc::now() + chrono::Duration::hours(1)).timestamp() as usize,
-                    },
-                    ..new_claims
-                };
-              
-                let refresh_token = match encode(&Header::default(), &refresh_claims, &EncodingKey::from_secret(get_jwt_encoding_key())) {
-                    Ok(token) => token,
-                    Err(_) => { return Err(ApiError::InternalServerError) }
-                };
-
-                let refresh_token_duration = match claims.persistent {
-                    true => Duration::days(30),
-                    false => Duration::hours(1),
-                };
-
-                //send the refresh token in a http-only cookie
-                cookies.add(Cookie::build(("refresh_token", refresh_token))
-                .http_only(true)
-                .secure(true)
-                .expires(OffsetDateTime::now_utc() + refresh_token_duration)
-                .max_age(refresh_token_duration)
-                .same_site(cookie::SameSite::Lax)
-                .build()
-                );
-
-                Ok(OkResponse::new(new_token))
-            },
-            // Err(_) => Err(status::Custom(Status::Unauthorized, Json(ErrorResponse {error:"Nieprawidłowy token".to_string()}))),
-            Err(_) => Err(ApiError::Forbidden)
+fn hash_refresh_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+fn random_id() -> String {
+    let mut id = [0u8; 16];
+    thread_rng().fill_bytes(&mut id);
+    hex::encode(id)
+}
+
+/// Issues a new refresh token in the given family, stores its hash and sends it in a http-only cookie.
+async fn issue_refresh_token(db: &dyn Storage, cookies: &Cookies, claims: &Claims, user_id: u32, family_id: &str) -> Result<(), ApiError> {
+    let refresh_token_duration = match claims.persistent {
+        true => chrono::Duration::seconds(config().auth.persistent_refresh_token_ttl),
+        false => chrono::Duration::seconds(config().auth.refresh_token_ttl),
+    };
+    let expires_at = chrono::Utc::now() + refresh_token_duration;
+
+    let refresh_claims = Claims {
+        exp: expires_at.timestamp() as usize,
+        jti: Some(random_id()),
+        typ: TokenType::Refresh,
+        ..claims.clone()
+    };
+
+    let refresh_token = match encode_jwt(&refresh_claims) {
+        Ok(token) => token,
+        Err(_) => {
+            eprintln!("Error generating token");
+            return Err(ApiError::CannotGenerateToken)
         }
-    } else {
-        Err(ApiError::Forbidden)
-        // Err(status::Custom(Status::Unauthorized, Json(ErrorResponse {error:"Brak tokena".to_string()})))
+    };
+
+    if db.store_refresh_token(&hash_refresh_token(&refresh_token), family_id, user_id, expires_at.timestamp()).await.is_err() {
+        return Err(ApiError::InternalServerError);
     }
+
+    let cookie_duration = Duration::seconds(refresh_token_duration.num_seconds());
+    cookies.add(Cookie::build(("refresh_token", refresh_token))
+        .http_only(true)
+        .secure(true)
+        .expires(OffsetDateTime::now_utc() + cookie_duration)
+        .max_age(cookie_duration)
+        .same_site(cookie::SameSite::Lax)
+        .build()
+    );
+    Ok(())
+}
+
+async fn refresh(State(db): State<Arc<dyn Storage>>, cookies: Cookies) -> Result<OkResponse<String>, ApiError> {
+    let Some(refresh_cookie) = cookies.get("refresh_token") else {
+        return Err(ApiError::Forbidden)
+    };
+    let token = refresh_cookie.value().to_string();
+    let claims = match validate_jwt_token(&token) {
+        Ok(claims) if claims.is_refresh_token() => claims,
+        _ => return Err(ApiError::Forbidden),
+    };
+
+    // every refresh token can be exchanged exactly once, a second use means it was stolen
+    let (family_id, user_id) = match db.use_refresh_token(&hash_refresh_token(&token)).await {
+        Ok(RefreshTokenStatus::Valid { family_id, user_id }) => (family_id, user_id),
+        Ok(RefreshTokenStatus::Reused) => {
+            eprintln!("Refresh token reuse detected for user {}, revoking the session", claims.sub);
+            cookies.remove(Cookie::build("refresh_token").build());
+            return Err(ApiError::Forbidden)
+        },
+        Ok(RefreshTokenStatus::Unknown) => return Err(ApiError::Forbidden),
+        Err(_) => return Err(ApiError::InternalServerError),
+    };
+
+    // the new token carries the current role for clients to show, access checks read it from the database on every request
+    let role = match db.get_user_status(&claims.sub).await {
+        Ok(Some(user)) => user.role,
+        Ok(None) => return Err(ApiError::Forbidden),
+        Err(_) => return Err(ApiError::InternalServerError),
+    };
+
+    let new_claims = Claims{
+        sub: claims.sub.clone(),
+        exp: (chrono::Utc::now() + chrono::Duration::seconds(config().auth.access_token_ttl)).timestamp() as usize,
+        persistent: claims.persistent,
+        jti: None,
+        scopes: None,
+        role,
+        typ: TokenType::Access,
+    };
+    let new_token = match encode_jwt(&new_claims) {
+        Ok(token) => token,
+        Err(_) => { return Err(ApiError::InternalServerError) }
+    };
+
+    //before returning the new token, also make a new refresh token
+    issue_refresh_token(db.as_ref(), &cookies, &new_claims, user_id, &family_id).await?;
+
+    Ok(OkResponse::new(new_token))
 }
 
-async fn logout(user: AuthenticatedUser, cookies: Cookies) -> Result<OkResponse<String>, Redirect>{
-    println!("{}",user.0.sub);
-    let refresh_cookie = cookies.get("refresh_token");
-    if refresh_cookie.is_some() {
+async fn logout(State(db): State<Arc<dyn Storage>>, user: AuthenticatedUser, cookies: Cookies) -> Result<OkResponse<String>, Redirect>{
+    if let Some(refresh_cookie) = cookies.get("refresh_token") {
+        if db.revoke_refresh_token_family(&hash_refresh_token(refresh_cookie.value())).await.is_err() {
+            eprintln!("Failed to revoke refresh token of {}", user.0.sub);
+        }
         cookies.remove(Cookie::build("refresh_token").build()); // Clone so it owns the cookie
         Ok(OkResponse::new("Logged out".to_string()))
     } else {
@@ -83,63 +127,46 @@ async fn logout(user: AuthenticatedUser, cookies: Cookies) -> Result<OkResponse<
 }
 
 
-async fn login(State(db): State<Arc<DbConn>>, cookies: Cookies, extract::Json(login_info): extract::Json<LoginFormData> ) -> Result<OkResponse<String>, ApiError> {
-    // match Ok(true) {
-    match db.login(&login_info.username, &login_info.password) {
+async fn login(State(db): State<Arc<dyn Storage>>, cookies: Cookies, extract::Json(login_info): extract::Json<LoginFormData> ) -> Result<OkResponse<String>, ApiError> {
+    match db.login(&login_info.username, &login_info.password).await {
         Ok(_) => {
+            let user = match db.get_user_status(&login_info.username).await {
+                Ok(Some(user)) => user,
+                _ => return Err(ApiError::InternalServerError),
+            };
+
             let claims = Claims {
                 sub: login_info.username.to_string().clone(),
-                exp: (chrono::Utc::now() + chrono::Duration::hours(1)).timestamp() as usize,
+                exp: (chrono::Utc::now() + chrono::Duration::seconds(config().auth.access_token_ttl)).timestamp() as usize,
                 persistent: login_info.persistent.unwrap_or(false),
+                jti: None,
+                scopes: None,
+                role: user.role,
+                typ: TokenType::Access,
             };
 
-            let token = match encode(&Header::default(), &claims, &EncodingKey::from_secret(get_jwt_encoding_key())) {
-                Ok(token) => token,
-                Err(_) => {
-                    eprintln!("Error generating token");
-                    return Err(ApiError::CannotGenerateToken)
-                }
-            };
-
-            let refresh_claims = Claims {
-                exp: match claims.persistent {
-                    true => (chrono::Utc::now() + chrono::Duration::days(30)).timestamp() as usize,
-                    false => (chrono::Utc::now() + chrono::Duration::hours(1)).timestamp() as usize,
-                },
-                ..claims
-            };
-
-            let refresh_token = match encode(&Header::default(), &refresh_claims, &EncodingKey::from_secret(get_jwt_encoding_key())) {
+            let token = match encode_jwt(&claims) {
                 Ok(token) => token,
                 Err(_) => {
                     eprintln!("Error generating token");
                     return Err(ApiError::CannotGenerateToken)
                 }
             };
-            let refresh_token_duration = match claims.persistent {
-                true => Duration::days(30),
-                false => Duration::hours(1),
-            };
 
-            cookies.add(Cookie::build(("refresh_token", refresh_token))
-                .http_only(true)
-                .secure(true)
-                .expires(OffsetDateTime::now_utc() + refresh_token_duration)
-                .max_age(refresh_token_duration)
-                .same_site(tower_cookies::cookie::SameSite::Lax)
-                .build()
-            );
+            // every login starts a new refresh token family
+            issue_refresh_token(db.as_ref(), &cookies, &claims, user.id, &random_id()).await?;
 
             Ok(OkResponse::new(token))
         },
         Err(UserError::InvalidCredentials) => Err(ApiError::InvalidCredentials),
+        Err(UserError::AccountDisabled) => Err(ApiError::AccountDisabled),
         Err(UserError::DatabaseError) => Err(ApiError::InternalServerError),
         Err(_) => Err(ApiError::InternalServerError),
     }
 }
 
-async fn create_user(State(db): State<Arc<DbConn>>, extract::Json(login_info): extract::Json<LoginFormData>) -> Result<OkResponse<String>, ApiError>{
-    match db.create_user(&login_info.username, &login_info.password) {
+async fn create_user(State(db): State<Arc<dyn Storage>>, extract::Json(login_info): extract::Json<LoginFormData>) -> Result<OkResponse<String>, ApiError>{
+    match db.create_user(&login_info.username, &login_info.password).await {
         Ok(_) => Ok(OkResponse::new(format!("User {} created successfully!", login_info.username))),
         Err(UserError::UserAlreadyExists) => Err(ApiError::UserAlreadyExists),
         Err(_) => Err(ApiError::InternalServerError),
@@ -151,7 +178,7 @@ async fn whoami(user: AuthenticatedUser) -> Result<OkResponse<Claims>, ApiError>
 }
 
 
-pub fn auth_router() -> Router<Arc<DbConn>> {
+pub fn auth_router() -> Router<Arc<dyn Storage>> {
     Router::new()
         .route("/login", post(login))
         .route("/logout", get(logout))
@@ -1,17 +1,46 @@
 mod url_shortener_routes;
 mod auth_routes;
 mod user_routes;
+mod api_key_routes;
+mod admin_routes;
+mod workspace_routes;
 
 use axum::Router;
 use user_routes::user_router;
 use std::sync::Arc;
-use crate::db::DbConn;
+use crate::storage::Storage;
+use crate::model::{AuthenticatedUser, WorkspaceRole};
+use crate::responses::ApiError;
 use url_shortener_routes::url_shortener_router;
 use auth_routes::auth_router;
+use api_key_routes::api_key_router;
+use admin_routes::admin_router;
+use workspace_routes::workspace_router;
 
-pub fn routes() -> axum::Router<Arc<DbConn>> {
+/// Id of the authenticated user, a token of a deleted user no longer counts as authenticated.
+async fn user_id(db: &dyn Storage, user: &AuthenticatedUser) -> Result<u32, ApiError> {
+    match db.get_user_id(&user.0.sub).await {
+        Ok(Some(id)) => Ok(id),
+        Ok(None) => Err(ApiError::AuthError),
+        Err(_) => Err(ApiError::InternalServerError),
+    }
+}
+
+/// Makes sure the user is a member of the workspace with at least the given role.
+async fn require_workspace_role(db: &dyn Storage, workspace_id: u32, user_id: u32, required: WorkspaceRole) -> Result<(), ApiError> {
+    match db.get_workspace_role(workspace_id, user_id).await {
+        Ok(Some(role)) if role >= required => Ok(()),
+        Ok(_) => Err(ApiError::Forbidden),
+        Err(_) => Err(ApiError::InternalServerError),
+    }
+}
+
+pub fn routes() -> axum::Router<Arc<dyn Storage>> {
     Router::new()
         .merge(url_shortener_router()) 
         .merge(auth_router())
         .merge(user_router())
+        .merge(api_key_router())
+        .merge(admin_router())
+        .merge(workspace_router())
 }
\ No newline at end of file
@@ -1,62 +1,451 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 
-use axum::extract::State;
-use axum::routing::{get, post};
+use axum::body::Bytes;
+use axum::extract::{ConnectInfo, DefaultBodyLimit, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, patch, post};
 use axum::{extract, Router};
-use serde::Deserialize;
-use crate::DbConn;
-use crate::model::AuthenticatedUser;
-use crate::responses::{ApiError, OkResponse};
+use serde::{Deserialize, Serialize};
+use crate::storage::{CacheStats, ClickEvent, LinkOwner, LinkStats, NewLink, Storage};
+use crate::codegen::{self, CodeStrategy};
+use crate::config::config;
+use crate::model::{AdminUser, AuthenticatedUser, RedirectStatus, Scope, WorkspaceRole};
+use crate::proxy;
+use crate::qr::{self, QrOptions};
+use crate::responses::{ApiError, LinkExpiredPage, NotFoundPage, OkResponse};
+use crate::validation::{self, ValidationError};
+use super::{require_workspace_role, user_id};
 
 #[derive(Deserialize)]
 struct LinkData {
     url: String,
-    code: Option<String>
+    code: Option<String>,
+    strategy: Option<CodeStrategy>,
+    expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    max_clicks: Option<u32>,
+    /// 301, 302, 307 or 308, temporary (307) when missing.
+    redirect_status: Option<u16>,
+    /// Id of the workspace the link is created in, a personal link when missing.
+    workspace: Option<u32>,
 }
 
-async fn shorten_link(State(db): State<Arc<DbConn>>,user: AuthenticatedUser, extract::Json(link): extract::Json<LinkData>) -> Result<OkResponse<String>, ApiError> {
-    let short_link: String;
-    
+/// A `LinkData` that passed validation.
+struct PreparedLink {
+    long: String,
+    /// Custom code, None when one has to be generated.
+    code: Option<String>,
+    strategy: CodeStrategy,
+    workspace: Option<u32>,
+    expires_at: Option<i64>,
+    max_clicks: Option<u32>,
+    redirect_status: RedirectStatus,
+}
+
+/// Outcome of one link of a bulk request, either the created code or the error.
+#[derive(Serialize)]
+struct BulkLinkResult {
+    /// Position of the link in the request, the CSV header row doesn't count.
+    index: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    details: Option<ValidationError>,
+}
+
+impl BulkLinkResult {
+    fn created(index: usize, code: String) -> Self {
+        BulkLinkResult { index, code: Some(code), error: None, details: None }
+    }
+
+    fn failed(index: usize, error: ApiError) -> Self {
+        let details = match &error {
+            ApiError::Validation(details) => Some(details.clone()),
+            _ => None,
+        };
+        BulkLinkResult { index, code: None, error: Some(error.message()), details }
+    }
+}
+
+#[derive(Deserialize)]
+struct EditLinkData {
+    url: String,
+}
+
+#[derive(Deserialize)]
+struct MoveLinkData {
+    workspace: u32,
+}
+
+/// Makes sure the authenticated user may act on the given short link. Personal links are only
+/// managed by their creator, links in a workspace by its members with at least the given role.
+async fn check_link_access(db: &dyn Storage, user: &AuthenticatedUser, short: &str, required: WorkspaceRole) -> Result<(), ApiError> {
+    let user_id = user_id(db, user).await?;
+
+    match db.get_url_owner(short).await {
+        Ok(Some(LinkOwner { workspace_id: Some(workspace_id), .. })) => require_workspace_role(db, workspace_id, user_id, required).await,
+        Ok(Some(owner)) if owner.user_id == user_id => Ok(()),
+        Ok(Some(_)) => Err(ApiError::Forbidden),
+        Ok(None) => Err(ApiError::NotFound),
+        Err(_) => Err(ApiError::InternalServerError),
+    }
+}
+
+/// Room for the other fields of a bulk link on top of its url, when sizing the request body limit.
+const BULK_LINK_OVERHEAD: usize = 512;
+
+/// How many different codes are tried before giving up on an auto-generated link.
+const MAX_CODE_ATTEMPTS: u32 = 10;
+
+fn prepare_link(link: LinkData) -> Result<PreparedLink, ApiError> {
+    let long = validation::normalize_url(&link.url)?;
+
+    let expires_at = link.expires_at.map(|date| date.timestamp());
+    if expires_at.is_some_and(|date| date <= chrono::Utc::now().timestamp()) || link.max_clicks == Some(0) {
+        return Err(ApiError::InvalidParameters);
+    }
+
+    let redirect_status = match link.redirect_status {
+        Some(code) => RedirectStatus::from_code(code).ok_or(ApiError::InvalidParameters)?,
+        None => RedirectStatus::default(),
+    };
+    // browsers cache permanent redirects, which would skip the expiration checks
+    if redirect_status.is_permanent() && (expires_at.is_some() || link.max_clicks.is_some()) {
+        return Err(ApiError::InvalidParameters);
+    }
+
+    Ok(PreparedLink {
+        long,
+        code: link.code.map(|code| validation::validate_code(&code)).transpose()?,
+        strategy: link.strategy.unwrap_or(codegen::code_settings().strategy),
+        workspace: link.workspace,
+        expires_at,
+        max_clicks: link.max_clicks,
+        redirect_status,
+    })
+}
+
+/// Codes generated for a link without a custom one, in the order they should be tried.
+fn generated_codes(link: &PreparedLink, seq: u64) -> Vec<String> {
+    let generator = codegen::generator(link.strategy);
+    let code_rules = validation::code_rules();
+    (0..MAX_CODE_ATTEMPTS)
+        .map(|attempt| code_rules.normalize(&generator.generate(&link.long, seq, attempt)))
+        .filter(|code| !code_rules.is_reserved(code))
+        .collect()
+}
+
+async fn shorten_link(State(db): State<Arc<dyn Storage>>,user: AuthenticatedUser, extract::Json(link): extract::Json<LinkData>) -> Result<OkResponse<String>, ApiError> {
+    user.require_scope(Scope::LinksWrite)?;
+    let user_id = user_id(db.as_ref(), &user).await?;
+    if let Some(workspace_id) = link.workspace {
+        require_workspace_role(db.as_ref(), workspace_id, user_id, WorkspaceRole::Editor).await?;
+    }
+    let owner = LinkOwner { user_id, workspace_id: link.workspace };
+    let link = prepare_link(link)?;
+
     if let Some(code) = link.code {
-        println!("code: {}", code);
-        match db.get_long_url(code.clone()) {
-            Some(_) => {
-                // there is already a URL with this code, we return error with explanation in JSON
-                // return Err(status::Custom(Status::Conflict, Json(ErrorResponse { error: "Jakiś pajac już zapisał link z takim kodem".to_string() })));
-                return Err(ApiError::Conflict)
-            },
-            None => {
-                short_link = code.to_string();
-            },
+        // claiming the code is the insert itself, so when two requests race for it exactly one wins
+        // and the other gets a conflict, there is no window between a check and the write
+        return match db.insert_url(&code, &link.long, owner, link.expires_at, link.max_clicks, link.redirect_status).await {
+            Ok(true) => Ok(OkResponse::new(code)),
+            Ok(false) => Err(ApiError::Conflict),
+            Err(_) => Err(ApiError::InternalServerError),
+        };
+    }
+
+    let seq = match db.next_url_id().await {
+        Ok(seq) => seq,
+        Err(_) => return Err(ApiError::InternalServerError),
+    };
+
+    // a generated code can collide with another link, in that case we try a different one
+    for short_link in generated_codes(&link, seq) {
+        match db.insert_url(&short_link, &link.long, owner, link.expires_at, link.max_clicks, link.redirect_status).await {
+            Ok(true) => return Ok(OkResponse::new(short_link)),
+            Ok(false) => continue,
+            Err(_) => return Err(ApiError::InternalServerError),
         }
-    } else {
-        short_link = format!("{:x}", md5::compute(&link.url))[..6].to_string();
     }
 
-    let long_url = link.url.to_string();
+    eprintln!("Could not find a free short code for {}", link.long);
+    Err(ApiError::InternalServerError)
+}
 
-    match db.insert_url(&short_link, &long_url, db.get_user_id(&user.0.sub).unwrap().unwrap()) {
-        // Ok(_) => Ok(Json(ShortLink { short_url: short_link })),
-        Ok(_) => Ok(OkResponse::new(short_link)),
-        Err(_) => Err(ApiError::InternalServerError)
-        // Err(_) => Err(status::Custom(Status::InternalServerError, Json(ErrorResponse { error: "Nie udało się zapisać rekordu w bazie".to_string() }))),
+/// Links of a bulk request, sent either as a JSON array of `LinkData` or as CSV with a header row
+/// naming the same fields. A link that can't be read only fails itself, not the whole request.
+fn parse_bulk_links(headers: &HeaderMap, body: &[u8]) -> Result<Vec<Result<LinkData, ApiError>>, ApiError> {
+    let content_type = headers.get(header::CONTENT_TYPE).and_then(|h| h.to_str().ok()).unwrap_or_default();
+    if content_type.starts_with("text/csv") {
+        let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).flexible(true).from_reader(body);
+        let columns = reader.headers().map_err(|_| ApiError::InvalidParameters)?;
+        if !columns.iter().any(|column| column == "url") {
+            return Err(ApiError::InvalidParameters);
+        }
+        return Ok(reader.deserialize().map(|link| link.map_err(|_| ApiError::InvalidParameters)).collect());
     }
+
+    let links: Vec<serde_json::Value> = serde_json::from_slice(body).map_err(|_| ApiError::InvalidParameters)?;
+    Ok(links.into_iter().map(|link| serde_json::from_value(link).map_err(|_| ApiError::InvalidParameters)).collect())
 }
 
+/// Creates up to urls.max_batch_size links at once. Every link is validated on its own and all
+/// valid ones are inserted in a single transaction, the response has the result of each link.
+async fn bulk_shorten_links(State(db): State<Arc<dyn Storage>>, user: AuthenticatedUser, headers: HeaderMap, body: Bytes) -> Result<OkResponse<Vec<BulkLinkResult>>, ApiError> {
+    user.require_scope(Scope::LinksWrite)?;
+    let user_id = user_id(db.as_ref(), &user).await?;
+    let links = parse_bulk_links(&headers, &body)?;
+    if links.is_empty() || links.len() > config().urls.max_batch_size {
+        return Err(ApiError::InvalidParameters);
+    }
+
+    let seq = match db.next_url_id().await {
+        Ok(seq) => seq,
+        Err(_) => return Err(ApiError::InternalServerError),
+    };
 
-async fn redirect(State(db): State<Arc<DbConn>>, extract::Path(short_url): extract::Path<String>) -> axum::response::Redirect {
-    match db.get_long_url(short_url) {
-        Some(long_rl) => {
-            axum::response::Redirect::permanent(&long_rl)
-        },
-        None => {
-            axum::response::Redirect::permanent("/")
+    let mut results = Vec::with_capacity(links.len());
+    let mut new_links = Vec::new();
+    // index of each new link in the request and whether it asked for a custom code
+    let mut pending = Vec::new();
+    let mut workspace_access: HashMap<u32, Result<(), ApiError>> = HashMap::new();
+    for (index, link) in links.into_iter().enumerate() {
+        if let Ok(LinkData { workspace: Some(workspace_id), .. }) = &link {
+            if !workspace_access.contains_key(workspace_id) {
+                let access = require_workspace_role(db.as_ref(), *workspace_id, user_id, WorkspaceRole::Editor).await;
+                workspace_access.insert(*workspace_id, access);
+            }
         }
+        let link = link.and_then(|link| {
+            if let Some(Err(error)) = link.workspace.and_then(|workspace_id| workspace_access.get(&workspace_id)) {
+                return Err(error.clone());
+            }
+            prepare_link(link)
+        });
+        let link = match link {
+            Ok(link) => link,
+            Err(error) => {
+                results.push(BulkLinkResult::failed(index, error));
+                continue;
+            },
+        };
+
+        let codes = match &link.code {
+            Some(code) => vec![code.clone()],
+            None => generated_codes(&link, seq + index as u64),
+        };
+        pending.push((index, link.code.is_some()));
+        new_links.push(NewLink {
+            codes,
+            long: link.long,
+            owner: LinkOwner { user_id, workspace_id: link.workspace },
+            expires_at: link.expires_at,
+            max_clicks: link.max_clicks,
+            redirect_status: link.redirect_status,
+        });
+    }
+
+    let claimed = match db.insert_urls(new_links).await {
+        Ok(claimed) => claimed,
+        Err(_) => return Err(ApiError::InternalServerError),
+    };
+    for ((index, custom_code), code) in pending.into_iter().zip(claimed) {
+        results.push(match code {
+            Some(code) => BulkLinkResult::created(index, code),
+            None if custom_code => BulkLinkResult::failed(index, ApiError::Conflict),
+            None => BulkLinkResult::failed(index, ApiError::InternalServerError),
+        });
+    }
+    results.sort_by_key(|result| result.index);
+    Ok(OkResponse::new(results))
+}
+
+
+async fn edit_link(State(db): State<Arc<dyn Storage>>, user: AuthenticatedUser, extract::Path(short): extract::Path<String>, extract::Json(link): extract::Json<EditLinkData>) -> Result<OkResponse<String>, ApiError> {
+    user.require_scope(Scope::LinksWrite)?;
+    let short = validation::code_rules().normalize(&short);
+    let long_url = validation::normalize_url(&link.url)?;
+    check_link_access(db.as_ref(), &user, &short, WorkspaceRole::Editor).await?;
+
+    match db.update_url(&short, &long_url).await {
+        Ok(_) => Ok(OkResponse::new(short)),
+        Err(_) => Err(ApiError::InternalServerError),
+    }
+}
+
+async fn archive_link(State(db): State<Arc<dyn Storage>>, user: AuthenticatedUser, extract::Path(short): extract::Path<String>) -> Result<OkResponse<String>, ApiError> {
+    user.require_scope(Scope::LinksWrite)?;
+    let short = validation::code_rules().normalize(&short);
+    check_link_access(db.as_ref(), &user, &short, WorkspaceRole::Editor).await?;
+
+    match db.set_url_archived(&short, true).await {
+        Ok(_) => Ok(OkResponse::new(short)),
+        Err(_) => Err(ApiError::InternalServerError),
+    }
+}
+
+async fn restore_link(State(db): State<Arc<dyn Storage>>, user: AuthenticatedUser, extract::Path(short): extract::Path<String>) -> Result<OkResponse<String>, ApiError> {
+    user.require_scope(Scope::LinksWrite)?;
+    let short = validation::code_rules().normalize(&short);
+    check_link_access(db.as_ref(), &user, &short, WorkspaceRole::Editor).await?;
+
+    match db.set_url_archived(&short, false).await {
+        Ok(_) => Ok(OkResponse::new(short)),
+        Err(_) => Err(ApiError::InternalServerError),
+    }
+}
+
+/// Hands a link over to a workspace, e.g. before its creator leaves. Needs edit rights on the
+/// link and in the target workspace.
+async fn move_link(State(db): State<Arc<dyn Storage>>, user: AuthenticatedUser, extract::Path(short): extract::Path<String>, extract::Json(data): extract::Json<MoveLinkData>) -> Result<OkResponse<String>, ApiError> {
+    user.require_scope(Scope::LinksWrite)?;
+    let short = validation::code_rules().normalize(&short);
+    check_link_access(db.as_ref(), &user, &short, WorkspaceRole::Editor).await?;
+    let user_id = user_id(db.as_ref(), &user).await?;
+    require_workspace_role(db.as_ref(), data.workspace, user_id, WorkspaceRole::Editor).await?;
+
+    match db.set_url_workspace(&short, data.workspace).await {
+        Ok(_) => Ok(OkResponse::new(short)),
+        Err(_) => Err(ApiError::InternalServerError),
+    }
+}
+
+/// Base URL used to build full short links, e.g. for QR codes.
+/// Falls back to the Host header when server.public_url is not set.
+fn public_base_url(headers: &HeaderMap) -> String {
+    if let Some(url) = &config().server.public_url {
+        return url.clone();
+    }
+    let host = headers.get(header::HOST).and_then(|h| h.to_str().ok()).unwrap_or("localhost");
+    format!("http://{}", host)
+}
+
+async fn link_qr_code(State(db): State<Arc<dyn Storage>>, headers: HeaderMap, extract::Path(short_url): extract::Path<String>, extract::Query(options): extract::Query<QrOptions>) -> Result<impl IntoResponse, ApiError> {
+    let short_url = validation::code_rules().normalize(&short_url);
+    // a code for a link that doesn't redirect anymore would only lead to an error page
+    match db.get_link_target(&short_url).await {
+        Ok(Some(target)) if target.is_expired() => return Err(ApiError::LinkExpired),
+        Ok(Some(_)) => {},
+        Ok(None) => return Err(ApiError::NotFound),
+        Err(_) => return Err(ApiError::InternalServerError),
     }
+
+    let full_url = format!("{}/link/{}", public_base_url(&headers), short_url);
+    let image = qr::render(&full_url, &options)?;
+    Ok(([(header::CONTENT_TYPE, image.content_type)], image.body))
+}
+
+
+/// Drops the host part of the address (last octet for IPv4, everything past /48 for IPv6).
+fn anonymize_ip(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            format!("{}.{}.{}.0", a, b, c)
+        },
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            format!("{:x}:{:x}:{:x}::", segments[0], segments[1], segments[2])
+        },
+    }
+}
+
+async fn link_stats(State(db): State<Arc<dyn Storage>>, user: AuthenticatedUser, extract::Path(short): extract::Path<String>) -> Result<OkResponse<LinkStats>, ApiError> {
+    user.require_scope(Scope::StatsRead)?;
+    let short = validation::code_rules().normalize(&short);
+    check_link_access(db.as_ref(), &user, &short, WorkspaceRole::Viewer).await?;
+
+    match db.get_link_stats(&short).await {
+        Ok(stats) => Ok(OkResponse::new(stats)),
+        Err(_) => Err(ApiError::InternalServerError),
+    }
+}
+
+async fn link_cache_stats(State(db): State<Arc<dyn Storage>>, _admin: AdminUser) -> Result<OkResponse<CacheStats>, ApiError> {
+    match db.cache_stats() {
+        Some(stats) => Ok(OkResponse::new(stats)),
+        None => Err(ApiError::NotFound),
+    }
+}
+
+/// How long browsers and proxies may keep a permanent redirect. Bounded so that a link switched
+/// to permanent by mistake recovers eventually.
+const PERMANENT_REDIRECT_MAX_AGE: u32 = 24 * 60 * 60;
+
+fn redirect_response(status: RedirectStatus, location: &str) -> Response {
+    let cache_control = match status.is_permanent() {
+        true => format!("public, max-age={}", PERMANENT_REDIRECT_MAX_AGE),
+        // every visit has to reach us to be counted and to follow edits of the destination
+        false => "no-store".to_string(),
+    };
+    let status = StatusCode::from_u16(status.code()).unwrap_or(StatusCode::TEMPORARY_REDIRECT);
+    (status, [(header::LOCATION, location.to_string()), (header::CACHE_CONTROL, cache_control)]).into_response()
+}
+
+/// API clients asking for JSON get the usual error body instead of the HTML page.
+fn wants_json(headers: &HeaderMap) -> bool {
+    headers.get(header::ACCEPT)
+        .and_then(|h| h.to_str().ok())
+        .is_some_and(|accept| accept.contains("application/json"))
+}
+
+/// Browsers get a page saying the link is gone, API clients the usual error body.
+fn link_expired(headers: &HeaderMap) -> Result<Response, ApiError> {
+    match wants_json(headers) {
+        true => Err(ApiError::LinkExpired),
+        false => Ok(LinkExpiredPage.into_response()),
+    }
+}
+
+async fn redirect(State(db): State<Arc<dyn Storage>>, ConnectInfo(peer): ConnectInfo<SocketAddr>, headers: HeaderMap, extract::Path(short_url): extract::Path<String>) -> Result<Response, ApiError> {
+    let short_url = validation::code_rules().normalize(&short_url);
+    let target = match db.get_link_target(&short_url).await {
+        Ok(Some(target)) => target,
+        Ok(None) if wants_json(&headers) => return Err(ApiError::NotFound),
+        Ok(None) => return Ok(NotFoundPage.into_response()),
+        Err(_) => return Err(ApiError::InternalServerError),
+    };
+
+    if target.is_expired() {
+        return link_expired(&headers);
+    }
+
+    let click = ClickEvent {
+        short: short_url.clone(),
+        referrer: headers.get(header::REFERER).and_then(|h| h.to_str().ok()).map(str::to_string),
+        user_agent: headers.get(header::USER_AGENT).and_then(|h| h.to_str().ok()).map(str::to_string),
+        ip: Some(anonymize_ip(proxy::client_ip(&headers, peer, proxy::trusted_proxies()))),
+    };
+    match db.record_click(click).await {
+        Ok(true) => {},
+        Ok(false) => return link_expired(&headers),
+        // without a click limit a failure only costs analytics, it shouldn't break the redirect itself
+        Err(_) if target.max_clicks.is_none() => eprintln!("Failed to record click on {}", short_url),
+        Err(_) => return Err(ApiError::InternalServerError),
+    }
+
+    Ok(redirect_response(target.redirect_status, &target.long))
+}
+
+/// A full batch of the longest allowed urls has to fit, the default limit is only 2 MB.
+fn bulk_body_limit() -> usize {
+    let urls = &config().urls;
+    urls.max_batch_size.saturating_mul(urls.max_length + BULK_LINK_OVERHEAD)
 }
 
-pub fn url_shortener_router() -> Router<Arc<DbConn>> {
+pub fn url_shortener_router() -> Router<Arc<dyn Storage>> {
     Router::new()
         .route("/shorten-link", post(shorten_link))
+        .route("/links/bulk", post(bulk_shorten_links).layer(DefaultBodyLimit::max(bulk_body_limit())))
         .route("/link/{short_url}", get(redirect))
+        .route("/link/{short_url}/qr", get(link_qr_code))
+        .route("/links/{short}", patch(edit_link).delete(archive_link))
+        .route("/links/{short}/restore", post(restore_link))
+        .route("/links/{short}/workspace", post(move_link))
+        .route("/links/{short}/stats", get(link_stats))
+        .route("/cache/stats", get(link_cache_stats))
 }
\ No newline at end of file
@@ -1,19 +1,44 @@
 use std::sync::Arc;
 
-use axum::{extract::State, routing::get, Router};
+use axum::{extract::{self, State}, routing::get, Router};
+use serde::Deserialize;
 
-use crate::{db::DbConn, model::AuthenticatedUser, responses::{ApiError, OkResponse}};
+use crate::{storage::Storage, model::{AuthenticatedUser, Scope, WorkspaceRole}, responses::{ApiError, OkResponse}};
+use super::{require_workspace_role, user_id};
 
-async fn get_user_links(user: AuthenticatedUser, State(db): State<Arc<DbConn>>) -> Result<OkResponse<Vec<(String,String)>>, ApiError> {
-    let user_id = db.get_user_id(&user.0.sub).unwrap().unwrap();
-    match db.get_user_links(user_id) {
+#[derive(Deserialize)]
+struct LinksQuery {
+    /// Links of this workspace instead of the user's personal ones.
+    workspace: Option<u32>,
+}
+
+async fn links(db: &dyn Storage, user: &AuthenticatedUser, query: LinksQuery, archived: bool) -> Result<OkResponse<Vec<(String,String)>>, ApiError> {
+    user.require_scope(Scope::LinksRead)?;
+    let user_id = user_id(db, user).await?;
+    let links = match query.workspace {
+        Some(workspace_id) => {
+            require_workspace_role(db, workspace_id, user_id, WorkspaceRole::Viewer).await?;
+            db.get_workspace_links(workspace_id, archived).await
+        },
+        None => db.get_user_links(user_id, archived).await,
+    };
+    match links {
         Ok(links) => Ok(OkResponse::new(links)),
         Err(_) => Err(ApiError::InternalServerError),
     }
 }
 
+async fn get_user_links(user: AuthenticatedUser, State(db): State<Arc<dyn Storage>>, extract::Query(query): extract::Query<LinksQuery>) -> Result<OkResponse<Vec<(String,String)>>, ApiError> {
+    links(db.as_ref(), &user, query, false).await
+}
+
+async fn get_archived_links(user: AuthenticatedUser, State(db): State<Arc<dyn Storage>>, extract::Query(query): extract::Query<LinksQuery>) -> Result<OkResponse<Vec<(String,String)>>, ApiError> {
+    links(db.as_ref(), &user, query, true).await
+}
+
 
-pub fn user_router() -> Router<Arc<DbConn>> {
+pub fn user_router() -> Router<Arc<dyn Storage>> {
     Router::new()
         .route("/get-user-links", get(get_user_links))
+        .route("/links/archived", get(get_archived_links))
 }
\ No newline at end of file
@@ -0,0 +1,111 @@
+use std::sync::Arc;
+
+use axum::{extract::{self, State}, routing::{get, put}, Router};
+use serde::Deserialize;
+
+use crate::{storage::{Storage, WorkspaceInfo, WorkspaceMember}, model::{AuthenticatedUser, Scope, WorkspaceRole}, responses::{ApiError, OkResponse}};
+use super::{require_workspace_role, user_id};
+
+#[derive(Deserialize)]
+struct NewWorkspaceData {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct MemberData {
+    role: WorkspaceRole,
+}
+
+/// Refuses to demote or remove the only owner of the workspace, nobody could manage its members afterwards.
+async fn check_not_last_owner(db: &dyn Storage, workspace_id: u32, username: &str) -> Result<(), ApiError> {
+    let members = db.get_workspace_members(workspace_id).await.map_err(|_| ApiError::InternalServerError)?;
+    let owners: Vec<&WorkspaceMember> = members.iter().filter(|member| member.role == WorkspaceRole::Owner).collect();
+    match owners.as_slice() {
+        [owner] if owner.username == username => Err(ApiError::InvalidParameters),
+        _ => Ok(()),
+    }
+}
+
+async fn list_workspaces(State(db): State<Arc<dyn Storage>>, user: AuthenticatedUser) -> Result<OkResponse<Vec<WorkspaceInfo>>, ApiError> {
+    user.require_scope(Scope::LinksRead)?;
+    let user_id = user_id(db.as_ref(), &user).await?;
+    match db.get_workspaces(user_id).await {
+        Ok(workspaces) => Ok(OkResponse::new(workspaces)),
+        Err(_) => Err(ApiError::InternalServerError),
+    }
+}
+
+async fn create_workspace(State(db): State<Arc<dyn Storage>>, user: AuthenticatedUser, extract::Json(data): extract::Json<NewWorkspaceData>) -> Result<OkResponse<WorkspaceInfo>, ApiError> {
+    user.require_session()?;
+    let user_id = user_id(db.as_ref(), &user).await?;
+    if data.name.trim().is_empty() {
+        return Err(ApiError::InvalidParameters);
+    }
+
+    match db.create_workspace(data.name.trim(), user_id).await {
+        Ok(workspace) => Ok(OkResponse::new(workspace)),
+        Err(_) => Err(ApiError::InternalServerError),
+    }
+}
+
+async fn list_members(State(db): State<Arc<dyn Storage>>, user: AuthenticatedUser, extract::Path(workspace_id): extract::Path<u32>) -> Result<OkResponse<Vec<WorkspaceMember>>, ApiError> {
+    user.require_scope(Scope::LinksRead)?;
+    let user_id = user_id(db.as_ref(), &user).await?;
+    require_workspace_role(db.as_ref(), workspace_id, user_id, WorkspaceRole::Viewer).await?;
+
+    match db.get_workspace_members(workspace_id).await {
+        Ok(members) => Ok(OkResponse::new(members)),
+        Err(_) => Err(ApiError::InternalServerError),
+    }
+}
+
+async fn set_member(State(db): State<Arc<dyn Storage>>, user: AuthenticatedUser, extract::Path((workspace_id, username)): extract::Path<(u32, String)>, extract::Json(data): extract::Json<MemberData>) -> Result<OkResponse<WorkspaceMember>, ApiError> {
+    user.require_session()?;
+    let user_id = user_id(db.as_ref(), &user).await?;
+    require_workspace_role(db.as_ref(), workspace_id, user_id, WorkspaceRole::Owner).await?;
+
+    let member_id = match db.get_user_id(&username).await {
+        Ok(Some(id)) => id,
+        Ok(None) => return Err(ApiError::NotFound),
+        Err(_) => return Err(ApiError::InternalServerError),
+    };
+    if data.role != WorkspaceRole::Owner {
+        check_not_last_owner(db.as_ref(), workspace_id, &username).await?;
+    }
+
+    match db.set_workspace_member(workspace_id, member_id, data.role).await {
+        Ok(_) => Ok(OkResponse::new(WorkspaceMember { username, role: data.role })),
+        Err(_) => Err(ApiError::InternalServerError),
+    }
+}
+
+/// Owners remove members, everyone else may only remove themselves to leave the workspace.
+async fn remove_member(State(db): State<Arc<dyn Storage>>, user: AuthenticatedUser, extract::Path((workspace_id, username)): extract::Path<(u32, String)>) -> Result<OkResponse<String>, ApiError> {
+    user.require_session()?;
+    let user_id = user_id(db.as_ref(), &user).await?;
+    let required = match username == user.0.sub {
+        true => WorkspaceRole::Viewer,
+        false => WorkspaceRole::Owner,
+    };
+    require_workspace_role(db.as_ref(), workspace_id, user_id, required).await?;
+
+    let member_id = match db.get_user_id(&username).await {
+        Ok(Some(id)) => id,
+        Ok(None) => return Err(ApiError::NotFound),
+        Err(_) => return Err(ApiError::InternalServerError),
+    };
+    check_not_last_owner(db.as_ref(), workspace_id, &username).await?;
+
+    match db.remove_workspace_member(workspace_id, member_id).await {
+        Ok(true) => Ok(OkResponse::new(username)),
+        Ok(false) => Err(ApiError::NotFound),
+        Err(_) => Err(ApiError::InternalServerError),
+    }
+}
+
+pub fn workspace_router() -> Router<Arc<dyn Storage>> {
+    Router::new()
+        .route("/workspaces", get(list_workspaces).post(create_workspace))
+        .route("/workspaces/{id}/members", get(list_members))
+        .route("/workspaces/{id}/members/{username}", put(set_member).delete(remove_member))
+}
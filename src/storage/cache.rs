@@ -0,0 +1,330 @@
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use lru::LruCache;
+use serde::Serialize;
+
+use crate::config::CacheConfig;
+use crate::model::{RedirectStatus, Role, Scope, WorkspaceRole};
+use crate::storage::{ApiKeyInfo, ClickEvent, LinkFilter, LinkInfo, LinkOwner, LinkStats, NewLink, LinkTarget, RefreshTokenStatus, Storage, StorageResult, SystemStats, UserError, UserInfo, UserStatus, WorkspaceInfo, WorkspaceMember};
+
+/// Unknown codes are cached for a shorter time, another instance may create the link in the meantime.
+const NEGATIVE_TTL: Duration = Duration::from_secs(10);
+
+pub struct CacheSettings {
+    pub size: usize,
+    pub ttl: Duration,
+}
+
+impl CacheSettings {
+    pub fn from_config(cache: &CacheConfig) -> Self {
+        CacheSettings { size: cache.size, ttl: Duration::from_secs(cache.ttl) }
+    }
+}
+
+#[derive(Serialize)]
+pub struct CacheStats {
+    pub capacity: usize,
+    pub entries: usize,
+    pub hits: u64,
+    /// Hits on codes that are known not to exist.
+    pub negative_hits: u64,
+    pub misses: u64,
+    pub hit_rate: f64,
+}
+
+struct CachedLink {
+    target: Option<LinkTarget>,
+    valid_until: Instant,
+}
+
+/// Keeps recently redirected links in memory so hot links don't hit the database on every click.
+/// Entries are dropped when the link is edited, archived or runs out of clicks on this instance;
+/// changes made by other instances sharing the database show up once the entry's TTL passes.
+pub struct CachedStorage {
+    inner: Arc<dyn Storage>,
+    links: Mutex<LruCache<String, CachedLink>>,
+    ttl: Duration,
+    /// Bumped on every invalidation so that a lookup racing with a write doesn't cache the old value.
+    generation: AtomicU64,
+    hits: AtomicU64,
+    negative_hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl CachedStorage {
+    pub fn new(inner: Arc<dyn Storage>, size: NonZeroUsize, ttl: Duration) -> Self {
+        CachedStorage {
+            inner,
+            links: Mutex::new(LruCache::new(size)),
+            ttl,
+            generation: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            negative_hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    fn invalidate(&self, short: &str) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.links.lock().unwrap().pop(short);
+    }
+
+    /// Links with an expiration date are evicted when they expire, so the next lookup sees it.
+    fn valid_until(&self, target: &Option<LinkTarget>) -> Instant {
+        let now = Instant::now();
+        let Some(target) = target else {
+            return now + NEGATIVE_TTL.min(self.ttl);
+        };
+        let expires_in = target.expires_at
+            .map(|expires_at| expires_at - chrono::Utc::now().timestamp())
+            .filter(|seconds| *seconds > 0)
+            .map(|seconds| Duration::from_secs(seconds as u64));
+        match expires_in {
+            Some(expires_in) => now + expires_in.min(self.ttl),
+            None => now + self.ttl,
+        }
+    }
+}
+
+/// Wraps the storage in a link cache unless it's turned off.
+pub fn with_cache(storage: Arc<dyn Storage>, settings: &CacheSettings) -> Arc<dyn Storage> {
+    match NonZeroUsize::new(settings.size) {
+        Some(size) if !settings.ttl.is_zero() => Arc::new(CachedStorage::new(storage, size, settings.ttl)),
+        _ => storage,
+    }
+}
+
+#[async_trait]
+impl Storage for CachedStorage {
+    async fn init_db(&self) -> StorageResult<Vec<(u32, &'static str)>> {
+        self.inner.init_db().await
+    }
+
+    async fn create_user(&self, username: &str, password: &str) -> Result<String, UserError> {
+        self.inner.create_user(username, password).await
+    }
+
+    async fn login(&self, username: &str, password: &str) -> Result<String, UserError> {
+        self.inner.login(username, password).await
+    }
+
+    async fn get_user_id(&self, username: &str) -> StorageResult<Option<u32>> {
+        self.inner.get_user_id(username).await
+    }
+
+    async fn insert_url(&self, short: &str, long: &str, owner: LinkOwner, expires_at: Option<i64>, max_clicks: Option<u32>, redirect_status: RedirectStatus) -> StorageResult<bool> {
+        let inserted = self.inner.insert_url(short, long, owner, expires_at, max_clicks, redirect_status).await?;
+        if inserted {
+            // the code may be cached as unknown
+            self.invalidate(short);
+        }
+        Ok(inserted)
+    }
+
+    async fn insert_urls(&self, links: Vec<NewLink>) -> StorageResult<Vec<Option<String>>> {
+        let claimed = self.inner.insert_urls(links).await?;
+        // the codes may be cached as unknown
+        for short in claimed.iter().flatten() {
+            self.invalidate(short);
+        }
+        Ok(claimed)
+    }
+
+    async fn get_link_target(&self, short: &str) -> StorageResult<Option<LinkTarget>> {
+        {
+            let mut links = self.links.lock().unwrap();
+            match links.get(short) {
+                Some(entry) if entry.valid_until > Instant::now() => {
+                    match entry.target {
+                        Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+                        None => self.negative_hits.fetch_add(1, Ordering::Relaxed),
+                    };
+                    return Ok(entry.target.clone());
+                },
+                Some(_) => { links.pop(short); },
+                None => {},
+            }
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let generation = self.generation.load(Ordering::SeqCst);
+        let target = self.inner.get_link_target(short).await?;
+
+        let mut links = self.links.lock().unwrap();
+        if self.generation.load(Ordering::SeqCst) == generation {
+            let valid_until = self.valid_until(&target);
+            links.put(short.to_string(), CachedLink { target: target.clone(), valid_until });
+        }
+        Ok(target)
+    }
+
+    async fn next_url_id(&self) -> StorageResult<u64> {
+        self.inner.next_url_id().await
+    }
+
+    async fn url_exists(&self, short: &str) -> StorageResult<bool> {
+        self.inner.url_exists(short).await
+    }
+
+    async fn get_url_owner(&self, short: &str) -> StorageResult<Option<LinkOwner>> {
+        self.inner.get_url_owner(short).await
+    }
+
+    async fn update_url(&self, short: &str, long: &str) -> StorageResult<()> {
+        let result = self.inner.update_url(short, long).await;
+        self.invalidate(short);
+        result
+    }
+
+    async fn set_url_archived(&self, short: &str, archived: bool) -> StorageResult<()> {
+        let result = self.inner.set_url_archived(short, archived).await;
+        self.invalidate(short);
+        result
+    }
+
+    async fn get_user_links(&self, user_id: u32, archived: bool) -> StorageResult<Vec<(String, String)>> {
+        self.inner.get_user_links(user_id, archived).await
+    }
+
+    async fn get_workspace_links(&self, workspace_id: u32, archived: bool) -> StorageResult<Vec<(String, String)>> {
+        self.inner.get_workspace_links(workspace_id, archived).await
+    }
+
+    async fn set_url_workspace(&self, short: &str, workspace_id: u32) -> StorageResult<()> {
+        self.inner.set_url_workspace(short, workspace_id).await
+    }
+
+    async fn record_click(&self, click: ClickEvent) -> StorageResult<bool> {
+        let short = click.short.clone();
+        let counted = self.inner.record_click(click).await?;
+        if counted {
+            // keep the cached count roughly in line so that is_expired stays meaningful
+            if let Some(CachedLink { target: Some(target), .. }) = self.links.lock().unwrap().peek_mut(&short) {
+                target.clicks += 1;
+            }
+        } else {
+            self.invalidate(&short);
+        }
+        Ok(counted)
+    }
+
+    async fn get_link_stats(&self, short: &str) -> StorageResult<LinkStats> {
+        self.inner.get_link_stats(short).await
+    }
+
+    async fn store_refresh_token(&self, token_hash: &str, family_id: &str, user_id: u32, expires_at: i64) -> StorageResult<()> {
+        self.inner.store_refresh_token(token_hash, family_id, user_id, expires_at).await
+    }
+
+    async fn use_refresh_token(&self, token_hash: &str) -> StorageResult<RefreshTokenStatus> {
+        self.inner.use_refresh_token(token_hash).await
+    }
+
+    async fn revoke_refresh_token_family(&self, token_hash: &str) -> StorageResult<()> {
+        self.inner.revoke_refresh_token_family(token_hash).await
+    }
+
+    async fn create_workspace(&self, name: &str, owner_id: u32) -> StorageResult<WorkspaceInfo> {
+        self.inner.create_workspace(name, owner_id).await
+    }
+
+    async fn get_workspaces(&self, user_id: u32) -> StorageResult<Vec<WorkspaceInfo>> {
+        self.inner.get_workspaces(user_id).await
+    }
+
+    async fn get_workspace_role(&self, workspace_id: u32, user_id: u32) -> StorageResult<Option<WorkspaceRole>> {
+        self.inner.get_workspace_role(workspace_id, user_id).await
+    }
+
+    async fn get_workspace_members(&self, workspace_id: u32) -> StorageResult<Vec<WorkspaceMember>> {
+        self.inner.get_workspace_members(workspace_id).await
+    }
+
+    async fn set_workspace_member(&self, workspace_id: u32, user_id: u32, role: WorkspaceRole) -> StorageResult<()> {
+        self.inner.set_workspace_member(workspace_id, user_id, role).await
+    }
+
+    async fn remove_workspace_member(&self, workspace_id: u32, user_id: u32) -> StorageResult<bool> {
+        self.inner.remove_workspace_member(workspace_id, user_id).await
+    }
+
+    async fn create_api_key(&self, user_id: u32, name: &str, prefix: &str, key_hash: &str, scopes: &[Scope]) -> StorageResult<ApiKeyInfo> {
+        self.inner.create_api_key(user_id, name, prefix, key_hash, scopes).await
+    }
+
+    async fn get_api_keys(&self, user_id: u32) -> StorageResult<Vec<ApiKeyInfo>> {
+        self.inner.get_api_keys(user_id).await
+    }
+
+    async fn revoke_api_key(&self, user_id: u32, key_id: u32) -> StorageResult<bool> {
+        self.inner.revoke_api_key(user_id, key_id).await
+    }
+
+    async fn use_api_key(&self, key_hash: &str) -> StorageResult<Option<(String, Vec<Scope>)>> {
+        self.inner.use_api_key(key_hash).await
+    }
+
+    async fn set_user_disabled(&self, username: &str, disabled: bool) -> StorageResult<bool> {
+        self.inner.set_user_disabled(username, disabled).await
+    }
+
+    async fn reset_password(&self, username: &str, password: &str) -> StorageResult<bool> {
+        self.inner.reset_password(username, password).await
+    }
+
+    async fn set_user_role(&self, username: &str, role: Role) -> StorageResult<bool> {
+        self.inner.set_user_role(username, role).await
+    }
+
+    async fn get_user(&self, username: &str) -> StorageResult<Option<UserInfo>> {
+        self.inner.get_user(username).await
+    }
+
+    async fn get_user_status(&self, username: &str) -> StorageResult<Option<UserStatus>> {
+        self.inner.get_user_status(username).await
+    }
+
+    async fn list_users(&self) -> StorageResult<Vec<UserInfo>> {
+        self.inner.list_users().await
+    }
+
+    async fn find_links(&self, filter: &LinkFilter) -> StorageResult<Vec<LinkInfo>> {
+        self.inner.find_links(filter).await
+    }
+
+    async fn delete_url(&self, short: &str) -> StorageResult<bool> {
+        let result = self.inner.delete_url(short).await;
+        self.invalidate(short);
+        result
+    }
+
+    async fn set_url_taken_down(&self, short: &str, taken_down: bool) -> StorageResult<bool> {
+        let result = self.inner.set_url_taken_down(short, taken_down).await;
+        self.invalidate(short);
+        result
+    }
+
+    async fn system_stats(&self) -> StorageResult<SystemStats> {
+        self.inner.system_stats().await
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        let links = self.links.lock().unwrap();
+        let hits = self.hits.load(Ordering::Relaxed);
+        let negative_hits = self.negative_hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        let lookups = hits + negative_hits + misses;
+        Some(CacheStats {
+            capacity: links.cap().get(),
+            entries: links.len(),
+            hits,
+            negative_hits,
+            misses,
+            hit_rate: if lookups == 0 { 0.0 } else { (hits + negative_hits) as f64 / lookups as f64 },
+        })
+    }
+}
@@ -0,0 +1,551 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use crate::model::{RedirectStatus, Role, Scope, WorkspaceRole};
+use crate::storage::{hash_password, verify_password, ApiKeyInfo, ClickEvent, DailyClicks, LinkFilter, LinkInfo, LinkOwner, LinkStats, NewLink, LinkTarget, RefreshTokenStatus, Storage, StorageResult, SystemStats, UserError, UserInfo, UserStatus, WorkspaceInfo, WorkspaceMember};
+
+struct User {
+    id: u32,
+    username: String,
+    password: String,
+    role: Role,
+    disabled: bool,
+}
+
+struct Url {
+    user_id: u32,
+    workspace_id: Option<u32>,
+    short: String,
+    long: String,
+    archived: bool,
+    taken_down: bool,
+    expires_at: Option<i64>,
+    max_clicks: Option<u32>,
+    clicks: u32,
+    redirect_status: RedirectStatus,
+}
+
+struct Workspace {
+    id: u32,
+    name: String,
+    created_at: i64,
+}
+
+struct Member {
+    workspace_id: u32,
+    user_id: u32,
+    role: WorkspaceRole,
+}
+
+struct Click {
+    short: String,
+    clicked_at: i64,
+}
+
+struct RefreshToken {
+    family_id: String,
+    user_id: u32,
+    expires_at: i64,
+    used: bool,
+    revoked: bool,
+}
+
+struct ApiKey {
+    user_id: u32,
+    key_hash: String,
+    info: ApiKeyInfo,
+}
+
+#[derive(Default)]
+struct MemoryData {
+    users: Vec<User>,
+    urls: Vec<Url>,
+    clicks: Vec<Click>,
+    refresh_tokens: HashMap<String, RefreshToken>,
+    api_keys: Vec<ApiKey>,
+    workspaces: Vec<Workspace>,
+    members: Vec<Member>,
+    next_url_id: u64,
+}
+
+impl MemoryData {
+    fn user_info(&self, user: &User) -> UserInfo {
+        UserInfo {
+            id: user.id,
+            username: user.username.clone(),
+            role: user.role,
+            disabled: user.disabled,
+            links: self.urls.iter().filter(|url| url.user_id == user.id).count() as u32,
+        }
+    }
+}
+
+/// Storage that keeps everything in memory, for tests and throwaway instances.
+pub struct MemoryStorage {
+    data: Mutex<MemoryData>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        MemoryStorage {
+            data: Mutex::new(MemoryData { next_url_id: 1, ..Default::default() }),
+        }
+    }
+}
+
+impl Default for MemoryStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn init_db(&self) -> StorageResult<Vec<(u32, &'static str)>> {
+        Ok(Vec::new())
+    }
+
+    async fn create_user(&self, username: &str, password: &str) -> Result<String, UserError> {
+        let password = hash_password(password).await;
+        let mut data = self.data.lock().unwrap();
+        if data.users.iter().any(|user| user.username == username) {
+            return Err(UserError::UserAlreadyExists);
+        }
+
+        let id = data.users.len() as u32 + 1;
+        data.users.push(User { id, username: username.to_string(), password, role: Role::User, disabled: false });
+        Ok("User created".to_string())
+    }
+
+    async fn login(&self, username: &str, password: &str) -> Result<String, UserError> {
+        let (db_password, disabled) = {
+            let data = self.data.lock().unwrap();
+            match data.users.iter().find(|user| user.username == username) {
+                Some(user) => (user.password.clone(), user.disabled),
+                None => return Err(UserError::InvalidCredentials),
+            }
+        };
+
+        if !verify_password(password, &db_password).await {
+            Err(UserError::InvalidCredentials)
+        } else if disabled {
+            Err(UserError::AccountDisabled)
+        } else {
+            Ok("Login successful".to_string())
+        }
+    }
+
+    async fn get_user_id(&self, username: &str) -> StorageResult<Option<u32>> {
+        let data = self.data.lock().unwrap();
+        Ok(data.users.iter().find(|user| user.username == username).map(|user| user.id))
+    }
+
+    async fn insert_url(&self, short: &str, long: &str, owner: LinkOwner, expires_at: Option<i64>, max_clicks: Option<u32>, redirect_status: RedirectStatus) -> StorageResult<bool> {
+        let mut data = self.data.lock().unwrap();
+        if data.urls.iter().any(|url| url.short == short) {
+            return Ok(false);
+        }
+
+        data.next_url_id += 1;
+        data.urls.push(Url {
+            user_id: owner.user_id,
+            workspace_id: owner.workspace_id,
+            short: short.to_string(),
+            long: long.to_string(),
+            archived: false,
+            taken_down: false,
+            expires_at,
+            max_clicks,
+            clicks: 0,
+            redirect_status,
+        });
+        Ok(true)
+    }
+
+    async fn insert_urls(&self, links: Vec<NewLink>) -> StorageResult<Vec<Option<String>>> {
+        let mut data = self.data.lock().unwrap();
+        let mut claimed = Vec::with_capacity(links.len());
+        for link in links {
+            let Some(short) = link.codes.into_iter().find(|code| data.urls.iter().all(|url| url.short != *code)) else {
+                claimed.push(None);
+                continue;
+            };
+
+            data.next_url_id += 1;
+            data.urls.push(Url {
+                user_id: link.owner.user_id,
+                workspace_id: link.owner.workspace_id,
+                short: short.clone(),
+                long: link.long,
+                archived: false,
+                taken_down: false,
+                expires_at: link.expires_at,
+                max_clicks: link.max_clicks,
+                clicks: 0,
+                redirect_status: link.redirect_status,
+            });
+            claimed.push(Some(short));
+        }
+        Ok(claimed)
+    }
+
+    async fn get_link_target(&self, short: &str) -> StorageResult<Option<LinkTarget>> {
+        let data = self.data.lock().unwrap();
+        Ok(data.urls.iter()
+            .find(|url| url.short == short && !url.archived && !url.taken_down)
+            .map(|url| LinkTarget {
+                long: url.long.clone(),
+                expires_at: url.expires_at,
+                max_clicks: url.max_clicks,
+                clicks: url.clicks,
+                redirect_status: url.redirect_status,
+            }))
+    }
+
+    async fn next_url_id(&self) -> StorageResult<u64> {
+        Ok(self.data.lock().unwrap().next_url_id)
+    }
+
+    async fn url_exists(&self, short: &str) -> StorageResult<bool> {
+        let data = self.data.lock().unwrap();
+        Ok(data.urls.iter().any(|url| url.short == short))
+    }
+
+    async fn get_url_owner(&self, short: &str) -> StorageResult<Option<LinkOwner>> {
+        let data = self.data.lock().unwrap();
+        Ok(data.urls.iter()
+            .find(|url| url.short == short)
+            .map(|url| LinkOwner { user_id: url.user_id, workspace_id: url.workspace_id }))
+    }
+
+    async fn update_url(&self, short: &str, long: &str) -> StorageResult<()> {
+        let mut data = self.data.lock().unwrap();
+        if let Some(url) = data.urls.iter_mut().find(|url| url.short == short) {
+            url.long = long.to_string();
+        }
+        Ok(())
+    }
+
+    async fn set_url_archived(&self, short: &str, archived: bool) -> StorageResult<()> {
+        let mut data = self.data.lock().unwrap();
+        if let Some(url) = data.urls.iter_mut().find(|url| url.short == short) {
+            url.archived = archived;
+        }
+        Ok(())
+    }
+
+    async fn get_user_links(&self, user_id: u32, archived: bool) -> StorageResult<Vec<(String, String)>> {
+        let data = self.data.lock().unwrap();
+        Ok(data.urls.iter()
+            .filter(|url| url.user_id == user_id && url.workspace_id.is_none() && url.archived == archived)
+            .map(|url| (url.short.clone(), url.long.clone()))
+            .collect())
+    }
+
+    async fn get_workspace_links(&self, workspace_id: u32, archived: bool) -> StorageResult<Vec<(String, String)>> {
+        let data = self.data.lock().unwrap();
+        Ok(data.urls.iter()
+            .filter(|url| url.workspace_id == Some(workspace_id) && url.archived == archived)
+            .map(|url| (url.short.clone(), url.long.clone()))
+            .collect())
+    }
+
+    async fn set_url_workspace(&self, short: &str, workspace_id: u32) -> StorageResult<()> {
+        let mut data = self.data.lock().unwrap();
+        if let Some(url) = data.urls.iter_mut().find(|url| url.short == short) {
+            url.workspace_id = Some(workspace_id);
+        }
+        Ok(())
+    }
+
+    async fn record_click(&self, click: ClickEvent) -> StorageResult<bool> {
+        let mut data = self.data.lock().unwrap();
+        match data.urls.iter_mut().find(|url| url.short == click.short) {
+            Some(url) if url.max_clicks.is_none_or(|max_clicks| url.clicks < max_clicks) => url.clicks += 1,
+            _ => return Ok(false),
+        }
+        data.clicks.push(Click { short: click.short, clicked_at: chrono::Utc::now().timestamp() });
+        Ok(true)
+    }
+
+    async fn get_link_stats(&self, short: &str) -> StorageResult<LinkStats> {
+        let data = self.data.lock().unwrap();
+        let mut per_day: Vec<DailyClicks> = Vec::new();
+        for click in data.clicks.iter().filter(|click| click.short == short) {
+            let day = chrono::DateTime::from_timestamp(click.clicked_at, 0)
+                .unwrap_or_default()
+                .format("%Y-%m-%d")
+                .to_string();
+            match per_day.iter_mut().find(|daily| daily.day == day) {
+                Some(daily) => daily.clicks += 1,
+                None => per_day.push(DailyClicks { day, clicks: 1 }),
+            }
+        }
+        per_day.sort_by(|a, b| a.day.cmp(&b.day));
+
+        let total = per_day.iter().map(|day| day.clicks).sum();
+        Ok(LinkStats { total, per_day })
+    }
+
+    async fn store_refresh_token(&self, token_hash: &str, family_id: &str, user_id: u32, expires_at: i64) -> StorageResult<()> {
+        let mut data = self.data.lock().unwrap();
+        let now = chrono::Utc::now().timestamp();
+        data.refresh_tokens.retain(|_, token| token.expires_at >= now);
+        data.refresh_tokens.insert(token_hash.to_string(), RefreshToken {
+            family_id: family_id.to_string(),
+            user_id,
+            expires_at,
+            used: false,
+            revoked: false,
+        });
+        Ok(())
+    }
+
+    async fn use_refresh_token(&self, token_hash: &str) -> StorageResult<RefreshTokenStatus> {
+        let mut data = self.data.lock().unwrap();
+        let Some(token) = data.refresh_tokens.get_mut(token_hash) else {
+            return Ok(RefreshTokenStatus::Unknown);
+        };
+
+        if token.used || token.revoked {
+            let family_id = token.family_id.clone();
+            for token in data.refresh_tokens.values_mut().filter(|token| token.family_id == family_id) {
+                token.revoked = true;
+            }
+            return Ok(RefreshTokenStatus::Reused);
+        }
+        if token.expires_at < chrono::Utc::now().timestamp() {
+            return Ok(RefreshTokenStatus::Unknown);
+        }
+
+        token.used = true;
+        Ok(RefreshTokenStatus::Valid { family_id: token.family_id.clone(), user_id: token.user_id })
+    }
+
+    async fn revoke_refresh_token_family(&self, token_hash: &str) -> StorageResult<()> {
+        let mut data = self.data.lock().unwrap();
+        let Some(family_id) = data.refresh_tokens.get(token_hash).map(|token| token.family_id.clone()) else {
+            return Ok(());
+        };
+        for token in data.refresh_tokens.values_mut().filter(|token| token.family_id == family_id) {
+            token.revoked = true;
+        }
+        Ok(())
+    }
+
+    async fn create_workspace(&self, name: &str, owner_id: u32) -> StorageResult<WorkspaceInfo> {
+        let mut data = self.data.lock().unwrap();
+        let id = data.workspaces.len() as u32 + 1;
+        let created_at = chrono::Utc::now().timestamp();
+        data.workspaces.push(Workspace { id, name: name.to_string(), created_at });
+        data.members.push(Member { workspace_id: id, user_id: owner_id, role: WorkspaceRole::Owner });
+        Ok(WorkspaceInfo { id, name: name.to_string(), role: WorkspaceRole::Owner, created_at })
+    }
+
+    async fn get_workspaces(&self, user_id: u32) -> StorageResult<Vec<WorkspaceInfo>> {
+        let data = self.data.lock().unwrap();
+        Ok(data.members.iter()
+            .filter(|member| member.user_id == user_id)
+            .filter_map(|member| data.workspaces.iter().find(|workspace| workspace.id == member.workspace_id).map(|workspace| WorkspaceInfo {
+                id: workspace.id,
+                name: workspace.name.clone(),
+                role: member.role,
+                created_at: workspace.created_at,
+            }))
+            .collect())
+    }
+
+    async fn get_workspace_role(&self, workspace_id: u32, user_id: u32) -> StorageResult<Option<WorkspaceRole>> {
+        let data = self.data.lock().unwrap();
+        Ok(data.members.iter()
+            .find(|member| member.workspace_id == workspace_id && member.user_id == user_id)
+            .map(|member| member.role))
+    }
+
+    async fn get_workspace_members(&self, workspace_id: u32) -> StorageResult<Vec<WorkspaceMember>> {
+        let data = self.data.lock().unwrap();
+        let mut members: Vec<WorkspaceMember> = data.members.iter()
+            .filter(|member| member.workspace_id == workspace_id)
+            .filter_map(|member| data.users.iter().find(|user| user.id == member.user_id).map(|user| WorkspaceMember {
+                username: user.username.clone(),
+                role: member.role,
+            }))
+            .collect();
+        members.sort_by(|a, b| a.username.cmp(&b.username));
+        Ok(members)
+    }
+
+    async fn set_workspace_member(&self, workspace_id: u32, user_id: u32, role: WorkspaceRole) -> StorageResult<()> {
+        let mut data = self.data.lock().unwrap();
+        match data.members.iter_mut().find(|member| member.workspace_id == workspace_id && member.user_id == user_id) {
+            Some(member) => member.role = role,
+            None => data.members.push(Member { workspace_id, user_id, role }),
+        }
+        Ok(())
+    }
+
+    async fn remove_workspace_member(&self, workspace_id: u32, user_id: u32) -> StorageResult<bool> {
+        let mut data = self.data.lock().unwrap();
+        let members = data.members.len();
+        data.members.retain(|member| !(member.workspace_id == workspace_id && member.user_id == user_id));
+        Ok(data.members.len() < members)
+    }
+
+    async fn create_api_key(&self, user_id: u32, name: &str, prefix: &str, key_hash: &str, scopes: &[Scope]) -> StorageResult<ApiKeyInfo> {
+        let mut data = self.data.lock().unwrap();
+        let info = ApiKeyInfo {
+            id: data.api_keys.len() as u32 + 1,
+            name: name.to_string(),
+            prefix: prefix.to_string(),
+            scopes: scopes.to_vec(),
+            created_at: chrono::Utc::now().timestamp(),
+            last_used_at: None,
+            revoked: false,
+        };
+        data.api_keys.push(ApiKey { user_id, key_hash: key_hash.to_string(), info: info.clone() });
+        Ok(info)
+    }
+
+    async fn get_api_keys(&self, user_id: u32) -> StorageResult<Vec<ApiKeyInfo>> {
+        let data = self.data.lock().unwrap();
+        Ok(data.api_keys.iter().filter(|key| key.user_id == user_id).map(|key| key.info.clone()).collect())
+    }
+
+    async fn revoke_api_key(&self, user_id: u32, key_id: u32) -> StorageResult<bool> {
+        let mut data = self.data.lock().unwrap();
+        match data.api_keys.iter_mut().find(|key| key.info.id == key_id && key.user_id == user_id) {
+            Some(key) => {
+                key.info.revoked = true;
+                Ok(true)
+            },
+            None => Ok(false),
+        }
+    }
+
+    async fn use_api_key(&self, key_hash: &str) -> StorageResult<Option<(String, Vec<Scope>)>> {
+        let mut data = self.data.lock().unwrap();
+        let MemoryData { api_keys, users, .. } = &mut *data;
+        let Some(key) = api_keys.iter_mut().find(|key| key.key_hash == key_hash && !key.info.revoked) else {
+            return Ok(None);
+        };
+        let Some(user) = users.iter().find(|user| user.id == key.user_id && !user.disabled) else {
+            return Ok(None);
+        };
+
+        key.info.last_used_at = Some(chrono::Utc::now().timestamp());
+        Ok(Some((user.username.clone(), key.info.scopes.clone())))
+    }
+
+    async fn set_user_disabled(&self, username: &str, disabled: bool) -> StorageResult<bool> {
+        let mut data = self.data.lock().unwrap();
+        let Some(user) = data.users.iter_mut().find(|user| user.username == username) else {
+            return Ok(false);
+        };
+        user.disabled = disabled;
+        let user_id = user.id;
+        if disabled {
+            data.refresh_tokens.retain(|_, token| token.user_id != user_id);
+        }
+        Ok(true)
+    }
+
+    async fn reset_password(&self, username: &str, password: &str) -> StorageResult<bool> {
+        let password = hash_password(password).await;
+        let mut data = self.data.lock().unwrap();
+        let Some(user) = data.users.iter_mut().find(|user| user.username == username) else {
+            return Ok(false);
+        };
+        user.password = password;
+        let user_id = user.id;
+        data.refresh_tokens.retain(|_, token| token.user_id != user_id);
+        Ok(true)
+    }
+
+    async fn set_user_role(&self, username: &str, role: Role) -> StorageResult<bool> {
+        let mut data = self.data.lock().unwrap();
+        match data.users.iter_mut().find(|user| user.username == username) {
+            Some(user) => {
+                user.role = role;
+                Ok(true)
+            },
+            None => Ok(false),
+        }
+    }
+
+    async fn get_user(&self, username: &str) -> StorageResult<Option<UserInfo>> {
+        let data = self.data.lock().unwrap();
+        Ok(data.users.iter().find(|user| user.username == username).map(|user| data.user_info(user)))
+    }
+
+    async fn get_user_status(&self, username: &str) -> StorageResult<Option<UserStatus>> {
+        let data = self.data.lock().unwrap();
+        Ok(data.users.iter().find(|user| user.username == username)
+            .map(|user| UserStatus { id: user.id, role: user.role, disabled: user.disabled }))
+    }
+
+    async fn list_users(&self) -> StorageResult<Vec<UserInfo>> {
+        let data = self.data.lock().unwrap();
+        Ok(data.users.iter().map(|user| data.user_info(user)).collect())
+    }
+
+    async fn find_links(&self, filter: &LinkFilter) -> StorageResult<Vec<LinkInfo>> {
+        let data = self.data.lock().unwrap();
+        let search = filter.search.as_ref().map(|search| search.to_lowercase());
+        Ok(data.urls.iter().rev()
+            .map(|url| (url, data.users.iter().find(|user| user.id == url.user_id).map(|user| user.username.clone())))
+            .filter(|(_, owner)| filter.owner.is_none() || *owner == filter.owner)
+            .filter(|(url, _)| search.as_ref().is_none_or(|search| {
+                url.short.to_lowercase().contains(search) || url.long.to_lowercase().contains(search)
+            }))
+            .filter(|(url, _)| filter.archived.is_none_or(|archived| url.archived == archived))
+            .skip(filter.offset as usize)
+            .take(filter.limit as usize)
+            .map(|(url, owner)| LinkInfo {
+                short: url.short.clone(),
+                long: url.long.clone(),
+                owner,
+                archived: url.archived,
+                taken_down: url.taken_down,
+                clicks: url.clicks,
+                max_clicks: url.max_clicks,
+                expires_at: url.expires_at,
+                redirect_status: url.redirect_status.code(),
+            })
+            .collect())
+    }
+
+    async fn delete_url(&self, short: &str) -> StorageResult<bool> {
+        let mut data = self.data.lock().unwrap();
+        let links = data.urls.len();
+        data.urls.retain(|url| url.short != short);
+        data.clicks.retain(|click| click.short != short);
+        Ok(data.urls.len() < links)
+    }
+
+    async fn set_url_taken_down(&self, short: &str, taken_down: bool) -> StorageResult<bool> {
+        let mut data = self.data.lock().unwrap();
+        match data.urls.iter_mut().find(|url| url.short == short) {
+            Some(url) => {
+                url.taken_down = taken_down;
+                Ok(true)
+            },
+            None => Ok(false),
+        }
+    }
+
+    async fn system_stats(&self) -> StorageResult<SystemStats> {
+        let data = self.data.lock().unwrap();
+        let day_ago = chrono::Utc::now().timestamp() - 24 * 60 * 60;
+        Ok(SystemStats {
+            users: data.users.len() as u32,
+            disabled_users: data.users.iter().filter(|user| user.disabled).count() as u32,
+            links: data.urls.len() as u32,
+            archived_links: data.urls.iter().filter(|url| url.archived).count() as u32,
+            taken_down_links: data.urls.iter().filter(|url| url.taken_down).count() as u32,
+            clicks: data.clicks.len() as u32,
+            clicks_last_day: data.clicks.iter().filter(|click| click.clicked_at >= day_ago).count() as u32,
+        })
+    }
+}
@@ -0,0 +1,348 @@
+mod cache;
+mod memory;
+mod postgres;
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bcrypt::{hash, DEFAULT_COST};
+use serde::Serialize;
+
+use crate::config::config;
+use crate::db::DbConn;
+use crate::model::{RedirectStatus, Role, Scope, WorkspaceRole};
+
+pub use cache::CacheStats;
+use cache::CacheSettings;
+pub use memory::MemoryStorage;
+pub use postgres::PgStorage;
+
+#[derive(Debug)]
+pub enum StorageError {
+    Database(String),
+}
+
+pub type StorageResult<T> = Result<T, StorageError>;
+
+impl std::fmt::Display for StorageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StorageError::Database(e) => write!(f, "database error: {}", e),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(e: rusqlite::Error) -> Self {
+        StorageError::Database(e.to_string())
+    }
+}
+
+#[derive(Debug)]
+pub enum UserError {
+    UserAlreadyExists,
+    InvalidCredentials,
+    /// The password was right but an administrator disabled the account.
+    AccountDisabled,
+    // DatabaseError{err: rusqlite::Error},
+    DatabaseError,
+}
+
+impl From<StorageError> for UserError {
+    fn from(_: StorageError) -> Self {
+        UserError::DatabaseError
+    }
+}
+
+/// Destination of a short link together with the limits that can make it expire.
+#[derive(Clone)]
+pub struct LinkTarget {
+    pub long: String,
+    pub expires_at: Option<i64>,
+    pub max_clicks: Option<u32>,
+    pub clicks: u32,
+    pub redirect_status: RedirectStatus,
+}
+
+/// A link expires when its date has passed or it has used up its clicks.
+fn is_expired(expires_at: Option<i64>, max_clicks: Option<u32>, clicks: u32) -> bool {
+    let past_date = expires_at.is_some_and(|expires_at| expires_at <= chrono::Utc::now().timestamp());
+    let out_of_clicks = max_clicks.is_some_and(|max_clicks| clicks >= max_clicks);
+    past_date || out_of_clicks
+}
+
+impl LinkTarget {
+    pub fn is_expired(&self) -> bool {
+        is_expired(self.expires_at, self.max_clicks, self.clicks)
+    }
+}
+
+/// Who a link belongs to. Links in a workspace are managed by its members, the user is
+/// just who created them, so they outlive the creator's membership.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LinkOwner {
+    pub user_id: u32,
+    pub workspace_id: Option<u32>,
+}
+
+/// A link to insert with `insert_urls`.
+pub struct NewLink {
+    /// Codes to claim in order of preference, the first free one is used.
+    pub codes: Vec<String>,
+    pub long: String,
+    pub owner: LinkOwner,
+    pub expires_at: Option<i64>,
+    pub max_clicks: Option<u32>,
+    pub redirect_status: RedirectStatus,
+}
+
+/// A single hit on a short link, the ip is expected to already be anonymised.
+pub struct ClickEvent {
+    pub short: String,
+    pub referrer: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct DailyClicks {
+    pub day: String,
+    pub clicks: u32,
+}
+
+#[derive(Serialize)]
+pub struct LinkStats {
+    pub total: u32,
+    pub per_day: Vec<DailyClicks>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct ApiKeyInfo {
+    pub id: u32,
+    pub name: String,
+    /// First characters of the key so that users can tell their keys apart.
+    pub prefix: String,
+    pub scopes: Vec<Scope>,
+    pub created_at: i64,
+    pub last_used_at: Option<i64>,
+    pub revoked: bool,
+}
+
+#[derive(Serialize, Clone)]
+pub struct UserInfo {
+    pub id: u32,
+    pub username: String,
+    pub role: Role,
+    pub disabled: bool,
+    /// Number of links including archived ones.
+    pub links: u32,
+}
+
+/// What every authenticated request needs to know about its user, without counting their links.
+#[derive(Clone, Copy)]
+pub struct UserStatus {
+    pub id: u32,
+    pub role: Role,
+    pub disabled: bool,
+}
+
+/// Everything stored about a link, for administration.
+#[derive(Serialize, Clone)]
+pub struct LinkInfo {
+    pub short: String,
+    pub long: String,
+    pub owner: Option<String>,
+    pub archived: bool,
+    /// Taken down by an administrator, unlike archiving the owner can't undo it.
+    pub taken_down: bool,
+    pub clicks: u32,
+    pub max_clicks: Option<u32>,
+    pub expires_at: Option<i64>,
+    pub redirect_status: u16,
+}
+
+impl LinkInfo {
+    pub fn is_expired(&self) -> bool {
+        is_expired(self.expires_at, self.max_clicks, self.clicks)
+    }
+}
+
+/// A workspace as seen by one of its members.
+#[derive(Serialize, Clone)]
+pub struct WorkspaceInfo {
+    pub id: u32,
+    pub name: String,
+    /// Role of the member the workspace was looked up for.
+    pub role: WorkspaceRole,
+    pub created_at: i64,
+}
+
+#[derive(Serialize, Clone)]
+pub struct WorkspaceMember {
+    pub username: String,
+    pub role: WorkspaceRole,
+}
+
+/// Narrows down `find_links`, filters that are None match every link.
+pub struct LinkFilter {
+    /// Username of the owner.
+    pub owner: Option<String>,
+    /// Case insensitive part of the code or the destination.
+    pub search: Option<String>,
+    pub archived: Option<bool>,
+    pub limit: u32,
+    pub offset: u32,
+}
+
+#[derive(Serialize)]
+pub struct SystemStats {
+    pub users: u32,
+    pub disabled_users: u32,
+    pub links: u32,
+    pub archived_links: u32,
+    pub taken_down_links: u32,
+    pub clicks: u32,
+    pub clicks_last_day: u32,
+}
+
+/// LIKE pattern matching `search` anywhere, with the wildcards in it escaped by a backslash.
+pub fn like_pattern(search: &str) -> String {
+    let escaped = search.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+pub fn scopes_to_string(scopes: &[Scope]) -> String {
+    scopes.iter().map(|scope| scope.as_str()).collect::<Vec<_>>().join(" ")
+}
+
+pub fn scopes_from_string(scopes: &str) -> Vec<Scope> {
+    scopes.split_whitespace().filter_map(Scope::parse).collect()
+}
+
+pub enum RefreshTokenStatus {
+    Valid { family_id: String, user_id: u32 },
+    /// The token was already used or revoked, which means it leaked. Its whole family gets revoked.
+    Reused,
+    Unknown,
+}
+
+/// Bcrypt is slow on purpose, so hashing runs on the blocking thread pool instead of stalling the async runtime.
+pub async fn hash_password(password: &str) -> String {
+    let password = password.to_string();
+    tokio::task::spawn_blocking(move || hash(password, DEFAULT_COST))
+        .await
+        .ok()
+        .and_then(|hashed| hashed.ok())
+        .unwrap_or_else(|| panic!("Failed to hash password"))
+}
+
+pub async fn verify_password(password: &str, hashed: &str) -> bool {
+    let (password, hashed) = (password.to_string(), hashed.to_string());
+    tokio::task::spawn_blocking(move || bcrypt::verify(password, &hashed).unwrap_or(false))
+        .await
+        .unwrap_or(false)
+}
+
+/// Everything the handlers need to persist: users, links, clicks and credentials.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Brings the schema up to date, returns the version and description of every migration that had to be applied.
+    async fn init_db(&self) -> StorageResult<Vec<(u32, &'static str)>>;
+
+    async fn create_user(&self, username: &str, password: &str) -> Result<String, UserError>;
+    async fn login(&self, username: &str, password: &str) -> Result<String, UserError>;
+    async fn get_user_id(&self, username: &str) -> StorageResult<Option<u32>>;
+
+    /// Returns false when the short code is already taken and nothing was written.
+    /// Implementations must check and claim the code atomically, callers rely on it to detect conflicts.
+    async fn insert_url(&self, short: &str, long: &str, owner: LinkOwner, expires_at: Option<i64>, max_clicks: Option<u32>, redirect_status: RedirectStatus) -> StorageResult<bool>;
+    /// Inserts all links in one transaction, every link claims the first of its codes that is free.
+    /// Returns the claimed code of each link, None when all its codes were taken.
+    async fn insert_urls(&self, links: Vec<NewLink>) -> StorageResult<Vec<Option<String>>>;
+    /// Looks up the destination of an active (not archived or taken down) link.
+    async fn get_link_target(&self, short: &str) -> StorageResult<Option<LinkTarget>>;
+    /// Id the next inserted link is expected to get, used by sequential code generation.
+    async fn next_url_id(&self) -> StorageResult<u64>;
+    /// Checks whether a short code is taken, including codes of archived links.
+    async fn url_exists(&self, short: &str) -> StorageResult<bool>;
+    /// None also for links without an owner, which nobody may manage.
+    async fn get_url_owner(&self, short: &str) -> StorageResult<Option<LinkOwner>>;
+    async fn update_url(&self, short: &str, long: &str) -> StorageResult<()>;
+    /// Archived links stop redirecting but keep their short code reserved.
+    async fn set_url_archived(&self, short: &str, archived: bool) -> StorageResult<()>;
+    /// Personal links of the user, links they created in a workspace belong to the workspace.
+    async fn get_user_links(&self, user_id: u32, archived: bool) -> StorageResult<Vec<(String, String)>>;
+    async fn get_workspace_links(&self, workspace_id: u32, archived: bool) -> StorageResult<Vec<(String, String)>>;
+    /// Moves the link into the workspace, its edit rights go with it.
+    async fn set_url_workspace(&self, short: &str, workspace_id: u32) -> StorageResult<()>;
+
+    /// Counts a click on the link and stores it for the statistics, both in one write. Returns false,
+    /// and records nothing, when the link already ran out of clicks.
+    async fn record_click(&self, click: ClickEvent) -> StorageResult<bool>;
+    async fn get_link_stats(&self, short: &str) -> StorageResult<LinkStats>;
+
+    async fn store_refresh_token(&self, token_hash: &str, family_id: &str, user_id: u32, expires_at: i64) -> StorageResult<()>;
+    /// Marks the refresh token as used so that it can't be exchanged again.
+    async fn use_refresh_token(&self, token_hash: &str) -> StorageResult<RefreshTokenStatus>;
+    /// Revokes every token of the family the given token belongs to.
+    async fn revoke_refresh_token_family(&self, token_hash: &str) -> StorageResult<()>;
+
+    /// Creates the workspace with the user as its owner.
+    async fn create_workspace(&self, name: &str, owner_id: u32) -> StorageResult<WorkspaceInfo>;
+    /// Workspaces the user is a member of.
+    async fn get_workspaces(&self, user_id: u32) -> StorageResult<Vec<WorkspaceInfo>>;
+    /// None when the user isn't a member, or there is no such workspace.
+    async fn get_workspace_role(&self, workspace_id: u32, user_id: u32) -> StorageResult<Option<WorkspaceRole>>;
+    async fn get_workspace_members(&self, workspace_id: u32) -> StorageResult<Vec<WorkspaceMember>>;
+    /// Adds the user to the workspace, or changes their role when they already are a member.
+    async fn set_workspace_member(&self, workspace_id: u32, user_id: u32, role: WorkspaceRole) -> StorageResult<()>;
+    /// Returns false when the user wasn't a member.
+    async fn remove_workspace_member(&self, workspace_id: u32, user_id: u32) -> StorageResult<bool>;
+
+    async fn create_api_key(&self, user_id: u32, name: &str, prefix: &str, key_hash: &str, scopes: &[Scope]) -> StorageResult<ApiKeyInfo>;
+    async fn get_api_keys(&self, user_id: u32) -> StorageResult<Vec<ApiKeyInfo>>;
+    /// Returns false when the key doesn't exist or belongs to someone else.
+    async fn revoke_api_key(&self, user_id: u32, key_id: u32) -> StorageResult<bool>;
+    /// Looks up an active API key by its hash, records when it was used and returns the owner's username and the key's scopes.
+    async fn use_api_key(&self, key_hash: &str) -> StorageResult<Option<(String, Vec<Scope>)>>;
+
+    /// Disabled users can't log in, refresh their session or use their access tokens and API keys,
+    /// so disabling ends every session of the user right away. Returns false when there is no such user.
+    async fn set_user_disabled(&self, username: &str, disabled: bool) -> StorageResult<bool>;
+    /// Replaces the password and ends every session of the user. Returns false when there is no such user.
+    async fn reset_password(&self, username: &str, password: &str) -> StorageResult<bool>;
+    /// Returns false when there is no such user.
+    async fn set_user_role(&self, username: &str, role: Role) -> StorageResult<bool>;
+    async fn get_user(&self, username: &str) -> StorageResult<Option<UserInfo>>;
+    /// Id, role and disabled flag of the user, cheap enough to look up on every request.
+    async fn get_user_status(&self, username: &str) -> StorageResult<Option<UserStatus>>;
+    async fn list_users(&self) -> StorageResult<Vec<UserInfo>>;
+    /// Links of all users matching the filter, newest first.
+    async fn find_links(&self, filter: &LinkFilter) -> StorageResult<Vec<LinkInfo>>;
+    /// Removes the link together with its click history, its code can be claimed again.
+    /// Returns false when there was no such link.
+    async fn delete_url(&self, short: &str) -> StorageResult<bool>;
+    /// Taken down links stop redirecting, their owner can't restore them. Returns false when there is no such link.
+    async fn set_url_taken_down(&self, short: &str, taken_down: bool) -> StorageResult<bool>;
+    async fn system_stats(&self) -> StorageResult<SystemStats>;
+
+    /// Hit rate of the link cache, None when the storage isn't cached.
+    fn cache_stats(&self) -> Option<CacheStats> {
+        None
+    }
+}
+
+/// Opens the storage selected by URL_SHORTENER_DATABASE_URL: `memory` for an in-memory
+/// store that is lost on restart, a `postgres://` url for PostgreSQL, anything else is a path to a SQLite file.
+/// Link lookups of the storage go through the link cache.
+pub fn connect(database_url: &str) -> StorageResult<Arc<dyn Storage>> {
+    let storage: Arc<dyn Storage> = match database_url {
+        "memory" | "memory://" => Arc::new(MemoryStorage::new()),
+        url if url.starts_with("postgres://") || url.starts_with("postgresql://") => Arc::new(PgStorage::new(url)?),
+        path => {
+            let path = path.strip_prefix("sqlite://").unwrap_or(path);
+            Arc::new(DbConn::new(path)?)
+        }
+    };
+    Ok(cache::with_cache(storage, &CacheSettings::from_config(&config().cache)))
+}
@@ -0,0 +1,586 @@
+use async_trait::async_trait;
+use deadpool_postgres::{Config, Pool, Runtime};
+use tokio_postgres::NoTls;
+
+use crate::migrations::{self, Migration};
+use crate::model::{RedirectStatus, Role, Scope, WorkspaceRole};
+use crate::storage::{hash_password, like_pattern, scopes_from_string, scopes_to_string, verify_password, ApiKeyInfo, ClickEvent, DailyClicks, LinkFilter, LinkInfo, LinkOwner, LinkStats, NewLink, LinkTarget, RefreshTokenStatus, Storage, StorageError, StorageResult, SystemStats, UserError, UserInfo, UserStatus, WorkspaceInfo, WorkspaceMember};
+
+/// Arbitrary key for the advisory lock that keeps replicas starting at the same time from
+/// running the same migration twice.
+const MIGRATION_LOCK_ID: i64 = 0x75726c73;
+
+/// PostgreSQL storage, lets several instances of the shortener share one database.
+pub struct PgStorage {
+    pool: Pool,
+}
+
+impl PgStorage {
+    /// Connections are opened lazily, so a wrong url only shows up on the first query.
+    pub fn new(database_url: &str) -> StorageResult<Self> {
+        let config = Config {
+            url: Some(database_url.to_string()),
+            ..Default::default()
+        };
+        let pool = config
+            .create_pool(Some(Runtime::Tokio1), NoTls)
+            .map_err(|e| StorageError::Database(e.to_string()))?;
+        Ok(Self { pool })
+    }
+}
+
+impl From<tokio_postgres::Error> for StorageError {
+    fn from(e: tokio_postgres::Error) -> Self {
+        StorageError::Database(e.to_string())
+    }
+}
+
+impl From<deadpool_postgres::PoolError> for StorageError {
+    fn from(e: deadpool_postgres::PoolError) -> Self {
+        StorageError::Database(e.to_string())
+    }
+}
+
+/// Maps `id, username, role, disabled, link count` columns.
+fn user_info_from_row(row: &tokio_postgres::Row) -> UserInfo {
+    UserInfo {
+        id: row.get::<_, i32>(0) as u32,
+        username: row.get(1),
+        role: Role::parse(row.get(2)).unwrap_or_default(),
+        disabled: row.get(3),
+        links: row.get::<_, i64>(4) as u32,
+    }
+}
+
+#[async_trait]
+impl Storage for PgStorage {
+    async fn init_db(&self) -> StorageResult<Vec<(u32, &'static str)>> {
+        let mut client = self.pool.get().await?;
+        // concurrent CREATE TABLE IF NOT EXISTS can still fail on a fresh database, so it takes the lock too
+        let tx = client.transaction().await?;
+        tx.execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK_ID]).await?;
+        tx.batch_execute("CREATE TABLE IF NOT EXISTS schema_version (version INTEGER NOT NULL)").await?;
+        tx.commit().await?;
+
+        let mut applied: Vec<&'static Migration> = Vec::new();
+        for migration in migrations::MIGRATIONS {
+            let tx = client.transaction().await?;
+            tx.execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK_ID]).await?;
+            let version: i32 = tx.query_one("SELECT COALESCE(MAX(version), 0) FROM schema_version", &[]).await?.get(0);
+            if migration.version as i32 <= version {
+                continue;
+            }
+
+            tx.batch_execute(migration.postgres).await?;
+            tx.execute("DELETE FROM schema_version", &[]).await?;
+            tx.execute("INSERT INTO schema_version (version) VALUES ($1)", &[&(migration.version as i32)]).await?;
+            tx.commit().await?;
+            applied.push(migration);
+        }
+        Ok(applied.iter().map(|migration| (migration.version, migration.description)).collect())
+    }
+
+    async fn insert_url(&self, short: &str, long: &str, owner: LinkOwner, expires_at: Option<i64>, max_clicks: Option<u32>, redirect_status: RedirectStatus) -> StorageResult<bool> {
+        let client = self.pool.get().await?;
+        let affected_rows = client.execute(
+            "INSERT INTO urls (short, long, user_id, workspace_id, expires_at, max_clicks, redirect_status) VALUES ($1, $2, $3, $4, $5, $6, $7)
+             ON CONFLICT (short) DO NOTHING",
+            &[&short, &long, &(owner.user_id as i32), &owner.workspace_id.map(|id| id as i32), &expires_at, &max_clicks.map(|max| max as i32), &(redirect_status.code() as i32)],
+        ).await?;
+        Ok(affected_rows > 0)
+    }
+
+    async fn insert_urls(&self, links: Vec<NewLink>) -> StorageResult<Vec<Option<String>>> {
+        let mut client = self.pool.get().await?;
+        let tx = client.transaction().await?;
+        let stmt = tx.prepare(
+            "INSERT INTO urls (short, long, user_id, workspace_id, expires_at, max_clicks, redirect_status) VALUES ($1, $2, $3, $4, $5, $6, $7)
+             ON CONFLICT (short) DO NOTHING",
+        ).await?;
+
+        let mut claimed = Vec::with_capacity(links.len());
+        for link in &links {
+            let (user_id, workspace_id) = (link.owner.user_id as i32, link.owner.workspace_id.map(|id| id as i32));
+            let (max_clicks, redirect_status) = (link.max_clicks.map(|max| max as i32), link.redirect_status.code() as i32);
+            let mut code = None;
+            for short in &link.codes {
+                let affected_rows = tx.execute(&stmt, &[short, &link.long, &user_id, &workspace_id, &link.expires_at, &max_clicks, &redirect_status]).await?;
+                if affected_rows > 0 {
+                    code = Some(short.clone());
+                    break;
+                }
+            }
+            claimed.push(code);
+        }
+        tx.commit().await?;
+        Ok(claimed)
+    }
+
+    async fn get_link_target(&self, short: &str) -> StorageResult<Option<LinkTarget>> {
+        let client = self.pool.get().await?;
+        let row = client.query_opt(
+            "SELECT long, expires_at, max_clicks, clicks, redirect_status FROM urls WHERE short = $1 AND NOT archived AND NOT taken_down",
+            &[&short],
+        ).await?;
+
+        Ok(row.map(|row| LinkTarget {
+            long: row.get(0),
+            expires_at: row.get(1),
+            max_clicks: row.get::<_, Option<i32>>(2).map(|max| max as u32),
+            clicks: row.get::<_, i32>(3) as u32,
+            redirect_status: RedirectStatus::from_code(row.get::<_, i32>(4) as u16).unwrap_or_default(),
+        }))
+    }
+
+    async fn record_click(&self, click: ClickEvent) -> StorageResult<bool> {
+        let client = self.pool.get().await?;
+        // counting and recording are one statement, the update's row lock keeps concurrent hits from overshooting
+        let affected_rows = client.execute(
+            "WITH counted AS (
+                UPDATE urls SET clicks = clicks + 1
+                WHERE short = $1 AND (max_clicks IS NULL OR clicks < max_clicks)
+                RETURNING short
+             )
+             INSERT INTO clicks (short, clicked_at, referrer, user_agent, ip)
+             SELECT short, $2, $3, $4, $5 FROM counted",
+            &[&click.short, &chrono::Utc::now().timestamp(), &click.referrer, &click.user_agent, &click.ip],
+        ).await?;
+        Ok(affected_rows > 0)
+    }
+
+    async fn get_link_stats(&self, short: &str) -> StorageResult<LinkStats> {
+        let client = self.pool.get().await?;
+        let rows = client.query(
+            "SELECT to_char(to_timestamp(clicked_at) AT TIME ZONE 'UTC', 'YYYY-MM-DD') AS day, COUNT(*) FROM clicks
+             WHERE short = $1 GROUP BY day ORDER BY day",
+            &[&short],
+        ).await?;
+
+        let per_day: Vec<DailyClicks> = rows.iter()
+            .map(|row| DailyClicks { day: row.get(0), clicks: row.get::<_, i64>(1) as u32 })
+            .collect();
+        let total = per_day.iter().map(|day| day.clicks).sum();
+        Ok(LinkStats { total, per_day })
+    }
+
+    async fn next_url_id(&self) -> StorageResult<u64> {
+        let client = self.pool.get().await?;
+        let id: i64 = client.query_one("SELECT COALESCE(MAX(id), 0)::BIGINT + 1 FROM urls", &[]).await?.get(0);
+        Ok(id as u64)
+    }
+
+    async fn url_exists(&self, short: &str) -> StorageResult<bool> {
+        let client = self.pool.get().await?;
+        let row = client.query_one("SELECT EXISTS (SELECT 1 FROM urls WHERE short = $1)", &[&short]).await?;
+        Ok(row.get(0))
+    }
+
+    async fn get_url_owner(&self, short: &str) -> StorageResult<Option<LinkOwner>> {
+        let client = self.pool.get().await?;
+        let row = client.query_opt("SELECT user_id, workspace_id FROM urls WHERE short = $1", &[&short]).await?;
+        Ok(row.and_then(|row| row.get::<_, Option<i32>>(0).map(|user_id| LinkOwner {
+            user_id: user_id as u32,
+            workspace_id: row.get::<_, Option<i32>>(1).map(|id| id as u32),
+        })))
+    }
+
+    async fn update_url(&self, short: &str, long: &str) -> StorageResult<()> {
+        let client = self.pool.get().await?;
+        client.execute("UPDATE urls SET long = $1 WHERE short = $2", &[&long, &short]).await?;
+        Ok(())
+    }
+
+    async fn set_url_archived(&self, short: &str, archived: bool) -> StorageResult<()> {
+        let client = self.pool.get().await?;
+        client.execute("UPDATE urls SET archived = $1 WHERE short = $2", &[&archived, &short]).await?;
+        Ok(())
+    }
+
+    async fn create_user(&self, username: &str, password: &str) -> Result<String, UserError> {
+        // hash before taking a connection so it isn't held for the whole bcrypt round
+        let password = hash_password(password).await;
+        let client = self.pool.get().await.map_err(StorageError::from)?;
+
+        let affected_rows = client.execute(
+            "INSERT INTO users (username, password) VALUES ($1, $2)
+             ON CONFLICT (username) DO NOTHING",
+            &[&username, &password],
+        ).await.map_err(StorageError::from)?;
+
+        if affected_rows == 0 {
+            return Err(UserError::UserAlreadyExists);
+        }
+
+        Ok("User created".to_string())
+    }
+
+    async fn login(&self, username: &str, password: &str) -> Result<String, UserError> {
+        let client = self.pool.get().await.map_err(StorageError::from)?;
+        let row = client.query_opt("SELECT password, disabled FROM users WHERE username = $1", &[&username])
+            .await
+            .map_err(StorageError::from)?;
+        let Some(row) = row else {
+            return Err(UserError::InvalidCredentials);
+        };
+
+        if !verify_password(password, row.get(0)).await {
+            Err(UserError::InvalidCredentials)
+        } else if row.get::<_, bool>(1) {
+            Err(UserError::AccountDisabled)
+        } else {
+            Ok("Login successful".to_string())
+        }
+    }
+
+    async fn get_user_id(&self, username: &str) -> StorageResult<Option<u32>> {
+        let client = self.pool.get().await?;
+        let row = client.query_opt("SELECT id FROM users WHERE username = $1", &[&username]).await?;
+        Ok(row.map(|row| row.get::<_, i32>(0) as u32))
+    }
+
+    async fn get_user_links(&self, user_id: u32, archived: bool) -> StorageResult<Vec<(String, String)>> {
+        let client = self.pool.get().await?;
+        let rows = client.query(
+            "SELECT short, long FROM urls WHERE user_id = $1 AND workspace_id IS NULL AND archived = $2 ORDER BY id",
+            &[&(user_id as i32), &archived],
+        ).await?;
+        Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+    }
+
+    async fn get_workspace_links(&self, workspace_id: u32, archived: bool) -> StorageResult<Vec<(String, String)>> {
+        let client = self.pool.get().await?;
+        let rows = client.query(
+            "SELECT short, long FROM urls WHERE workspace_id = $1 AND archived = $2 ORDER BY id",
+            &[&(workspace_id as i32), &archived],
+        ).await?;
+        Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+    }
+
+    async fn set_url_workspace(&self, short: &str, workspace_id: u32) -> StorageResult<()> {
+        let client = self.pool.get().await?;
+        client.execute("UPDATE urls SET workspace_id = $1 WHERE short = $2", &[&(workspace_id as i32), &short]).await?;
+        Ok(())
+    }
+
+    async fn store_refresh_token(&self, token_hash: &str, family_id: &str, user_id: u32, expires_at: i64) -> StorageResult<()> {
+        let client = self.pool.get().await?;
+        // expired tokens are useless, so clean them up whenever a new one is issued
+        client.execute(
+            "DELETE FROM refresh_tokens WHERE expires_at < $1",
+            &[&chrono::Utc::now().timestamp()],
+        ).await?;
+        client.execute(
+            "INSERT INTO refresh_tokens (token_hash, family_id, user_id, expires_at) VALUES ($1, $2, $3, $4)",
+            &[&token_hash, &family_id, &(user_id as i32), &expires_at],
+        ).await?;
+        Ok(())
+    }
+
+    async fn use_refresh_token(&self, token_hash: &str) -> StorageResult<RefreshTokenStatus> {
+        let mut client = self.pool.get().await?;
+        // the row stays locked until commit, so two replicas can't both exchange the same token
+        let tx = client.transaction().await?;
+        let row = tx.query_opt(
+            "SELECT family_id, user_id, expires_at, used, revoked FROM refresh_tokens WHERE token_hash = $1 FOR UPDATE",
+            &[&token_hash],
+        ).await?;
+        let Some(row) = row else {
+            return Ok(RefreshTokenStatus::Unknown);
+        };
+
+        let family_id: String = row.get(0);
+        let user_id = row.get::<_, i32>(1) as u32;
+        let expires_at: i64 = row.get(2);
+        let used: bool = row.get(3);
+        let revoked: bool = row.get(4);
+
+        if used || revoked {
+            tx.execute("UPDATE refresh_tokens SET revoked = TRUE WHERE family_id = $1", &[&family_id]).await?;
+            tx.commit().await?;
+            return Ok(RefreshTokenStatus::Reused);
+        }
+        if expires_at < chrono::Utc::now().timestamp() {
+            return Ok(RefreshTokenStatus::Unknown);
+        }
+
+        tx.execute("UPDATE refresh_tokens SET used = TRUE WHERE token_hash = $1", &[&token_hash]).await?;
+        tx.commit().await?;
+        Ok(RefreshTokenStatus::Valid { family_id, user_id })
+    }
+
+    async fn revoke_refresh_token_family(&self, token_hash: &str) -> StorageResult<()> {
+        let client = self.pool.get().await?;
+        client.execute(
+            "UPDATE refresh_tokens SET revoked = TRUE
+             WHERE family_id = (SELECT family_id FROM refresh_tokens WHERE token_hash = $1)",
+            &[&token_hash],
+        ).await?;
+        Ok(())
+    }
+
+    async fn create_workspace(&self, name: &str, owner_id: u32) -> StorageResult<WorkspaceInfo> {
+        let mut client = self.pool.get().await?;
+        let created_at = chrono::Utc::now().timestamp();
+        let tx = client.transaction().await?;
+        let row = tx.query_one(
+            "INSERT INTO workspaces (name, created_at) VALUES ($1, $2) RETURNING id",
+            &[&name, &created_at],
+        ).await?;
+        let id: i32 = row.get(0);
+        tx.execute(
+            "INSERT INTO workspace_members (workspace_id, user_id, role) VALUES ($1, $2, $3)",
+            &[&id, &(owner_id as i32), &WorkspaceRole::Owner.as_str()],
+        ).await?;
+        tx.commit().await?;
+
+        Ok(WorkspaceInfo { id: id as u32, name: name.to_string(), role: WorkspaceRole::Owner, created_at })
+    }
+
+    async fn get_workspaces(&self, user_id: u32) -> StorageResult<Vec<WorkspaceInfo>> {
+        let client = self.pool.get().await?;
+        let rows = client.query(
+            "SELECT workspaces.id, workspaces.name, workspace_members.role, workspaces.created_at FROM workspaces
+             JOIN workspace_members ON workspace_members.workspace_id = workspaces.id
+             WHERE workspace_members.user_id = $1 ORDER BY workspaces.id",
+            &[&(user_id as i32)],
+        ).await?;
+        Ok(rows.iter().map(|row| WorkspaceInfo {
+            id: row.get::<_, i32>(0) as u32,
+            name: row.get(1),
+            role: WorkspaceRole::parse(row.get(2)).unwrap_or(WorkspaceRole::Viewer),
+            created_at: row.get(3),
+        }).collect())
+    }
+
+    async fn get_workspace_role(&self, workspace_id: u32, user_id: u32) -> StorageResult<Option<WorkspaceRole>> {
+        let client = self.pool.get().await?;
+        let row = client.query_opt(
+            "SELECT role FROM workspace_members WHERE workspace_id = $1 AND user_id = $2",
+            &[&(workspace_id as i32), &(user_id as i32)],
+        ).await?;
+        Ok(row.and_then(|row| WorkspaceRole::parse(row.get(0))))
+    }
+
+    async fn get_workspace_members(&self, workspace_id: u32) -> StorageResult<Vec<WorkspaceMember>> {
+        let client = self.pool.get().await?;
+        let rows = client.query(
+            "SELECT users.username, workspace_members.role FROM workspace_members
+             JOIN users ON users.id = workspace_members.user_id
+             WHERE workspace_members.workspace_id = $1 ORDER BY users.username",
+            &[&(workspace_id as i32)],
+        ).await?;
+        Ok(rows.iter().map(|row| WorkspaceMember {
+            username: row.get(0),
+            role: WorkspaceRole::parse(row.get(1)).unwrap_or(WorkspaceRole::Viewer),
+        }).collect())
+    }
+
+    async fn set_workspace_member(&self, workspace_id: u32, user_id: u32, role: WorkspaceRole) -> StorageResult<()> {
+        let client = self.pool.get().await?;
+        client.execute(
+            "INSERT INTO workspace_members (workspace_id, user_id, role) VALUES ($1, $2, $3)
+             ON CONFLICT (workspace_id, user_id) DO UPDATE SET role = excluded.role",
+            &[&(workspace_id as i32), &(user_id as i32), &role.as_str()],
+        ).await?;
+        Ok(())
+    }
+
+    async fn remove_workspace_member(&self, workspace_id: u32, user_id: u32) -> StorageResult<bool> {
+        let client = self.pool.get().await?;
+        let affected_rows = client.execute(
+            "DELETE FROM workspace_members WHERE workspace_id = $1 AND user_id = $2",
+            &[&(workspace_id as i32), &(user_id as i32)],
+        ).await?;
+        Ok(affected_rows > 0)
+    }
+
+    async fn create_api_key(&self, user_id: u32, name: &str, prefix: &str, key_hash: &str, scopes: &[Scope]) -> StorageResult<ApiKeyInfo> {
+        let client = self.pool.get().await?;
+        let created_at = chrono::Utc::now().timestamp();
+        let row = client.query_one(
+            "INSERT INTO api_keys (user_id, name, prefix, key_hash, scopes, created_at) VALUES ($1, $2, $3, $4, $5, $6)
+             RETURNING id",
+            &[&(user_id as i32), &name, &prefix, &key_hash, &scopes_to_string(scopes), &created_at],
+        ).await?;
+
+        Ok(ApiKeyInfo {
+            id: row.get::<_, i32>(0) as u32,
+            name: name.to_string(),
+            prefix: prefix.to_string(),
+            scopes: scopes.to_vec(),
+            created_at,
+            last_used_at: None,
+            revoked: false,
+        })
+    }
+
+    async fn get_api_keys(&self, user_id: u32) -> StorageResult<Vec<ApiKeyInfo>> {
+        let client = self.pool.get().await?;
+        let rows = client.query(
+            "SELECT id, name, prefix, scopes, created_at, last_used_at, revoked FROM api_keys WHERE user_id = $1 ORDER BY id",
+            &[&(user_id as i32)],
+        ).await?;
+
+        Ok(rows.iter().map(|row| ApiKeyInfo {
+            id: row.get::<_, i32>(0) as u32,
+            name: row.get(1),
+            prefix: row.get(2),
+            scopes: scopes_from_string(row.get(3)),
+            created_at: row.get(4),
+            last_used_at: row.get(5),
+            revoked: row.get(6),
+        }).collect())
+    }
+
+    async fn revoke_api_key(&self, user_id: u32, key_id: u32) -> StorageResult<bool> {
+        let client = self.pool.get().await?;
+        let affected_rows = client.execute(
+            "UPDATE api_keys SET revoked = TRUE WHERE id = $1 AND user_id = $2",
+            &[&(key_id as i32), &(user_id as i32)],
+        ).await?;
+        Ok(affected_rows > 0)
+    }
+
+    async fn use_api_key(&self, key_hash: &str) -> StorageResult<Option<(String, Vec<Scope>)>> {
+        let client = self.pool.get().await?;
+        let row = client.query_opt(
+            "UPDATE api_keys SET last_used_at = $1
+             FROM users
+             WHERE users.id = api_keys.user_id AND api_keys.key_hash = $2 AND NOT api_keys.revoked AND NOT users.disabled
+             RETURNING users.username, api_keys.scopes",
+            &[&chrono::Utc::now().timestamp(), &key_hash],
+        ).await?;
+        Ok(row.map(|row| (row.get(0), scopes_from_string(row.get(1)))))
+    }
+
+    async fn set_user_disabled(&self, username: &str, disabled: bool) -> StorageResult<bool> {
+        let mut client = self.pool.get().await?;
+        let tx = client.transaction().await?;
+        let affected_rows = tx.execute("UPDATE users SET disabled = $1 WHERE username = $2", &[&disabled, &username]).await?;
+        if disabled {
+            tx.execute(
+                "DELETE FROM refresh_tokens WHERE user_id = (SELECT id FROM users WHERE username = $1)",
+                &[&username],
+            ).await?;
+        }
+        tx.commit().await?;
+        Ok(affected_rows > 0)
+    }
+
+    async fn reset_password(&self, username: &str, password: &str) -> StorageResult<bool> {
+        let password = hash_password(password).await;
+        let mut client = self.pool.get().await?;
+        let tx = client.transaction().await?;
+        let affected_rows = tx.execute("UPDATE users SET password = $1 WHERE username = $2", &[&password, &username]).await?;
+        tx.execute(
+            "DELETE FROM refresh_tokens WHERE user_id = (SELECT id FROM users WHERE username = $1)",
+            &[&username],
+        ).await?;
+        tx.commit().await?;
+        Ok(affected_rows > 0)
+    }
+
+    async fn set_user_role(&self, username: &str, role: Role) -> StorageResult<bool> {
+        let client = self.pool.get().await?;
+        let affected_rows = client.execute("UPDATE users SET role = $1 WHERE username = $2", &[&role.as_str(), &username]).await?;
+        Ok(affected_rows > 0)
+    }
+
+    async fn get_user(&self, username: &str) -> StorageResult<Option<UserInfo>> {
+        let client = self.pool.get().await?;
+        let row = client.query_opt(
+            "SELECT users.id, users.username, users.role, users.disabled, COUNT(urls.id) FROM users
+             LEFT JOIN urls ON urls.user_id = users.id
+             WHERE users.username = $1
+             GROUP BY users.id",
+            &[&username],
+        ).await?;
+        Ok(row.as_ref().map(user_info_from_row))
+    }
+
+    async fn get_user_status(&self, username: &str) -> StorageResult<Option<UserStatus>> {
+        let client = self.pool.get().await?;
+        let row = client.query_opt("SELECT id, role, disabled FROM users WHERE username = $1", &[&username]).await?;
+        Ok(row.map(|row| UserStatus {
+            id: row.get::<_, i32>(0) as u32,
+            role: Role::parse(row.get(1)).unwrap_or_default(),
+            disabled: row.get(2),
+        }))
+    }
+
+    async fn list_users(&self) -> StorageResult<Vec<UserInfo>> {
+        let client = self.pool.get().await?;
+        let rows = client.query(
+            "SELECT users.id, users.username, users.role, users.disabled, COUNT(urls.id) FROM users
+             LEFT JOIN urls ON urls.user_id = users.id
+             GROUP BY users.id ORDER BY users.id",
+            &[],
+        ).await?;
+        Ok(rows.iter().map(user_info_from_row).collect())
+    }
+
+    async fn find_links(&self, filter: &LinkFilter) -> StorageResult<Vec<LinkInfo>> {
+        let client = self.pool.get().await?;
+        let search = filter.search.as_deref().map(like_pattern);
+        let rows = client.query(
+            "SELECT urls.short, urls.long, users.username, urls.archived, urls.taken_down, urls.clicks, urls.max_clicks, urls.expires_at, urls.redirect_status
+             FROM urls LEFT JOIN users ON users.id = urls.user_id
+             WHERE ($1::TEXT IS NULL OR users.username = $1)
+               AND ($2::TEXT IS NULL OR urls.short ILIKE $2 OR urls.long ILIKE $2)
+               AND ($3::BOOLEAN IS NULL OR urls.archived = $3)
+             ORDER BY urls.id DESC LIMIT $4 OFFSET $5",
+            &[&filter.owner, &search, &filter.archived, &(filter.limit as i64), &(filter.offset as i64)],
+        ).await?;
+        Ok(rows.iter().map(|row| LinkInfo {
+            short: row.get(0),
+            long: row.get(1),
+            owner: row.get(2),
+            archived: row.get(3),
+            taken_down: row.get(4),
+            clicks: row.get::<_, i32>(5) as u32,
+            max_clicks: row.get::<_, Option<i32>>(6).map(|max| max as u32),
+            expires_at: row.get(7),
+            redirect_status: row.get::<_, i32>(8) as u16,
+        }).collect())
+    }
+
+    async fn delete_url(&self, short: &str) -> StorageResult<bool> {
+        let mut client = self.pool.get().await?;
+        let tx = client.transaction().await?;
+        tx.execute("DELETE FROM clicks WHERE short = $1", &[&short]).await?;
+        let affected_rows = tx.execute("DELETE FROM urls WHERE short = $1", &[&short]).await?;
+        tx.commit().await?;
+        Ok(affected_rows > 0)
+    }
+
+    async fn set_url_taken_down(&self, short: &str, taken_down: bool) -> StorageResult<bool> {
+        let client = self.pool.get().await?;
+        let affected_rows = client.execute("UPDATE urls SET taken_down = $1 WHERE short = $2", &[&taken_down, &short]).await?;
+        Ok(affected_rows > 0)
+    }
+
+    async fn system_stats(&self) -> StorageResult<SystemStats> {
+        let client = self.pool.get().await?;
+        let day_ago = chrono::Utc::now().timestamp() - 24 * 60 * 60;
+        let row = client.query_one(
+            "SELECT
+                (SELECT COUNT(*) FROM users),
+                (SELECT COUNT(*) FROM users WHERE disabled),
+                (SELECT COUNT(*) FROM urls),
+                (SELECT COUNT(*) FROM urls WHERE archived),
+                (SELECT COUNT(*) FROM urls WHERE taken_down),
+                (SELECT COUNT(*) FROM clicks),
+                (SELECT COUNT(*) FROM clicks WHERE clicked_at >= $1)",
+            &[&day_ago],
+        ).await?;
+        Ok(SystemStats {
+            users: row.get::<_, i64>(0) as u32,
+            disabled_users: row.get::<_, i64>(1) as u32,
+            links: row.get::<_, i64>(2) as u32,
+            archived_links: row.get::<_, i64>(3) as u32,
+            taken_down_links: row.get::<_, i64>(4) as u32,
+            clicks: row.get::<_, i64>(5) as u32,
+            clicks_last_day: row.get::<_, i64>(6) as u32,
+        })
+    }
+}
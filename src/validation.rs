@@ -0,0 +1,155 @@
+use std::sync::OnceLock;
+
+use serde::Serialize;
+use url::Url;
+
+use crate::config::{config, CodesConfig, UrlsConfig};
+
+/// Describes which field of the request was rejected and why. `code` is stable so that
+/// clients can map it to their own messages, `message` is meant for humans.
+#[derive(Serialize, Clone, Debug)]
+pub struct ValidationError {
+    pub field: &'static str,
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl ValidationError {
+    pub fn new(field: &'static str, code: &'static str, message: impl Into<String>) -> Self {
+        ValidationError { field, code, message: message.into() }
+    }
+}
+
+/// Rules for destination URLs.
+pub struct UrlRules {
+    pub allowed_schemes: Vec<String>,
+    pub max_length: usize,
+}
+
+impl UrlRules {
+    fn from_config(urls: &UrlsConfig) -> Self {
+        UrlRules {
+            allowed_schemes: urls.allowed_schemes.iter().map(|scheme| scheme.to_ascii_lowercase()).collect(),
+            max_length: urls.max_length,
+        }
+    }
+}
+
+static URL_RULES: OnceLock<UrlRules> = OnceLock::new();
+
+pub fn url_rules() -> &'static UrlRules {
+    URL_RULES.get_or_init(|| UrlRules::from_config(&config().urls))
+}
+
+/// Codes that would collide with our own routes or look official, always reserved.
+const RESERVED_CODES: &[&str] = &[
+    "admin", "api", "api-keys", "archived", "assets", "bulk", "cache", "create_user", "get-user-links",
+    "link", "links", "login", "logout", "refresh", "shorten-link", "static", "test", "whoami",
+];
+
+/// Rules for short codes chosen by users.
+pub struct CodeRules {
+    pub alphabet: String,
+    pub min_length: usize,
+    pub max_length: usize,
+    /// When false codes are stored and looked up in lowercase, so `Promo` and `promo` are the same link.
+    pub case_sensitive: bool,
+    pub reserved: Vec<String>,
+}
+
+impl CodeRules {
+    /// Configured reserved codes are added to the built-in list.
+    fn from_config(codes: &CodesConfig) -> Self {
+        let mut reserved: Vec<String> = RESERVED_CODES.iter().map(|code| code.to_string()).collect();
+        reserved.extend(codes.reserved.iter().map(|code| code.to_lowercase()));
+
+        CodeRules {
+            alphabet: codes.alphabet.clone(),
+            min_length: codes.min_length,
+            max_length: codes.max_length,
+            case_sensitive: codes.case_sensitive,
+            reserved,
+        }
+    }
+
+    /// Reserved words are matched regardless of case, `Admin` is as misleading as `admin`.
+    pub fn is_reserved(&self, code: &str) -> bool {
+        let code = code.to_lowercase();
+        self.reserved.contains(&code)
+    }
+
+    /// Form in which the code is stored and looked up.
+    pub fn normalize(&self, code: &str) -> String {
+        match self.case_sensitive {
+            true => code.to_string(),
+            false => code.to_lowercase(),
+        }
+    }
+}
+
+static CODE_RULES: OnceLock<CodeRules> = OnceLock::new();
+
+pub fn code_rules() -> &'static CodeRules {
+    CODE_RULES.get_or_init(|| CodeRules::from_config(&config().codes))
+}
+
+/// Checks a code picked by the user against the code rules and returns it normalised.
+pub fn validate_code(code: &str) -> Result<String, ValidationError> {
+    let rules = code_rules();
+    let length = code.chars().count();
+    if length < rules.min_length {
+        return Err(ValidationError::new("code", "too_short", format!("Code must be at least {} characters long", rules.min_length)));
+    }
+    if length > rules.max_length {
+        return Err(ValidationError::new("code", "too_long", format!("Code must be at most {} characters long", rules.max_length)));
+    }
+    if let Some(invalid) = code.chars().find(|c| !rules.alphabet.contains(*c)) {
+        return Err(ValidationError::new(
+            "code",
+            "invalid_characters",
+            format!("Character {:?} is not allowed, codes may only use: {}", invalid, rules.alphabet),
+        ));
+    }
+    if rules.is_reserved(code) {
+        return Err(ValidationError::new("code", "reserved", format!("Code {} is reserved", code)));
+    }
+    Ok(rules.normalize(code))
+}
+
+/// Validates a destination URL and returns it in normalised form: lowercase scheme and host,
+/// international domains in punycode, default ports dropped and unsafe characters percent-encoded.
+pub fn normalize_url(input: &str) -> Result<String, ValidationError> {
+    let rules = url_rules();
+    let input = input.trim();
+    if input.is_empty() {
+        return Err(ValidationError::new("url", "empty", "URL is required"));
+    }
+
+    let url = Url::parse(input)
+        .map_err(|e| ValidationError::new("url", "invalid_url", format!("Not a valid absolute URL: {}", e)))?;
+
+    if !rules.allowed_schemes.iter().any(|scheme| scheme == url.scheme()) {
+        return Err(ValidationError::new(
+            "url",
+            "unsupported_scheme",
+            format!("Scheme {} is not allowed, use one of: {}", url.scheme(), rules.allowed_schemes.join(", ")),
+        ));
+    }
+    if url.host_str().is_none_or(str::is_empty) {
+        return Err(ValidationError::new("url", "missing_host", "URL must contain a host"));
+    }
+    // https://trusted.com@evil.com style links are a phishing classic
+    if !url.username().is_empty() || url.password().is_some() {
+        return Err(ValidationError::new("url", "credentials_not_allowed", "URL must not contain a username or password"));
+    }
+
+    let normalized = url.to_string();
+    if normalized.len() > rules.max_length {
+        return Err(ValidationError::new(
+            "url",
+            "too_long",
+            format!("URL is longer than {} characters", rules.max_length),
+        ));
+    }
+    Ok(normalized)
+}
@@ -0,0 +1,37 @@
+//! Maintenance commands of the binary.
+
+mod common;
+
+use common::storage;
+use url_shortener::admin::{self, AdminCommand, LinkCommand};
+use url_shortener::model::RedirectStatus;
+use url_shortener::storage::{LinkOwner, Storage};
+
+async fn insert_link(db: &dyn Storage, user_id: u32, short: &str) -> bool {
+    let owner = LinkOwner { user_id, workspace_id: None };
+    db.insert_url(short, "https://example.com/", owner, None, None, RedirectStatus::default()).await.unwrap()
+}
+
+fn delete(code: &str, release_code: bool) -> AdminCommand {
+    AdminCommand::Link(LinkCommand::Delete { code: code.to_string(), release_code })
+}
+
+#[tokio::test]
+async fn link_delete_keeps_the_code_unless_released() {
+    let db = storage("memory").await;
+    db.create_user("owner", "secret-password").await.unwrap();
+    let user_id = db.get_user_id("owner").await.unwrap().unwrap();
+    assert!(insert_link(db.as_ref(), user_id, "promo").await);
+
+    admin::run(db.as_ref(), delete("promo", false)).await.unwrap();
+    assert!(db.get_link_target("promo").await.unwrap().is_none());
+    assert!(db.url_exists("promo").await.unwrap());
+    assert!(!insert_link(db.as_ref(), user_id, "promo").await);
+
+    admin::run(db.as_ref(), delete("promo", true)).await.unwrap();
+    assert!(!db.url_exists("promo").await.unwrap());
+    assert!(insert_link(db.as_ref(), user_id, "promo").await);
+
+    assert!(admin::run(db.as_ref(), delete("missing", false)).await.is_err());
+    assert!(admin::run(db.as_ref(), delete("missing", true)).await.is_err());
+}
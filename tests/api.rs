@@ -0,0 +1,199 @@
+//! The HTTP API end to end, from account creation to link statistics, mostly on the in-memory storage.
+
+mod common;
+
+use std::sync::Arc;
+
+use axum::http::{header, StatusCode};
+use common::{app, delete, get, login, patch, post, storage, TempDatabase};
+use serde_json::json;
+use url_shortener::model::{encode_jwt, Claims, Role, TokenType};
+use url_shortener::storage::Storage;
+
+#[tokio::test]
+async fn login_and_refresh() {
+    let app = app(storage("memory").await);
+    let credentials = json!({ "username": "alice", "password": "secret-password" });
+
+    assert_eq!(post("/create_user").json(credentials.clone()).send(&app).await.status, StatusCode::OK);
+    assert_eq!(post("/create_user").json(credentials.clone()).send(&app).await.status, StatusCode::CONFLICT);
+    let wrong = json!({ "username": "alice", "password": "wrong" });
+    assert_eq!(post("/login").json(wrong).send(&app).await.status, StatusCode::UNAUTHORIZED);
+
+    let login = post("/login").json(credentials).send(&app).await;
+    assert_eq!(login.status, StatusCode::OK);
+    let access_token = login.data().as_str().unwrap().to_string();
+    let refresh_token = login.cookie("refresh_token").expect("login sets the refresh cookie");
+
+    let whoami = get("/whoami").token(&access_token).send(&app).await;
+    assert_eq!(whoami.status, StatusCode::OK);
+    assert_eq!(whoami.data()["sub"], "alice");
+    // the refresh token is only good for /refresh
+    assert_eq!(get("/whoami").token(&refresh_token).send(&app).await.status, StatusCode::UNAUTHORIZED);
+    let cookie = format!("refresh_token={}", access_token);
+    assert_eq!(get("/refresh").header("cookie", &cookie).send(&app).await.status, StatusCode::FORBIDDEN);
+
+    let cookie = format!("refresh_token={}", refresh_token);
+    let refreshed = get("/refresh").header("cookie", &cookie).send(&app).await;
+    assert_eq!(refreshed.status, StatusCode::OK);
+    let new_token = refreshed.data().as_str().unwrap();
+    assert_eq!(get("/whoami").token(new_token).send(&app).await.status, StatusCode::OK);
+    assert_ne!(refreshed.cookie("refresh_token").unwrap(), refresh_token);
+
+    // refresh tokens are single use
+    assert_eq!(get("/refresh").header("cookie", &cookie).send(&app).await.status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn shorten_and_redirect() {
+    let app = app(storage("memory").await);
+    let token = login(&app, "bob").await;
+
+    assert_eq!(post("/shorten-link").json(json!({ "url": "https://example.com/" })).send(&app).await.status, StatusCode::UNAUTHORIZED);
+
+    let generated = post("/shorten-link").token(&token).json(json!({ "url": "https://example.com/generated" })).send(&app).await;
+    assert_eq!(generated.status, StatusCode::OK);
+    let code = generated.data().as_str().unwrap().to_string();
+    assert!(!code.is_empty());
+
+    let custom = post("/shorten-link").token(&token).json(json!({ "url": "https://example.com/custom", "code": "my-link" })).send(&app).await;
+    assert_eq!(custom.status, StatusCode::OK);
+    assert_eq!(custom.data(), "my-link");
+    let taken = post("/shorten-link").token(&token).json(json!({ "url": "https://example.com/other", "code": "my-link" })).send(&app).await;
+    assert_eq!(taken.status, StatusCode::CONFLICT);
+
+    let redirect = get(&format!("/link/{}", code)).send(&app).await;
+    assert_eq!(redirect.status, StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(redirect.headers[header::LOCATION], "https://example.com/generated");
+    let redirect = get("/link/my-link").send(&app).await;
+    assert_eq!(redirect.headers[header::LOCATION], "https://example.com/custom");
+
+    let unknown = get("/link/missing").header("accept", "application/json").send(&app).await;
+    assert_eq!(unknown.status, StatusCode::NOT_FOUND);
+
+    let links = get("/get-user-links").token(&token).send(&app).await;
+    assert_eq!(links.status, StatusCode::OK);
+    assert_eq!(links.data().as_array().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn edit_archive_and_restore() {
+    let app = app(storage("memory").await);
+    let token = login(&app, "carol").await;
+    let other = login(&app, "dave").await;
+
+    let created = post("/shorten-link").token(&token).json(json!({ "url": "https://example.com/old", "code": "edited" })).send(&app).await;
+    assert_eq!(created.status, StatusCode::OK);
+    // the redirect is cached, edits have to reach it anyway
+    assert_eq!(get("/link/edited").send(&app).await.headers[header::LOCATION], "https://example.com/old");
+
+    let edit = json!({ "url": "https://example.com/new" });
+    assert_eq!(patch("/links/edited").token(&other).json(edit.clone()).send(&app).await.status, StatusCode::FORBIDDEN);
+    assert_eq!(patch("/links/edited").token(&token).json(edit).send(&app).await.status, StatusCode::OK);
+    assert_eq!(get("/link/edited").send(&app).await.headers[header::LOCATION], "https://example.com/new");
+
+    assert_eq!(delete("/links/edited").token(&other).send(&app).await.status, StatusCode::FORBIDDEN);
+    assert_eq!(delete("/links/edited").token(&token).send(&app).await.status, StatusCode::OK);
+    let archived = get("/link/edited").header("accept", "application/json").send(&app).await;
+    assert_eq!(archived.status, StatusCode::NOT_FOUND);
+    let archived_links = get("/links/archived").token(&token).send(&app).await;
+    assert_eq!(archived_links.data().as_array().unwrap().len(), 1);
+    // archiving doesn't free the code
+    let reuse = post("/shorten-link").token(&other).json(json!({ "url": "https://example.com/", "code": "edited" })).send(&app).await;
+    assert_eq!(reuse.status, StatusCode::CONFLICT);
+
+    assert_eq!(post("/links/edited/restore").token(&token).send(&app).await.status, StatusCode::OK);
+    let restored = get("/link/edited").send(&app).await;
+    assert_eq!(restored.status, StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(restored.headers[header::LOCATION], "https://example.com/new");
+}
+
+#[tokio::test]
+async fn link_stats() {
+    let app = app(storage("memory").await);
+    let token = login(&app, "erin").await;
+    let other = login(&app, "frank").await;
+
+    let created = post("/shorten-link").token(&token).json(json!({ "url": "https://example.com/stats", "code": "counted" })).send(&app).await;
+    assert_eq!(created.status, StatusCode::OK);
+    for _ in 0..3 {
+        assert_eq!(get("/link/counted").send(&app).await.status, StatusCode::TEMPORARY_REDIRECT);
+    }
+
+    let stats = get("/links/counted/stats").token(&token).send(&app).await;
+    assert_eq!(stats.status, StatusCode::OK);
+    assert_eq!(stats.data()["total"], 3);
+    let today = chrono::Utc::now().format("%Y-%m-%d").to_string();
+    assert_eq!(stats.data()["per_day"][0]["day"], today.as_str());
+    assert_eq!(stats.data()["per_day"][0]["clicks"], 3);
+
+    assert_eq!(get("/links/counted/stats").token(&other).send(&app).await.status, StatusCode::FORBIDDEN);
+    assert_eq!(get("/links/missing/stats").token(&token).send(&app).await.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn token_of_unknown_user() {
+    let app = app(storage("memory").await);
+    let claims = Claims {
+        sub: "ghost".to_string(),
+        exp: (chrono::Utc::now() + chrono::Duration::minutes(5)).timestamp() as usize,
+        persistent: false,
+        jti: None,
+        scopes: None,
+        role: Role::User,
+        typ: TokenType::Access,
+    };
+    let token = encode_jwt(&claims).unwrap();
+
+    assert_eq!(get("/get-user-links").token(&token).send(&app).await.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(get("/links/archived").token(&token).send(&app).await.status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn disabling_and_demoting_apply_to_issued_tokens() {
+    let db = storage("memory").await;
+    let app = app(db.clone());
+    let admin = login(&app, "grace").await;
+    let user = login(&app, "heidi").await;
+    assert!(db.set_user_role("grace", Role::Admin).await.unwrap());
+
+    // the role is read from the database, not from the token issued before the promotion
+    assert_eq!(get("/admin/users").token(&admin).send(&app).await.status, StatusCode::OK);
+    assert_eq!(get("/whoami").token(&admin).send(&app).await.data()["role"], "admin");
+
+    assert_eq!(get("/whoami").token(&user).send(&app).await.status, StatusCode::OK);
+    assert_eq!(post("/admin/users/heidi/disable").token(&admin).send(&app).await.status, StatusCode::OK);
+    assert_eq!(get("/whoami").token(&user).send(&app).await.status, StatusCode::FORBIDDEN);
+    assert_eq!(post("/admin/users/heidi/enable").token(&admin).send(&app).await.status, StatusCode::OK);
+    assert_eq!(get("/whoami").token(&user).send(&app).await.status, StatusCode::OK);
+
+    assert!(db.set_user_role("grace", Role::User).await.unwrap());
+    assert_eq!(get("/admin/users").token(&admin).send(&app).await.status, StatusCode::FORBIDDEN);
+}
+
+async fn links_in_creation_order(db: Arc<dyn Storage>) {
+    let app = app(db);
+    let token = login(&app, "ordered").await;
+    let codes = ["zulu", "alpha", "mike", "bravo"];
+    for code in codes {
+        let link = json!({ "url": format!("https://example.com/{}", code), "code": code });
+        assert_eq!(post("/shorten-link").token(&token).json(link).send(&app).await.status, StatusCode::OK);
+    }
+    assert_eq!(delete("/links/mike").token(&token).send(&app).await.status, StatusCode::OK);
+    assert_eq!(post("/links/mike/restore").token(&token).send(&app).await.status, StatusCode::OK);
+
+    let links = get("/get-user-links").token(&token).send(&app).await;
+    let listed: Vec<&str> = links.data().as_array().unwrap().iter().map(|link| link[0].as_str().unwrap()).collect();
+    assert_eq!(listed, codes);
+}
+
+#[tokio::test]
+async fn links_in_creation_order_memory() {
+    links_in_creation_order(storage("memory").await).await;
+}
+
+#[tokio::test]
+async fn links_in_creation_order_sqlite() {
+    let database = TempDatabase::new("ordered");
+    links_in_creation_order(storage(&database.url()).await).await;
+}
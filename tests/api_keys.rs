@@ -0,0 +1,128 @@
+//! API keys: what a key may do with its scopes, and how the storages look keys up by their hash.
+
+mod common;
+
+use std::sync::Arc;
+
+use axum::http::StatusCode;
+use axum::Router;
+use common::{app, delete, get, login, post, storage, TempDatabase};
+use serde_json::json;
+use url_shortener::model::{hash_api_key, Role, Scope, API_KEY_PREFIX};
+use url_shortener::storage::Storage;
+
+async fn create_key(app: &Router, token: &str, scopes: &[&str]) -> (u64, String) {
+    let created = post("/api-keys").token(token).json(json!({ "name": "ci", "scopes": scopes })).send(app).await;
+    assert_eq!(created.status, StatusCode::OK);
+    let key = created.data()["key"].as_str().unwrap().to_string();
+    assert!(key.starts_with(API_KEY_PREFIX));
+    assert!(key.starts_with(created.data()["prefix"].as_str().unwrap()));
+    (created.data()["id"].as_u64().unwrap(), key)
+}
+
+#[tokio::test]
+async fn scopes_limit_the_key() {
+    let app = app(storage("memory").await);
+    let token = login(&app, "kim").await;
+    let link = json!({ "url": "https://example.com/keyed", "code": "keyed" });
+    assert_eq!(post("/shorten-link").token(&token).json(link).send(&app).await.status, StatusCode::OK);
+
+    let (_, read_key) = create_key(&app, &token, &["links:read"]).await;
+    assert_eq!(get("/get-user-links").token(&read_key).send(&app).await.status, StatusCode::OK);
+    let link = json!({ "url": "https://example.com/denied" });
+    assert_eq!(post("/shorten-link").token(&read_key).json(link.clone()).send(&app).await.status, StatusCode::FORBIDDEN);
+    assert_eq!(get("/links/keyed/stats").token(&read_key).send(&app).await.status, StatusCode::FORBIDDEN);
+
+    let (_, write_key) = create_key(&app, &token, &["links:write", "stats:read"]).await;
+    assert_eq!(post("/shorten-link").token(&write_key).json(link).send(&app).await.status, StatusCode::OK);
+    assert_eq!(get("/links/keyed/stats").token(&write_key).send(&app).await.status, StatusCode::OK);
+    assert_eq!(get("/get-user-links").token(&write_key).send(&app).await.status, StatusCode::FORBIDDEN);
+
+    let unknown = format!("{}{}", API_KEY_PREFIX, "0".repeat(48));
+    assert_eq!(get("/get-user-links").token(&unknown).send(&app).await.status, StatusCode::UNAUTHORIZED);
+    let no_scopes = json!({ "name": "empty", "scopes": [] });
+    assert_eq!(post("/api-keys").token(&token).json(no_scopes).send(&app).await.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn keys_cannot_manage_keys_or_administrate() {
+    let db = storage("memory").await;
+    let app = app(db.clone());
+    let token = login(&app, "lena").await;
+    assert!(db.set_user_role("lena", Role::Admin).await.unwrap());
+    let (id, key) = create_key(&app, &token, &["links:read", "links:write", "stats:read"]).await;
+
+    let new_key = json!({ "name": "escalated", "scopes": ["links:write"] });
+    assert_eq!(post("/api-keys").token(&key).json(new_key).send(&app).await.status, StatusCode::FORBIDDEN);
+    assert_eq!(get("/api-keys").token(&key).send(&app).await.status, StatusCode::FORBIDDEN);
+    assert_eq!(delete(&format!("/api-keys/{}", id)).token(&key).send(&app).await.status, StatusCode::FORBIDDEN);
+    // the key of an admin still isn't an admin
+    assert_eq!(get("/admin/users").token(&token).send(&app).await.status, StatusCode::OK);
+    assert_eq!(get("/admin/users").token(&key).send(&app).await.status, StatusCode::FORBIDDEN);
+    assert_eq!(get("/admin/stats").token(&key).send(&app).await.status, StatusCode::FORBIDDEN);
+    assert_eq!(get("/cache/stats").token(&key).send(&app).await.status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn last_use_and_revocation() {
+    let app = app(storage("memory").await);
+    let token = login(&app, "milo").await;
+    let other = login(&app, "nora").await;
+    let (id, key) = create_key(&app, &token, &["links:read"]).await;
+
+    let keys = get("/api-keys").token(&token).send(&app).await;
+    assert!(keys.data()[0]["last_used_at"].is_null());
+    assert_eq!(get("/get-user-links").token(&key).send(&app).await.status, StatusCode::OK);
+    let keys = get("/api-keys").token(&token).send(&app).await;
+    assert!(keys.data()[0]["last_used_at"].as_i64().unwrap() >= keys.data()[0]["created_at"].as_i64().unwrap());
+
+    // only the owner revokes a key
+    assert_eq!(delete(&format!("/api-keys/{}", id)).token(&other).send(&app).await.status, StatusCode::NOT_FOUND);
+    assert_eq!(get("/get-user-links").token(&key).send(&app).await.status, StatusCode::OK);
+    assert_eq!(delete(&format!("/api-keys/{}", id)).token(&token).send(&app).await.status, StatusCode::OK);
+    assert_eq!(get("/get-user-links").token(&key).send(&app).await.status, StatusCode::UNAUTHORIZED);
+    let keys = get("/api-keys").token(&token).send(&app).await;
+    assert_eq!(keys.data()[0]["revoked"], true);
+}
+
+/// The lookup behind every request made with a key, the same for every storage.
+async fn lookup_by_hash(db: Arc<dyn Storage>) {
+    db.create_user("key-owner", "secret-password").await.unwrap();
+    let user_id = db.get_user_id("key-owner").await.unwrap().unwrap();
+    let key = format!("{}{}", API_KEY_PREFIX, "ab".repeat(24));
+    let prefix = &key[..API_KEY_PREFIX.len() + 8];
+    let info = db.create_api_key(user_id, "ci", prefix, &hash_api_key(&key), &[Scope::LinksRead, Scope::StatsRead]).await.unwrap();
+    assert!(info.last_used_at.is_none());
+
+    let Some((username, scopes)) = db.use_api_key(&hash_api_key(&key)).await.unwrap() else {
+        panic!("the key is found by its hash");
+    };
+    assert_eq!(username, "key-owner");
+    assert_eq!(scopes, vec![Scope::LinksRead, Scope::StatsRead]);
+    // the plain key isn't stored
+    assert!(db.use_api_key(&key).await.unwrap().is_none());
+    assert!(db.use_api_key(prefix).await.unwrap().is_none());
+
+    let keys = db.get_api_keys(user_id).await.unwrap();
+    assert_eq!(keys[0].prefix, prefix);
+    assert!(keys[0].last_used_at.is_some());
+
+    assert!(db.set_user_disabled("key-owner", true).await.unwrap());
+    assert!(db.use_api_key(&hash_api_key(&key)).await.unwrap().is_none());
+    assert!(db.set_user_disabled("key-owner", false).await.unwrap());
+
+    assert!(!db.revoke_api_key(user_id + 1, info.id).await.unwrap());
+    assert!(db.revoke_api_key(user_id, info.id).await.unwrap());
+    assert!(db.use_api_key(&hash_api_key(&key)).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn memory_lookup() {
+    lookup_by_hash(storage("memory").await).await;
+}
+
+#[tokio::test]
+async fn sqlite_lookup() {
+    let database = TempDatabase::new("api-keys");
+    lookup_by_hash(storage(&database.url()).await).await;
+}
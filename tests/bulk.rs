@@ -0,0 +1,154 @@
+//! `POST /links/bulk` on the in-memory storage and on SQLite, where the links go in one transaction.
+
+mod common;
+
+use std::sync::Arc;
+
+use axum::http::{header, StatusCode};
+use axum::Router;
+use common::{app, get, login, post, put, storage, TempDatabase};
+use serde_json::{json, Value};
+use url_shortener::storage::Storage;
+
+/// The result of each link, by its index in the request.
+fn link_results(response: &common::TestResponse) -> Vec<Value> {
+    let results = response.data().as_array().expect("a result per link").clone();
+    for (index, result) in results.iter().enumerate() {
+        assert_eq!(result["index"], index);
+    }
+    results
+}
+
+async fn redirects_to(app: &Router, code: &str) -> Option<String> {
+    let response = get(&format!("/link/{}", code)).header("accept", "application/json").send(app).await;
+    response.headers.get(header::LOCATION).map(|location| location.to_str().unwrap().to_string())
+}
+
+async fn json_and_csv(db: Arc<dyn Storage>) {
+    let app = app(db);
+    let token = login(&app, "bulk-json").await;
+
+    let links = json!([
+        { "url": "https://example.com/first", "code": "bulk-first" },
+        { "url": "https://example.com/generated" },
+    ]);
+    let response = post("/links/bulk").token(&token).json(links).send(&app).await;
+    assert_eq!(response.status, StatusCode::OK);
+    let results = link_results(&response);
+    assert_eq!(results[0]["code"], "bulk-first");
+    let generated = results[1]["code"].as_str().unwrap();
+    assert_eq!(redirects_to(&app, "bulk-first").await.unwrap(), "https://example.com/first");
+    assert_eq!(redirects_to(&app, generated).await.unwrap(), "https://example.com/generated");
+
+    let csv = "url,code,redirect_status\nhttps://example.com/csv,bulk-csv,301\n  https://example.com/trimmed  ,,\n";
+    let response = post("/links/bulk").token(&token).body("text/csv", csv).send(&app).await;
+    assert_eq!(response.status, StatusCode::OK);
+    let results = link_results(&response);
+    assert_eq!(results[0]["code"], "bulk-csv");
+    let redirect = get("/link/bulk-csv").send(&app).await;
+    assert_eq!(redirect.status, StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(redirects_to(&app, results[1]["code"].as_str().unwrap()).await.unwrap(), "https://example.com/trimmed");
+
+    let no_url_column = "link,code\nhttps://example.com/,bulk-nourl\n";
+    assert_eq!(post("/links/bulk").token(&token).body("text/csv", no_url_column).send(&app).await.status, StatusCode::BAD_REQUEST);
+    assert_eq!(post("/links/bulk").token(&token).json(json!({ "url": "https://example.com/" })).send(&app).await.status, StatusCode::BAD_REQUEST);
+    assert_eq!(post("/links/bulk").token(&token).json(json!([])).send(&app).await.status, StatusCode::BAD_REQUEST);
+}
+
+async fn failed_rows(db: Arc<dyn Storage>) {
+    let app = app(db);
+    let token = login(&app, "bulk-rows").await;
+    let taken = json!({ "url": "https://example.com/taken", "code": "bulk-taken" });
+    assert_eq!(post("/shorten-link").token(&token).json(taken).send(&app).await.status, StatusCode::OK);
+
+    let links = json!([
+        { "url": "javascript:alert(1)" },
+        { "url": "https://example.com/ok", "code": "bulk-ok" },
+        { "url": "https://example.com/again", "code": "bulk-taken" },
+        { "url": "https://example.com/twice", "code": "bulk-twice" },
+        { "url": "https://example.com/second", "code": "bulk-twice" },
+        { "code": "bulk-no-url" },
+        { "url": "https://example.com/last" },
+    ]);
+    let response = post("/links/bulk").token(&token).json(links).send(&app).await;
+    assert_eq!(response.status, StatusCode::OK);
+    let results = link_results(&response);
+
+    assert_eq!(results[0]["error"], "Validation failed");
+    assert_eq!(results[0]["details"]["field"], "url");
+    assert_eq!(results[1]["code"], "bulk-ok");
+    assert_eq!(results[2]["error"], "Data already exists");
+    // the first link of the batch gets the code, only the second one fails
+    assert_eq!(results[3]["code"], "bulk-twice");
+    assert_eq!(results[4]["error"], "Data already exists");
+    assert_eq!(results[5]["error"], "Invalid parameters");
+    assert!(results[6]["code"].is_string());
+
+    // the failed links didn't keep the others from being created, or change the existing one
+    assert_eq!(redirects_to(&app, "bulk-ok").await.unwrap(), "https://example.com/ok");
+    assert_eq!(redirects_to(&app, "bulk-twice").await.unwrap(), "https://example.com/twice");
+    assert_eq!(redirects_to(&app, "bulk-taken").await.unwrap(), "https://example.com/taken");
+    assert_eq!(redirects_to(&app, results[6]["code"].as_str().unwrap()).await.unwrap(), "https://example.com/last");
+    assert!(redirects_to(&app, "bulk-no-url").await.is_none());
+}
+
+async fn foreign_workspace(db: Arc<dyn Storage>) {
+    let app = app(db);
+    let owner = login(&app, "bulk-owner").await;
+    let token = login(&app, "bulk-member").await;
+    let workspace = post("/workspaces").token(&owner).json(json!({ "name": "bulk" })).send(&app).await.data()["id"].as_u64().unwrap();
+    let readable = post("/workspaces").token(&owner).json(json!({ "name": "readable" })).send(&app).await.data()["id"].as_u64().unwrap();
+    let join = |workspace: u64, role: &str| put(&format!("/workspaces/{}/members/bulk-member", workspace)).token(&owner).json(json!({ "role": role }));
+    assert_eq!(join(workspace, "editor").send(&app).await.status, StatusCode::OK);
+    assert_eq!(join(readable, "viewer").send(&app).await.status, StatusCode::OK);
+
+    let links = json!([
+        { "url": "https://example.com/team", "code": "bulk-team", "workspace": workspace },
+        { "url": "https://example.com/readonly", "code": "bulk-readonly", "workspace": readable },
+        { "url": "https://example.com/unknown", "code": "bulk-unknown", "workspace": 999_999 },
+        { "url": "https://example.com/personal", "code": "bulk-personal" },
+    ]);
+    let response = post("/links/bulk").token(&token).json(links).send(&app).await;
+    assert_eq!(response.status, StatusCode::OK);
+    let results = link_results(&response);
+    assert_eq!(results[0]["code"], "bulk-team");
+    assert_eq!(results[1]["error"], "Forbidden");
+    assert_eq!(results[2]["error"], "Forbidden");
+    assert_eq!(results[3]["code"], "bulk-personal");
+    assert!(redirects_to(&app, "bulk-readonly").await.is_none());
+}
+
+async fn batch_size(db: Arc<dyn Storage>) {
+    let app = app(db);
+    let token = login(&app, "bulk-size").await;
+    let links = |count: usize| Value::Array((0..count).map(|i| json!({ "url": format!("https://example.com/{}", i) })).collect());
+
+    let max = url_shortener::config::config().urls.max_batch_size;
+    let response = post("/links/bulk").token(&token).json(links(max + 1)).send(&app).await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    // nothing of a rejected batch is created
+    let created = get("/get-user-links").token(&token).send(&app).await;
+    assert_eq!(created.data().as_array().unwrap().len(), 0);
+
+    let response = post("/links/bulk").token(&token).json(links(max)).send(&app).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(link_results(&response).iter().all(|result| result["code"].is_string()));
+}
+
+#[tokio::test]
+async fn memory() {
+    json_and_csv(storage("memory").await).await;
+    failed_rows(storage("memory").await).await;
+    foreign_workspace(storage("memory").await).await;
+    batch_size(storage("memory").await).await;
+}
+
+#[tokio::test]
+async fn sqlite() {
+    let database = TempDatabase::new("bulk");
+    let db = storage(&database.url()).await;
+    json_and_csv(db.clone()).await;
+    failed_rows(db.clone()).await;
+    foreign_workspace(db.clone()).await;
+    batch_size(db).await;
+}
@@ -0,0 +1,51 @@
+//! Clicks are counted and recorded together, also for links without a limit.
+
+mod common;
+
+use std::sync::Arc;
+
+use axum::http::StatusCode;
+use common::{app, get, login, post, storage, TempDatabase};
+use serde_json::json;
+use url_shortener::storage::{LinkFilter, Storage};
+
+async fn link_clicks(db: &dyn Storage, short: &str) -> u32 {
+    let filter = LinkFilter { owner: None, search: Some(short.to_string()), archived: None, limit: 10, offset: 0 };
+    db.find_links(&filter).await.unwrap().into_iter().find(|link| link.short == short).unwrap().clicks
+}
+
+async fn count_clicks(db: Arc<dyn Storage>) {
+    let app = app(db.clone());
+    let token = login(&app, "clicker").await;
+
+    let limited = json!({ "url": "https://example.com/limited", "code": "limited", "max_clicks": 2 });
+    assert_eq!(post("/shorten-link").token(&token).json(limited).send(&app).await.status, StatusCode::OK);
+    let unlimited = json!({ "url": "https://example.com/unlimited", "code": "unlimited" });
+    assert_eq!(post("/shorten-link").token(&token).json(unlimited).send(&app).await.status, StatusCode::OK);
+
+    for _ in 0..2 {
+        assert_eq!(get("/link/limited").send(&app).await.status, StatusCode::TEMPORARY_REDIRECT);
+    }
+    assert_eq!(get("/link/limited").send(&app).await.status, StatusCode::GONE);
+    assert_eq!(get("/link/limited").send(&app).await.status, StatusCode::GONE);
+    for _ in 0..3 {
+        assert_eq!(get("/link/unlimited").send(&app).await.status, StatusCode::TEMPORARY_REDIRECT);
+    }
+
+    // refused clicks are neither counted nor recorded
+    assert_eq!(link_clicks(db.as_ref(), "limited").await, 2);
+    assert_eq!(get("/links/limited/stats").token(&token).send(&app).await.data()["total"], 2);
+    assert_eq!(link_clicks(db.as_ref(), "unlimited").await, 3);
+    assert_eq!(get("/links/unlimited/stats").token(&token).send(&app).await.data()["total"], 3);
+}
+
+#[tokio::test]
+async fn count_clicks_sqlite() {
+    let database = TempDatabase::new("clicks");
+    count_clicks(storage(&database.url()).await).await;
+}
+
+#[tokio::test]
+async fn count_clicks_memory() {
+    count_clicks(storage("memory").await).await;
+}
@@ -0,0 +1,149 @@
+//! Generated short codes. This binary configures a reserved code of its own, so it sets up its
+//! own config instead of the shared one.
+
+mod common;
+
+use std::collections::HashSet;
+
+use axum::http::StatusCode;
+use common::{app, login, post, storage};
+use serde_json::json;
+use url_shortener::codegen::{self, CodeGenerator, CodeStrategy, HashGenerator, RandomGenerator, SequentialGenerator, WordsGenerator};
+use url_shortener::config::{self, AuthConfig, CodesConfig, Config};
+
+const RESERVED_URL: &str = "https://example.com/reserved";
+
+fn init() {
+    config::init(Config {
+        auth: AuthConfig { jwt_secret: Some("integration-tests-jwt-signing-secret".to_string()), ..Default::default() },
+        codes: CodesConfig {
+            strategy: CodeStrategy::Hash,
+            length: 9,
+            salt: "pepper".to_string(),
+            // the code the hash strategy tries first for RESERVED_URL
+            reserved: vec![HashGenerator.generate(RESERVED_URL, 0, 0)],
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+}
+
+fn is_base62(code: &str) -> bool {
+    code.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+#[test]
+fn hash_codes() {
+    let first = HashGenerator.generate("https://example.com/", 0, 0);
+    assert_eq!(first.len(), 6);
+    assert!(first.chars().all(|c| c.is_ascii_hexdigit() && !c.is_ascii_uppercase()));
+    // the same url always gets the same first candidate, the seq doesn't matter
+    assert_eq!(HashGenerator.generate("https://example.com/", 42, 0), first);
+    assert_ne!(HashGenerator.generate("https://example.com/other", 0, 0), first);
+
+    let retries: Vec<String> = (1..6).map(|attempt| HashGenerator.generate("https://example.com/", 0, attempt)).collect();
+    assert!(retries.iter().all(|code| *code != first));
+    assert_eq!(retries.iter().map(String::len).collect::<Vec<_>>(), [6, 7, 7, 8, 8]);
+}
+
+#[test]
+fn random_codes() {
+    let generator = RandomGenerator { length: 9 };
+    let codes: HashSet<String> = (0..1000).map(|_| generator.generate("https://example.com/", 0, 0)).collect();
+    assert_eq!(codes.len(), 1000);
+    assert!(codes.iter().all(|code| code.len() == 9 && is_base62(code)));
+    // each alphabet's characters show up, not just digits or lowercase
+    let chars: HashSet<char> = codes.iter().flat_map(|code| code.chars()).collect();
+    assert!(chars.iter().any(char::is_ascii_digit) && chars.iter().any(char::is_ascii_lowercase) && chars.iter().any(char::is_ascii_uppercase));
+
+    assert_eq!(generator.generate("https://example.com/", 0, 4).len(), 11);
+}
+
+#[test]
+fn sequential_codes() {
+    let generator = SequentialGenerator::new("pepper");
+    let codes: Vec<String> = (0..100_000).map(|seq| generator.generate("https://example.com/", seq, 0)).collect();
+    assert!(codes.iter().all(|code| is_base62(code) && code.len() <= 3));
+    assert_eq!(codes.iter().collect::<HashSet<_>>().len(), codes.len());
+    // a retry takes the next id
+    assert_eq!(generator.generate("https://example.com/", 10, 2), codes[12]);
+
+    // the salt shuffles the alphabet, the same salt always the same way
+    let unsalted = SequentialGenerator::new("");
+    assert_eq!(unsalted.generate("", 61, 0), "Z");
+    assert_eq!(unsalted.generate("", 62, 0), "10");
+    assert_ne!(unsalted.generate("", 12345, 0), codes[12345]);
+    assert_eq!(SequentialGenerator::new("pepper").generate("", 12345, 0), codes[12345]);
+}
+
+#[test]
+fn words_codes() {
+    for _ in 0..200 {
+        let code = WordsGenerator.generate("https://example.com/", 0, 0);
+        let parts: Vec<&str> = code.split('-').collect();
+        assert_eq!(parts.len(), 3, "{}", code);
+        assert!(parts[0].chars().all(|c| c.is_ascii_lowercase()) && parts[1].chars().all(|c| c.is_ascii_lowercase()), "{}", code);
+        assert!(parts[2].parse::<u32>().unwrap() < 100, "{}", code);
+    }
+    // retries widen the number range
+    let widened = (0..200).map(|_| WordsGenerator.generate("", 0, 6)).filter_map(|code| code.rsplit('-').next()?.parse::<u32>().ok());
+    assert!(widened.max().unwrap() >= 100);
+}
+
+#[test]
+fn strategies_by_name() {
+    for (name, strategy) in [("hash", CodeStrategy::Hash), ("MD5", CodeStrategy::Hash), ("random", CodeStrategy::Random), ("Sequential", CodeStrategy::Sequential), ("words", CodeStrategy::Words)] {
+        assert_eq!(CodeStrategy::parse(name), Some(strategy));
+    }
+    assert_eq!(CodeStrategy::parse("uuid"), None);
+}
+
+#[tokio::test]
+async fn reserved_codes_are_skipped() {
+    init();
+    let app = app(storage("memory").await);
+    let token = login(&app, "reserver").await;
+    let reserved = HashGenerator.generate(RESERVED_URL, 0, 0);
+
+    let created = post("/shorten-link").token(&token).json(json!({ "url": RESERVED_URL })).send(&app).await;
+    assert_eq!(created.status, StatusCode::OK);
+    let code = created.data().as_str().unwrap();
+    assert_ne!(code, reserved);
+    assert_eq!(code, HashGenerator.generate(RESERVED_URL, 0, 1));
+
+    // nor can it be picked by hand
+    let custom = post("/shorten-link").token(&token).json(json!({ "url": RESERVED_URL, "code": reserved })).send(&app).await;
+    assert_eq!(custom.status, StatusCode::BAD_REQUEST);
+    assert_eq!(custom.body["details"]["code"], "reserved");
+}
+
+#[tokio::test]
+async fn request_strategy_overrides_the_default() {
+    init();
+    assert_eq!(codegen::code_settings().strategy, CodeStrategy::Hash);
+    let app = app(storage("memory").await);
+    let token = login(&app, "strategist").await;
+    let shorten = |strategy: Option<&str>| {
+        let mut link = json!({ "url": "https://example.com/strategy" });
+        if let Some(strategy) = strategy {
+            link["strategy"] = json!(strategy);
+        }
+        post("/shorten-link").token(&token).json(link)
+    };
+
+    let default = shorten(None).send(&app).await;
+    assert_eq!(default.data(), HashGenerator.generate("https://example.com/strategy", 0, 0).as_str());
+
+    let random = shorten(Some("random")).send(&app).await;
+    let code = random.data().as_str().unwrap();
+    assert!(code.len() == 9 && is_base62(code), "{}", code);
+
+    let words = shorten(Some("words")).send(&app).await;
+    assert_eq!(words.data().as_str().unwrap().split('-').count(), 3);
+
+    let sequential = shorten(Some("sequential")).send(&app).await;
+    let code = sequential.data().as_str().unwrap();
+    assert!(code.len() <= 2 && is_base62(code), "{}", code);
+
+    assert!(shorten(Some("uuid")).send(&app).await.status.is_client_error());
+}
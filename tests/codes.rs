@@ -0,0 +1,45 @@
+//! Generated codes that are already taken by another link.
+
+mod common;
+
+use std::sync::Arc;
+
+use axum::http::{header, StatusCode};
+use common::{app, get, login, post, storage, TempDatabase};
+use serde_json::json;
+use url_shortener::codegen::{self, CodeStrategy};
+use url_shortener::model::RedirectStatus;
+use url_shortener::storage::{LinkOwner, Storage};
+use url_shortener::validation;
+
+async fn generated_code_taken(db: Arc<dyn Storage>) {
+    let app = app(db.clone());
+    let token = login(&app, "hasher").await;
+    let user_id = db.get_user_id("hasher").await.unwrap().unwrap();
+
+    // the hash strategy always tries the same code first, give it to another link
+    let first_try = codegen::generator(CodeStrategy::Hash).generate("https://example.com/b", 0, 0);
+    let first_try = validation::code_rules().normalize(&first_try);
+    let owner = LinkOwner { user_id, workspace_id: None };
+    assert!(db.insert_url(&first_try, "https://example.com/a", owner, None, None, RedirectStatus::default()).await.unwrap());
+
+    let body = json!({ "url": "https://example.com/b", "strategy": "hash" });
+    let created = post("/shorten-link").token(&token).json(body).send(&app).await;
+    assert_eq!(created.status, StatusCode::OK);
+    let code = created.data().as_str().unwrap();
+    assert_ne!(code, first_try);
+
+    assert_eq!(get(&format!("/link/{}", code)).send(&app).await.headers[header::LOCATION], "https://example.com/b");
+    assert_eq!(get(&format!("/link/{}", first_try)).send(&app).await.headers[header::LOCATION], "https://example.com/a");
+}
+
+#[tokio::test]
+async fn generated_code_taken_sqlite() {
+    let database = TempDatabase::new("collision");
+    generated_code_taken(storage(&database.url()).await).await;
+}
+
+#[tokio::test]
+async fn generated_code_taken_memory() {
+    generated_code_taken(storage("memory").await).await;
+}
@@ -0,0 +1,163 @@
+//! Helpers shared by the integration tests: a configured app on a fresh storage and a way to call it.
+
+#![allow(dead_code)]
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::http::{header, HeaderMap, Method, Request, StatusCode};
+use axum::Router;
+use tower::ServiceExt;
+use tower_cookies::CookieManagerLayer;
+use url_shortener::config::{self, AuthConfig, Config};
+use url_shortener::jwt_keys;
+use url_shortener::routes::routes;
+use url_shortener::storage::{self, Storage};
+
+/// Loads a test configuration once per test binary, every test shares it.
+pub fn init() {
+    config::init(Config {
+        auth: AuthConfig { jwt_secret: Some("integration-tests-jwt-signing-secret".to_string()), ..Default::default() },
+        ..Default::default()
+    });
+    jwt_keys::init_jwt_keys().expect("Failed to load JWT signing keys");
+}
+
+/// A migrated storage behind the link cache, the same as the server gets for `database.url`.
+pub async fn storage(database_url: &str) -> Arc<dyn Storage> {
+    init();
+    let db = storage::connect(database_url).expect("Failed to connect to database");
+    db.init_db().await.expect("Failed to initialize database");
+    db
+}
+
+/// A SQLite database file in its own directory, removed when the test ends.
+pub struct TempDatabase {
+    dir: PathBuf,
+}
+
+impl TempDatabase {
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("url-shortener-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        TempDatabase { dir }
+    }
+
+    pub fn url(&self) -> String {
+        self.dir.join("test.db").to_string_lossy().to_string()
+    }
+}
+
+impl Drop for TempDatabase {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// The API routes with the layers they need from the server.
+pub fn app(db: Arc<dyn Storage>) -> Router {
+    routes().layer(CookieManagerLayer::new()).with_state(db)
+}
+
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: serde_json::Value,
+    /// The raw body, for responses that aren't JSON.
+    pub text: String,
+}
+
+impl TestResponse {
+    /// The `data` field of an `OkResponse`.
+    pub fn data(&self) -> &serde_json::Value {
+        &self.body["data"]
+    }
+
+    pub fn cookie(&self, name: &str) -> Option<String> {
+        self.headers.get_all(header::SET_COOKIE).iter()
+            .filter_map(|value| value.to_str().ok())
+            .filter_map(|value| value.split(';').next())
+            .find_map(|pair| pair.strip_prefix(&format!("{}=", name)).map(str::to_string))
+    }
+}
+
+pub struct Call {
+    request: axum::http::request::Builder,
+    body: Body,
+}
+
+impl Call {
+    pub fn new(method: Method, uri: &str) -> Self {
+        let peer: SocketAddr = "203.0.113.7:40000".parse().unwrap();
+        Call {
+            request: Request::builder().method(method).uri(uri).extension(ConnectInfo(peer)),
+            body: Body::empty(),
+        }
+    }
+
+    pub fn token(mut self, token: &str) -> Self {
+        self.request = self.request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        self
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.request = self.request.header(name, value);
+        self
+    }
+
+    pub fn json(mut self, body: serde_json::Value) -> Self {
+        self.request = self.request.header(header::CONTENT_TYPE, "application/json");
+        self.body = Body::from(body.to_string());
+        self
+    }
+
+    pub fn body(mut self, content_type: &str, body: impl Into<String>) -> Self {
+        self.request = self.request.header(header::CONTENT_TYPE, content_type);
+        self.body = Body::from(body.into());
+        self
+    }
+
+    pub async fn send(self, app: &Router) -> TestResponse {
+        let response = app.clone().oneshot(self.request.body(self.body).unwrap()).await.unwrap();
+        let status = response.status();
+        let headers = response.headers().clone();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = serde_json::from_slice(&bytes).unwrap_or(serde_json::Value::Null);
+        let text = String::from_utf8_lossy(&bytes).to_string();
+        TestResponse { status, headers, body, text }
+    }
+}
+
+pub fn get(uri: &str) -> Call {
+    Call::new(Method::GET, uri)
+}
+
+pub fn post(uri: &str) -> Call {
+    Call::new(Method::POST, uri)
+}
+
+pub fn put(uri: &str) -> Call {
+    Call::new(Method::PUT, uri)
+}
+
+pub fn patch(uri: &str) -> Call {
+    Call::new(Method::PATCH, uri)
+}
+
+pub fn delete(uri: &str) -> Call {
+    Call::new(Method::DELETE, uri)
+}
+
+/// Creates the user and logs in, returns the access token.
+pub async fn login(app: &Router, username: &str) -> String {
+    let credentials = serde_json::json!({ "username": username, "password": "secret-password" });
+    let created = post("/create_user").json(credentials.clone()).send(app).await;
+    assert_eq!(created.status, StatusCode::OK);
+    let response = post("/login").json(credentials).send(app).await;
+    assert_eq!(response.status, StatusCode::OK);
+    response.data().as_str().unwrap().to_string()
+}
@@ -0,0 +1,44 @@
+//! Requests racing for the same custom code, exactly one of them may get it.
+
+mod common;
+
+use axum::http::{header, StatusCode};
+use axum::Router;
+use common::{app, get, login, post, storage, TempDatabase};
+use serde_json::json;
+
+const REQUESTS: usize = 16;
+
+async fn race_for_code(app: Router) {
+    let token = login(&app, "racer").await;
+
+    let requests = (0..REQUESTS).map(|i| {
+        let app = app.clone();
+        let body = json!({ "url": format!("https://example.com/{}", i), "code": "contested" });
+        let call = post("/shorten-link").token(&token).json(body);
+        tokio::spawn(async move { call.send(&app).await.status })
+    }).collect::<Vec<_>>();
+
+    let mut statuses = Vec::new();
+    for request in requests {
+        statuses.push(request.await.unwrap());
+    }
+    assert_eq!(statuses.iter().filter(|status| **status == StatusCode::OK).count(), 1, "{:?}", statuses);
+    assert_eq!(statuses.iter().filter(|status| **status == StatusCode::CONFLICT).count(), REQUESTS - 1, "{:?}", statuses);
+
+    // the code points at the url of the request that won
+    let winner = statuses.iter().position(|status| *status == StatusCode::OK).unwrap();
+    let redirect = get("/link/contested").send(&app).await;
+    assert_eq!(redirect.headers[header::LOCATION], format!("https://example.com/{}", winner).as_str());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn same_custom_code_sqlite() {
+    let database = TempDatabase::new("race");
+    race_for_code(app(storage(&database.url()).await)).await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn same_custom_code_memory() {
+    race_for_code(app(storage("memory").await)).await;
+}
@@ -0,0 +1,175 @@
+//! Loading the configuration from a file and checking it.
+
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use clap::Parser;
+use url_shortener::codegen::CodeStrategy;
+use url_shortener::config::{Cli, Config};
+
+/// A config file in the temp directory, removed when the test ends.
+struct ConfigFile(PathBuf);
+
+impl ConfigFile {
+    fn new(name: &str, content: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("url-shortener-config-{}-{}.toml", name, std::process::id()));
+        std::fs::write(&path, content).unwrap();
+        ConfigFile(path)
+    }
+
+    fn load(&self, args: &[&str]) -> Result<Config, Vec<String>> {
+        let path = self.0.to_string_lossy().to_string();
+        let cli = Cli::parse_from(["url-shortener", "--config", &path].iter().chain(args));
+        Config::load(&cli)
+    }
+}
+
+/// Tests of this file share the process environment, they set their variables one at a time.
+static ENV: Mutex<()> = Mutex::new(());
+
+/// Loads the config with the given environment variables set, and removes them again.
+fn load_with_env(file: &ConfigFile, env: &[(&str, &str)], args: &[&str]) -> Result<Config, Vec<String>> {
+    let _env = ENV.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    for (name, value) in env {
+        std::env::set_var(name, value);
+    }
+    let config = file.load(args);
+    for (name, _) in env {
+        std::env::remove_var(name);
+    }
+    config
+}
+
+impl Drop for ConfigFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+#[test]
+fn code_alphabet_without_url_delimiters() {
+    for delimiter in ["/", "?", "#"] {
+        let file = ConfigFile::new("alphabet", &format!("[codes]\nalphabet = \"abc{}\"\n", delimiter));
+        let errors = load_with_env(&file, &[], &[]).unwrap_err();
+        assert_eq!(errors, vec![format!("codes.alphabet: can't contain {}", delimiter)]);
+    }
+
+    let file = ConfigFile::new("alphabet", "[codes]\nalphabet = \"abc.~\"\n");
+    assert_eq!(load_with_env(&file, &[], &[]).unwrap().codes.alphabet, "abc.~");
+}
+
+#[test]
+fn file_then_environment_then_flags() {
+    let file = ConfigFile::new("precedence", r#"
+        [server]
+        address = "0.0.0.0"
+        port = 3000
+        cors_origins = ["https://file.example"]
+
+        [database]
+        url = "file.db"
+
+        [cache]
+        size = 100
+
+        [auth]
+        access_token_ttl = 600
+
+        [codes]
+        strategy = "random"
+    "#);
+
+    let config = load_with_env(&file, &[], &[]).unwrap();
+    assert_eq!((config.server.address.as_str(), config.server.port), ("0.0.0.0", 3000));
+    assert_eq!(config.database.url, "file.db");
+    assert_eq!(config.cache.size, 100);
+    assert_eq!(config.auth.access_token_ttl, 600);
+    // settings missing from the file keep their defaults
+    assert_eq!(config.cache.ttl, 60);
+    assert_eq!(config.urls.max_batch_size, 1000);
+
+    let env = [
+        ("URL_SHORTENER_PORT", "4000"),
+        ("URL_SHORTENER_DATABASE_URL", "env.db"),
+        ("URL_SHORTENER_CORS_ORIGINS", "https://env.example, https://other.example"),
+        ("URL_SHORTENER_CACHE_SIZE", "200"),
+        ("URL_SHORTENER_ACCESS_TOKEN_TTL", "900"),
+        ("URL_SHORTENER_CODE_STRATEGY", "words"),
+    ];
+    let config = load_with_env(&file, &env, &[]).unwrap();
+    assert_eq!((config.server.address.as_str(), config.server.port), ("0.0.0.0", 4000));
+    assert_eq!(config.database.url, "env.db");
+    assert_eq!(config.server.cors_origins, ["https://env.example", "https://other.example"]);
+    assert_eq!(config.cache.size, 200);
+    assert_eq!(config.auth.access_token_ttl, 900);
+    assert_eq!(config.codes.strategy, CodeStrategy::Words);
+
+    let flags = ["--port", "5000", "--database-url", "memory", "--cors-origin", "https://cli.example"];
+    let config = load_with_env(&file, &env, &flags).unwrap();
+    assert_eq!((config.server.address.as_str(), config.server.port), ("0.0.0.0", 5000));
+    assert_eq!(config.database.url, "memory");
+    assert_eq!(config.server.cors_origins, ["https://cli.example"]);
+    // settings without a flag keep the value of the environment
+    assert_eq!(config.cache.size, 200);
+}
+
+#[test]
+fn every_problem_is_reported() {
+    let file = ConfigFile::new("invalid", r#"
+        [server]
+        public_url = "ftp://sho.rt"
+        cors_origins = ["*", "https://app.example"]
+        trusted_proxies = ["10.0.0.0/8", "proxy.local"]
+
+        [database]
+        readers = 0
+
+        [auth]
+        jwt_secret = "too-short"
+        refresh_token_ttl = 0
+
+        [codes]
+        length = 0
+        min_length = 10
+        max_length = 5
+
+        [urls]
+        allowed_schemes = []
+        max_batch_size = 0
+    "#);
+    let errors = load_with_env(&file, &[("URL_SHORTENER_PORT", "abc")], &[]).unwrap_err();
+    let expected = [
+        "URL_SHORTENER_PORT=\"abc\": invalid digit found in string",
+        "server.public_url: \"ftp://sho.rt\" is not an absolute http(s) URL",
+        "server.cors_origins: * can't be combined with other origins",
+        "server.trusted_proxies: \"proxy.local\" is not an IP address or CIDR range",
+        "database.readers: must be at least 1",
+        "auth.refresh_token_ttl: must be a positive number of seconds",
+        "auth.jwt_secret: must be at least 32 bytes long",
+        "codes.length: must be at least 1",
+        "codes.min_length, codes.max_length: must satisfy 0 < min <= max, got 10 and 5",
+        "urls.allowed_schemes: at least one scheme is required",
+        "urls.max_batch_size: must be at least 1",
+    ];
+    assert_eq!(errors, expected);
+
+    // flags are checked like everything else
+    let file = ConfigFile::new("valid", "");
+    let errors = load_with_env(&file, &[], &["--public-url", "sho.rt", "--cors-origin", "https://app.example/"]).unwrap_err();
+    assert_eq!(errors, [
+        "server.public_url: \"sho.rt\" is not an absolute http(s) URL",
+        "server.cors_origins: \"https://app.example/\" is not an origin like https://example.com",
+    ]);
+}
+
+#[test]
+fn unreadable_files() {
+    let file = ConfigFile::new("unknown-field", "[server]\nprot = 3000\n");
+    let errors = load_with_env(&file, &[], &[]).unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].contains("unknown field `prot`"), "{:?}", errors);
+
+    let missing = ConfigFile(std::env::temp_dir().join("url-shortener-config-missing.toml"));
+    let errors = load_with_env(&missing, &[], &[]).unwrap_err();
+    assert!(errors[0].starts_with("Cannot read config file"), "{:?}", errors);
+}
@@ -0,0 +1,79 @@
+//! Links past their expiration date, on the in-memory storage behind the link cache.
+
+mod common;
+
+use std::time::Duration;
+
+use axum::http::{header, StatusCode};
+use common::{app, get, login, post, storage};
+use serde_json::json;
+use url_shortener::model::RedirectStatus;
+use url_shortener::storage::LinkOwner;
+
+#[tokio::test]
+async fn expired_links_are_gone() {
+    let db = storage("memory").await;
+    let app = app(db.clone());
+    login(&app, "expirer").await;
+    let owner = LinkOwner { user_id: db.get_user_id("expirer").await.unwrap().unwrap(), workspace_id: None };
+    // the API refuses dates in the past, links get there by getting old
+    let yesterday = chrono::Utc::now().timestamp() - 24 * 60 * 60;
+    assert!(db.insert_url("expired", "https://example.com/expired", owner, Some(yesterday), None, RedirectStatus::default()).await.unwrap());
+
+    let api = get("/link/expired").header("accept", "application/json").send(&app).await;
+    assert_eq!(api.status, StatusCode::GONE);
+    assert_eq!(api.body["error"], "Link expired");
+    assert!(api.headers.get(header::LOCATION).is_none());
+
+    let browser = get("/link/expired").header("accept", "text/html,application/xhtml+xml,*/*;q=0.8").send(&app).await;
+    assert_eq!(browser.status, StatusCode::GONE);
+    assert!(browser.headers[header::CONTENT_TYPE].to_str().unwrap().starts_with("text/html"));
+    assert_eq!(browser.headers[header::CACHE_CONTROL], "no-store");
+    assert!(browser.headers.get(header::LOCATION).is_none());
+
+    // expired links aren't clicked
+    assert_eq!(db.get_link_stats("expired").await.unwrap().total, 0);
+}
+
+#[tokio::test]
+async fn used_up_links_are_gone_for_browsers_too() {
+    let app = app(storage("memory").await);
+    let token = login(&app, "limiter").await;
+    let link = json!({ "url": "https://example.com/once", "code": "once", "max_clicks": 1 });
+    assert_eq!(post("/shorten-link").token(&token).json(link).send(&app).await.status, StatusCode::OK);
+
+    assert_eq!(get("/link/once").send(&app).await.status, StatusCode::TEMPORARY_REDIRECT);
+    let browser = get("/link/once").header("accept", "text/html").send(&app).await;
+    assert_eq!(browser.status, StatusCode::GONE);
+    assert!(browser.headers[header::CONTENT_TYPE].to_str().unwrap().starts_with("text/html"));
+    let api = get("/link/once").header("accept", "application/json").send(&app).await;
+    assert_eq!(api.status, StatusCode::GONE);
+    assert_eq!(api.body["error"], "Link expired");
+}
+
+#[tokio::test]
+async fn cache_evicts_links_when_they_expire() {
+    let db = storage("memory").await;
+    let app = app(db.clone());
+    login(&app, "cached").await;
+    let owner = LinkOwner { user_id: db.get_user_id("cached").await.unwrap().unwrap(), workspace_id: None };
+    let expires_at = chrono::Utc::now().timestamp() + 2;
+    assert!(db.insert_url("short-lived", "https://example.com/soon", owner, Some(expires_at), None, RedirectStatus::default()).await.unwrap());
+
+    assert_eq!(get("/link/short-lived").send(&app).await.status, StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(get("/link/short-lived").send(&app).await.status, StatusCode::TEMPORARY_REDIRECT);
+    let stats = db.cache_stats().unwrap();
+    assert_eq!((stats.entries, stats.hits), (1, 1));
+
+    // well within the cache ttl of a minute, but past the expiration date
+    let wait = (expires_at + 1 - chrono::Utc::now().timestamp()).max(0) as u64;
+    tokio::time::sleep(Duration::from_secs(wait)).await;
+
+    let misses = db.cache_stats().unwrap().misses;
+    let expired = get("/link/short-lived").header("accept", "application/json").send(&app).await;
+    assert_eq!(expired.status, StatusCode::GONE);
+    // the entry was dropped at the expiration date and the link read again
+    let stats = db.cache_stats().unwrap();
+    assert_eq!(stats.misses, misses + 1);
+    assert_eq!(stats.hits, 1);
+}
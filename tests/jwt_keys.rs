@@ -0,0 +1,78 @@
+//! Signing key rotation with a key file. This binary configures a key file instead of the
+//! shared `auth.jwt_secret`, so it has its own process and config.
+
+mod common;
+
+use std::path::Path;
+
+use axum::http::StatusCode;
+use common::{app, get, login, storage};
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use url_shortener::config::{self, AuthConfig, Config};
+use url_shortener::jwt_keys::{self, JwtKeySet};
+use url_shortener::model::{validate_jwt_token, Claims, Role, TokenType};
+
+fn claims(username: &str) -> Claims {
+    Claims {
+        sub: username.to_string(),
+        exp: (chrono::Utc::now() + chrono::Duration::minutes(5)).timestamp() as usize,
+        persistent: false,
+        jti: None,
+        scopes: None,
+        role: Role::User,
+        typ: TokenType::Access,
+    }
+}
+
+/// A token signed with the given key of the file, as a server that had it active would have issued.
+fn sign(path: &Path, kid: Option<&str>, secret_of: &str, username: &str) -> String {
+    let key_set = JwtKeySet::load(path).unwrap();
+    let secret = hex::decode(&key_set.get(secret_of).unwrap().secret).unwrap();
+    let header = Header { kid: kid.map(str::to_string), ..Header::new(Algorithm::HS256) };
+    encode(&header, &claims(username), &EncodingKey::from_secret(&secret)).unwrap()
+}
+
+#[tokio::test]
+async fn rotation_keeps_old_tokens_valid() {
+    let dir = common::TempDatabase::new("jwt-keys");
+    let path = Path::new(&dir.url()).with_file_name("jwt_keys.json");
+    config::init(Config {
+        auth: AuthConfig { jwt_keys_file: path.clone(), jwt_secret: None, ..Default::default() },
+        ..Default::default()
+    });
+
+    let old_kid = jwt_keys::rotate_key_file(&path).unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+    let old_token = sign(&path, Some(&old_kid), &old_kid, "rotated");
+
+    // what `rotate-jwt-key` does before the server is restarted
+    let new_kid = jwt_keys::rotate_key_file(&path).unwrap();
+    assert_ne!(new_kid, old_kid);
+    let key_set = JwtKeySet::load(&path).unwrap();
+    assert_eq!(key_set.active, new_kid);
+    assert!(key_set.get(&old_kid).unwrap().retired_at.is_some());
+    assert!(!path.with_file_name("jwt_keys.json.tmp").exists());
+
+    let keys = jwt_keys::init_jwt_keys().unwrap();
+    assert_eq!(keys.active_kid, new_kid);
+    assert_eq!(validate_jwt_token(&old_token).ok().map(|claims| claims.sub), Some("rotated".to_string()));
+
+    let app = app(storage("memory").await);
+    let new_token = login(&app, "rotated").await;
+    assert_eq!(jsonwebtoken::decode_header(&new_token).unwrap().kid.as_deref(), Some(new_kid.as_str()));
+    assert_eq!(get("/whoami").token(&new_token).send(&app).await.status, StatusCode::OK);
+    assert_eq!(get("/whoami").token(&old_token).send(&app).await.status, StatusCode::OK);
+
+    // a kid nobody knows, even with a secret we do know, and no kid at all
+    let unknown = sign(&path, Some("unknown"), &new_kid, "rotated");
+    assert_eq!(get("/whoami").token(&unknown).send(&app).await.status, StatusCode::UNAUTHORIZED);
+    let without_kid = sign(&path, None, &new_kid, "rotated");
+    assert_eq!(get("/whoami").token(&without_kid).send(&app).await.status, StatusCode::UNAUTHORIZED);
+    // and the kid of one key with the secret of another
+    let mismatched = sign(&path, Some(&old_kid), &new_kid, "rotated");
+    assert_eq!(get("/whoami").token(&mismatched).send(&app).await.status, StatusCode::UNAUTHORIZED);
+}
@@ -0,0 +1,99 @@
+//! Upgrading databases created before migrations existed.
+
+mod common;
+
+use common::{storage, TempDatabase};
+use rusqlite::Connection;
+use url_shortener::migrations;
+
+/// The schema the first release created, without a user_version.
+fn create_baseline(conn: &Connection) {
+    conn.execute_batch(
+        "CREATE TABLE users (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            username TEXT UNIQUE NOT NULL,
+            password TEXT NOT NULL
+        );
+        CREATE TABLE urls (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER,
+            short TEXT UNIQUE NOT NULL,
+            long TEXT NOT NULL,
+            FOREIGN KEY(user_id) REFERENCES users(id)
+        );
+        INSERT INTO users (username, password) VALUES ('old-user', 'hash');
+        INSERT INTO urls (user_id, short, long) VALUES (1, 'old', 'https://example.com/old');",
+    ).unwrap();
+}
+
+fn columns(conn: &Connection, table: &str) -> Vec<String> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table)).unwrap();
+    stmt.query_map([], |row| row.get(1)).unwrap().collect::<Result<_, _>>().unwrap()
+}
+
+fn tables(conn: &Connection) -> Vec<String> {
+    let mut stmt = conn.prepare("SELECT name FROM sqlite_master WHERE type = 'table'").unwrap();
+    stmt.query_map([], |row| row.get(0)).unwrap().collect::<Result<_, _>>().unwrap()
+}
+
+fn assert_upgraded(conn: &Connection) {
+    assert_eq!(migrations::current_version(conn).unwrap(), migrations::latest_version());
+
+    let urls = columns(conn, "urls");
+    for column in ["archived", "expires_at", "max_clicks", "clicks", "redirect_status", "taken_down", "workspace_id"] {
+        assert!(urls.iter().any(|name| name == column), "urls.{} is missing", column);
+    }
+    let users = columns(conn, "users");
+    for column in ["disabled", "role"] {
+        assert!(users.iter().any(|name| name == column), "users.{} is missing", column);
+    }
+    let tables = tables(conn);
+    for table in ["clicks", "refresh_tokens", "api_keys", "workspaces", "workspace_members"] {
+        assert!(tables.iter().any(|name| name == table), "table {} is missing", table);
+    }
+
+    let (long, archived, role): (String, bool, String) = conn.query_row(
+        "SELECT urls.long, urls.archived, users.role FROM urls JOIN users ON users.id = urls.user_id WHERE urls.short = 'old'",
+        [],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    ).unwrap();
+    assert_eq!(long, "https://example.com/old");
+    assert!(!archived);
+    assert_eq!(role, "user");
+}
+
+#[tokio::test]
+async fn upgrade_baseline_database() {
+    let database = TempDatabase::new("upgrade");
+    let mut conn = Connection::open(database.url()).unwrap();
+    create_baseline(&conn);
+    assert_eq!(migrations::current_version(&conn).unwrap(), 0);
+
+    let applied = migrations::run(&mut conn).unwrap();
+    assert_eq!(applied.len(), migrations::MIGRATIONS.len());
+    assert_upgraded(&conn);
+
+    // a second run has nothing left to do
+    assert!(migrations::run(&mut conn).unwrap().is_empty());
+    assert_upgraded(&conn);
+    drop(conn);
+
+    // and the server reads the old link from the upgraded database
+    let db = storage(&database.url()).await;
+    let target = db.get_link_target("old").await.unwrap().expect("the old link survived");
+    assert_eq!(target.long, "https://example.com/old");
+}
+
+#[test]
+fn upgrade_keeps_columns_added_before_migrations() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    create_baseline(&conn);
+    // the archived flag shipped before the migrations did
+    conn.execute("ALTER TABLE urls ADD COLUMN archived INTEGER NOT NULL DEFAULT 0", []).unwrap();
+    conn.execute("UPDATE urls SET archived = 1", []).unwrap();
+
+    migrations::run(&mut conn).unwrap();
+    assert_eq!(migrations::current_version(&conn).unwrap(), migrations::latest_version());
+    let archived: bool = conn.query_row("SELECT archived FROM urls WHERE short = 'old'", [], |row| row.get(0)).unwrap();
+    assert!(archived);
+}
@@ -0,0 +1,34 @@
+//! A configured `server.not_found_page`. The page is read once per process, so this binary
+//! sets up its own config instead of the shared one.
+
+mod common;
+
+use axum::http::{header, StatusCode};
+use common::{app, get, storage};
+use url_shortener::config::{self, AuthConfig, Config, ServerConfig};
+
+#[tokio::test]
+async fn custom_page() {
+    let page = std::env::temp_dir().join(format!("url-shortener-404-{}.html", std::process::id()));
+    std::fs::write(&page, "<h1>Nothing here, try the homepage</h1>").unwrap();
+    config::init(Config {
+        server: ServerConfig { not_found_page: Some(page.clone()), ..Default::default() },
+        auth: AuthConfig { jwt_secret: Some("integration-tests-jwt-signing-secret".to_string()), ..Default::default() },
+        ..Default::default()
+    });
+    let app = app(storage("memory").await);
+    let missing = get("/link/missing").header("accept", "text/html").send(&app).await;
+    assert_eq!(missing.status, StatusCode::NOT_FOUND);
+    assert!(missing.headers[header::CONTENT_TYPE].to_str().unwrap().starts_with("text/html"));
+    assert_eq!(missing.text, "<h1>Nothing here, try the homepage</h1>");
+
+    // the page is read once, the file isn't needed for every request
+    std::fs::remove_file(&page).unwrap();
+    let again = get("/link/missing").send(&app).await;
+    assert_eq!(again.status, StatusCode::NOT_FOUND);
+    assert_eq!(again.text, missing.text);
+
+    // API clients still get JSON
+    let api = get("/link/missing").header("accept", "application/json").send(&app).await;
+    assert_eq!(api.body["error"], "Data not found");
+}
@@ -0,0 +1,155 @@
+//! PostgreSQL storage, run only when `TEST_DATABASE_URL` points at a database the tests may write to:
+//!
+//! ```bash
+//! TEST_DATABASE_URL=postgres://postgres@localhost:5432/shortener_test cargo test --test postgres
+//! ```
+//! Every run uses fresh usernames and codes, so the database doesn't have to be emptied in between.
+
+mod common;
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use common::storage;
+use url_shortener::migrations;
+use url_shortener::model::{hash_api_key, RedirectStatus, Scope, API_KEY_PREFIX};
+use url_shortener::storage::{ClickEvent, LinkOwner, RefreshTokenStatus, Storage};
+
+/// None when `TEST_DATABASE_URL` isn't set and the test should be skipped.
+fn database_url() -> Option<String> {
+    let url = std::env::var("TEST_DATABASE_URL").ok();
+    if url.is_none() {
+        eprintln!("TEST_DATABASE_URL is not set, skipping the PostgreSQL test");
+    }
+    url
+}
+
+async fn postgres() -> Option<Arc<dyn Storage>> {
+    Some(storage(&database_url()?).await)
+}
+
+/// A name no earlier run used.
+fn unique(name: &str) -> String {
+    let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().subsec_nanos();
+    format!("{}-{}-{:x}", name, std::process::id(), nanos)
+}
+
+async fn create_user(db: &dyn Storage, name: &str) -> u32 {
+    let username = unique(name);
+    db.create_user(&username, "secret-password").await.unwrap();
+    db.get_user_id(&username).await.unwrap().unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn init_db_twice() {
+    let Some(url) = database_url() else { return };
+    common::init();
+    let db = url_shortener::storage::connect(&url).unwrap();
+
+    // replicas starting together must not apply a migration twice
+    let (first, second) = tokio::join!(db.init_db(), db.init_db());
+    let (first, second) = (first.unwrap(), second.unwrap());
+    let first: HashSet<u32> = first.iter().map(|(version, _)| *version).collect();
+    assert!(second.iter().all(|(version, _)| !first.contains(version)), "{:?} {:?}", first, second);
+
+    assert!(db.init_db().await.unwrap().is_empty());
+    assert!(migrations::latest_version() > 0);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn insert_url_conflict() {
+    let Some(db) = postgres().await else { return };
+    let owner = LinkOwner { user_id: create_user(db.as_ref(), "inserter").await, workspace_id: None };
+    let code = unique("taken");
+
+    let inserts = (0..16).map(|i| {
+        let (db, code) = (db.clone(), code.clone());
+        tokio::spawn(async move {
+            db.insert_url(&code, &format!("https://example.com/{}", i), owner, None, None, RedirectStatus::default()).await.unwrap()
+        })
+    }).collect::<Vec<_>>();
+    let mut inserted = 0;
+    for insert in inserts {
+        if insert.await.unwrap() {
+            inserted += 1;
+        }
+    }
+    assert_eq!(inserted, 1);
+
+    assert!(!db.insert_url(&code, "https://example.com/again", owner, None, None, RedirectStatus::default()).await.unwrap());
+    assert!(db.get_link_target(&code).await.unwrap().unwrap().long.starts_with("https://example.com/"));
+}
+
+#[tokio::test]
+async fn refresh_token_reuse() {
+    let Some(db) = postgres().await else { return };
+    let user_id = create_user(db.as_ref(), "refresher").await;
+    let family = unique("family");
+    let (first, second) = (unique("first"), unique("second"));
+    let expires_at = chrono::Utc::now().timestamp() + 60;
+    db.store_refresh_token(&first, &family, user_id, expires_at).await.unwrap();
+    db.store_refresh_token(&second, &family, user_id, expires_at).await.unwrap();
+
+    match db.use_refresh_token(&first).await.unwrap() {
+        RefreshTokenStatus::Valid { family_id, user_id: owner } => {
+            assert_eq!(family_id, family);
+            assert_eq!(owner, user_id);
+        },
+        _ => panic!("the first use of a token is valid"),
+    }
+    assert!(matches!(db.use_refresh_token(&first).await.unwrap(), RefreshTokenStatus::Reused));
+    // the reuse revoked the whole family
+    assert!(matches!(db.use_refresh_token(&second).await.unwrap(), RefreshTokenStatus::Reused));
+    assert!(matches!(db.use_refresh_token(&unique("unknown")).await.unwrap(), RefreshTokenStatus::Unknown));
+}
+
+fn click(short: &str) -> ClickEvent {
+    ClickEvent { short: short.to_string(), referrer: None, user_agent: None, ip: None }
+}
+
+#[tokio::test]
+async fn record_click_at_limit() {
+    let Some(db) = postgres().await else { return };
+    let owner = LinkOwner { user_id: create_user(db.as_ref(), "clicker").await, workspace_id: None };
+    let code = unique("limited");
+    assert!(db.insert_url(&code, "https://example.com/limited", owner, None, Some(2), RedirectStatus::default()).await.unwrap());
+
+    assert!(db.record_click(click(&code)).await.unwrap());
+    assert!(db.record_click(click(&code)).await.unwrap());
+    assert!(!db.record_click(click(&code)).await.unwrap());
+    assert!(!db.record_click(click(&code)).await.unwrap());
+    // clicks over the limit aren't recorded either
+    assert_eq!(db.get_link_stats(&code).await.unwrap().total, 2);
+}
+
+#[tokio::test]
+async fn api_key_lookup() {
+    let Some(db) = postgres().await else { return };
+    let username = unique("key-owner");
+    db.create_user(&username, "secret-password").await.unwrap();
+    let user_id = db.get_user_id(&username).await.unwrap().unwrap();
+    let key = format!("{}{}", API_KEY_PREFIX, hex_suffix(&username));
+    let prefix = &key[..API_KEY_PREFIX.len() + 8];
+    let info = db.create_api_key(user_id, "ci", prefix, &hash_api_key(&key), &[Scope::LinksWrite]).await.unwrap();
+    assert!(info.last_used_at.is_none());
+
+    let Some((owner, scopes)) = db.use_api_key(&hash_api_key(&key)).await.unwrap() else {
+        panic!("the key is found by its hash");
+    };
+    assert_eq!(owner, username);
+    assert_eq!(scopes, vec![Scope::LinksWrite]);
+    assert!(db.use_api_key(&key).await.unwrap().is_none());
+    assert!(db.get_api_keys(user_id).await.unwrap()[0].last_used_at.is_some());
+
+    assert!(db.set_user_disabled(&username, true).await.unwrap());
+    assert!(db.use_api_key(&hash_api_key(&key)).await.unwrap().is_none());
+    assert!(db.set_user_disabled(&username, false).await.unwrap());
+
+    assert!(db.revoke_api_key(user_id, info.id).await.unwrap());
+    assert!(db.use_api_key(&hash_api_key(&key)).await.unwrap().is_none());
+}
+
+/// Key material that differs between runs, so keys of earlier runs don't match.
+fn hex_suffix(seed: &str) -> String {
+    hash_api_key(seed)[..48].to_string()
+}
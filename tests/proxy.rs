@@ -0,0 +1,90 @@
+//! Client addresses behind trusted and untrusted proxies.
+
+use std::net::{IpAddr, SocketAddr};
+
+use axum::http::{HeaderMap, HeaderValue};
+use url_shortener::proxy::{client_ip, IpRange};
+
+fn ip(address: &str) -> IpAddr {
+    address.parse().unwrap()
+}
+
+fn peer(address: &str) -> SocketAddr {
+    SocketAddr::new(ip(address), 40000)
+}
+
+fn forwarded_for(values: &[&str]) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    for value in values {
+        headers.append("X-Forwarded-For", HeaderValue::from_str(value).unwrap());
+    }
+    headers
+}
+
+fn ranges(ranges: &[&str]) -> Vec<IpRange> {
+    ranges.iter().map(|range| IpRange::parse(range).unwrap()).collect()
+}
+
+#[test]
+fn ip_ranges() {
+    let private = IpRange::parse("10.0.0.0/8").unwrap();
+    assert!(private.contains(ip("10.1.2.3")));
+    assert!(!private.contains(ip("11.0.0.1")));
+    assert!(private.contains(ip("::ffff:10.0.0.1")));
+
+    let single = IpRange::parse("192.0.2.1").unwrap();
+    assert!(single.contains(ip("192.0.2.1")));
+    assert!(!single.contains(ip("192.0.2.2")));
+
+    let v6 = IpRange::parse("2001:db8::/32").unwrap();
+    assert!(v6.contains(ip("2001:db8:1::1")));
+    assert!(!v6.contains(ip("2001:db9::1")));
+    assert!(!v6.contains(ip("10.0.0.1")));
+
+    assert!(IpRange::parse("0.0.0.0/0").unwrap().contains(ip("203.0.113.9")));
+    for invalid in ["", "proxy.local", "10.0.0.0/33", "2001:db8::/129", "10.0.0.0/x"] {
+        assert!(IpRange::parse(invalid).is_none(), "{:?}", invalid);
+    }
+}
+
+#[test]
+fn untrusted_peer_ignores_the_header() {
+    let headers = forwarded_for(&["198.51.100.1"]);
+    assert_eq!(client_ip(&headers, peer("203.0.113.7"), &[]), ip("203.0.113.7"));
+    assert_eq!(client_ip(&headers, peer("203.0.113.7"), &ranges(&["10.0.0.0/8"])), ip("203.0.113.7"));
+}
+
+#[test]
+fn trusted_proxy_takes_the_rightmost_untrusted_entry() {
+    let proxies = ranges(&["10.0.0.0/8"]);
+    // the client made up the first entry, our proxy appended the address it really came from
+    let headers = forwarded_for(&["1.2.3.4, 198.51.100.1"]);
+    assert_eq!(client_ip(&headers, peer("10.0.0.5"), &proxies), ip("198.51.100.1"));
+
+    // a chain of our own proxies is skipped
+    let headers = forwarded_for(&["1.2.3.4, 198.51.100.1, 10.0.0.9"]);
+    assert_eq!(client_ip(&headers, peer("10.0.0.5"), &proxies), ip("198.51.100.1"));
+
+    // several header lines count as one list
+    let headers = forwarded_for(&["1.2.3.4", "198.51.100.1:5555"]);
+    assert_eq!(client_ip(&headers, peer("10.0.0.5"), &proxies), ip("198.51.100.1"));
+
+    let headers = forwarded_for(&["[2001:db8::1]:443"]);
+    assert_eq!(client_ip(&headers, peer("10.0.0.5"), &proxies), ip("2001:db8::1"));
+}
+
+#[test]
+fn trusted_proxy_without_a_usable_header() {
+    let proxies = ranges(&["10.0.0.0/8"]);
+    assert_eq!(client_ip(&HeaderMap::new(), peer("10.0.0.5"), &proxies), ip("10.0.0.5"));
+
+    // nothing left of garbage is believed
+    let headers = forwarded_for(&["198.51.100.1, garbage, 10.0.0.9"]);
+    assert_eq!(client_ip(&headers, peer("10.0.0.5"), &proxies), ip("10.0.0.9"));
+
+    // only proxies in the chain, the leftmost one is the closest we get to the client
+    let headers = forwarded_for(&["10.0.0.7, 10.0.0.9"]);
+    assert_eq!(client_ip(&headers, peer("10.0.0.5"), &proxies), ip("10.0.0.7"));
+
+    assert_eq!(client_ip(&HeaderMap::new(), peer("::ffff:10.0.0.5"), &proxies), ip("10.0.0.5"));
+}
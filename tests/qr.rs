@@ -0,0 +1,94 @@
+//! Rendered QR code images.
+
+mod common;
+
+use axum::http::{header, StatusCode};
+use common::{app, delete, get, login, post, storage};
+use qrcode::{EcLevel, QrCode};
+use serde_json::json;
+use url_shortener::model::RedirectStatus;
+use url_shortener::qr::{render, QrFormat, QrOptions};
+use url_shortener::storage::LinkOwner;
+
+const DATA: &str = "https://sho.rt/abc123";
+
+fn png(size: u32) -> Option<image::RgbaImage> {
+    let options = QrOptions { format: Some(QrFormat::Png), size: Some(size), ..Default::default() };
+    let rendered = render(DATA, &options).ok()?;
+    assert_eq!(rendered.content_type, "image/png");
+    Some(image::load_from_memory(&rendered.body).unwrap().to_rgba8())
+}
+
+/// Modules across the code with the default margin of 4.
+fn total_modules() -> u32 {
+    QrCode::with_error_correction_level(DATA, EcLevel::M).unwrap().width() as u32 + 2 * 4
+}
+
+#[test]
+fn png_is_exactly_the_requested_size() {
+    let total = total_modules();
+    for size in [256, 231, 257, 100, 1000, 1024, total, total + 1] {
+        let img = png(size).unwrap_or_else(|| panic!("size {} was rejected", size));
+        assert_eq!(img.dimensions(), (size, size), "size {}", size);
+    }
+}
+
+#[test]
+fn png_is_centred() {
+    let total = total_modules();
+    let size = 256;
+    let scale = size / total;
+    let start = (size - total * scale) / 2 + 4 * scale;
+    let img = png(size).unwrap();
+
+    // the finder pattern in the top left corner starts right after the quiet zone
+    assert_eq!(img.get_pixel(start, start).0, [0, 0, 0, 255]);
+    assert_eq!(img.get_pixel(start - 1, start).0, [255, 255, 255, 255]);
+    assert_eq!(img.get_pixel(start, start - 1).0, [255, 255, 255, 255]);
+    // and the one in the top right corner ends as far from the right edge, give or take the odd pixel
+    let end = start + (total - 2 * 4) * scale;
+    assert_eq!(img.get_pixel(end - 1, start).0, [0, 0, 0, 255]);
+    assert_eq!(img.get_pixel(end, start).0, [255, 255, 255, 255]);
+    assert!((size - end).abs_diff(start) <= 1);
+}
+
+#[test]
+fn png_size_is_limited() {
+    assert!(png(total_modules() - 1).is_none());
+    assert!(png(0).is_none());
+    assert!(png(1025).is_none());
+}
+
+#[test]
+fn svg_has_the_requested_size() {
+    let options = QrOptions { format: Some(QrFormat::Svg), size: Some(231), ..Default::default() };
+    let Ok(rendered) = render(DATA, &options) else { panic!("the svg was rejected") };
+    assert_eq!(rendered.content_type, "image/svg+xml");
+    let svg = String::from_utf8(rendered.body).unwrap();
+    assert!(svg.contains("width=\"231\" height=\"231\""));
+}
+
+#[tokio::test]
+async fn only_live_links_get_a_code() {
+    let db = storage("memory").await;
+    let app = app(db.clone());
+    let token = login(&app, "printer").await;
+    for code in ["live", "put-away", "taken-down"] {
+        let link = json!({ "url": format!("https://example.com/{}", code), "code": code });
+        assert_eq!(post("/shorten-link").token(&token).json(link).send(&app).await.status, StatusCode::OK);
+    }
+    assert_eq!(delete("/links/put-away").token(&token).send(&app).await.status, StatusCode::OK);
+    assert!(db.set_url_taken_down("taken-down", true).await.unwrap());
+    let owner = LinkOwner { user_id: db.get_user_id("printer").await.unwrap().unwrap(), workspace_id: None };
+    let yesterday = chrono::Utc::now().timestamp() - 24 * 60 * 60;
+    assert!(db.insert_url("expired", "https://example.com/expired", owner, Some(yesterday), None, RedirectStatus::default()).await.unwrap());
+
+    let live = get("/link/live/qr").send(&app).await;
+    assert_eq!(live.status, StatusCode::OK);
+    assert_eq!(live.headers[header::CONTENT_TYPE], "image/png");
+    for code in ["put-away", "taken-down", "missing"] {
+        assert_eq!(get(&format!("/link/{}/qr", code)).send(&app).await.status, StatusCode::NOT_FOUND, "{}", code);
+    }
+    assert_eq!(get("/link/expired/qr").send(&app).await.status, StatusCode::GONE);
+    assert_eq!(get("/link/live/qr?size=2048").send(&app).await.status, StatusCode::BAD_REQUEST);
+}
@@ -0,0 +1,80 @@
+//! What the redirect endpoint answers and what browsers and proxies may cache of it.
+
+mod common;
+
+use axum::http::{header, StatusCode};
+use common::{app, get, login, post, storage};
+use serde_json::json;
+
+#[tokio::test]
+async fn redirect_statuses_and_caching() {
+    let app = app(storage("memory").await);
+    let token = login(&app, "ivan").await;
+
+    for (code, cache_control) in [
+        (301, "public, max-age=86400"),
+        (308, "public, max-age=86400"),
+        (302, "no-store"),
+        (307, "no-store"),
+    ] {
+        let short = format!("status-{}", code);
+        let link = json!({ "url": format!("https://example.com/{}", code), "code": short, "redirect_status": code });
+        assert_eq!(post("/shorten-link").token(&token).json(link).send(&app).await.status, StatusCode::OK);
+
+        let redirect = get(&format!("/link/{}", short)).send(&app).await;
+        assert_eq!(redirect.status.as_u16(), code);
+        assert_eq!(redirect.headers[header::LOCATION], format!("https://example.com/{}", code));
+        assert_eq!(redirect.headers[header::CACHE_CONTROL], cache_control, "{}", code);
+    }
+
+    // links are temporary unless asked otherwise, so that visits keep being counted
+    let link = json!({ "url": "https://example.com/default", "code": "status-default" });
+    assert_eq!(post("/shorten-link").token(&token).json(link).send(&app).await.status, StatusCode::OK);
+    let redirect = get("/link/status-default").send(&app).await;
+    assert_eq!(redirect.status, StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(redirect.headers[header::CACHE_CONTROL], "no-store");
+
+    for code in [200, 303, 404] {
+        let link = json!({ "url": "https://example.com/", "redirect_status": code });
+        assert_eq!(post("/shorten-link").token(&token).json(link).send(&app).await.status, StatusCode::BAD_REQUEST, "{}", code);
+    }
+}
+
+#[tokio::test]
+async fn permanent_redirects_cannot_expire() {
+    let app = app(storage("memory").await);
+    let token = login(&app, "judy").await;
+    let tomorrow = (chrono::Utc::now() + chrono::Duration::days(1)).to_rfc3339();
+
+    for code in [301, 308] {
+        let expiring = json!({ "url": "https://example.com/", "redirect_status": code, "expires_at": tomorrow });
+        assert_eq!(post("/shorten-link").token(&token).json(expiring).send(&app).await.status, StatusCode::BAD_REQUEST);
+        let limited = json!({ "url": "https://example.com/", "redirect_status": code, "max_clicks": 10 });
+        assert_eq!(post("/shorten-link").token(&token).json(limited).send(&app).await.status, StatusCode::BAD_REQUEST);
+    }
+    for code in [302, 307] {
+        let expiring = json!({ "url": "https://example.com/", "redirect_status": code, "expires_at": tomorrow, "max_clicks": 10 });
+        assert_eq!(post("/shorten-link").token(&token).json(expiring).send(&app).await.status, StatusCode::OK);
+    }
+
+    let links = get("/get-user-links").token(&token).send(&app).await;
+    assert_eq!(links.data().as_array().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn unknown_codes() {
+    let app = app(storage("memory").await);
+
+    let browser = get("/link/missing").header("accept", "text/html,application/xhtml+xml,*/*;q=0.8").send(&app).await;
+    assert_eq!(browser.status, StatusCode::NOT_FOUND);
+    assert!(browser.headers[header::CONTENT_TYPE].to_str().unwrap().starts_with("text/html"));
+    assert_eq!(browser.headers[header::CACHE_CONTROL], "no-store");
+    assert!(browser.text.contains("Link not found"));
+    // no Accept header at all, like curl
+    assert!(get("/link/missing").send(&app).await.headers[header::CONTENT_TYPE].to_str().unwrap().starts_with("text/html"));
+
+    let api = get("/link/missing").header("accept", "application/json").send(&app).await;
+    assert_eq!(api.status, StatusCode::NOT_FOUND);
+    assert!(api.headers[header::CONTENT_TYPE].to_str().unwrap().starts_with("application/json"));
+    assert_eq!(api.body["error"], "Data not found");
+}
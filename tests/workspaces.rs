@@ -0,0 +1,139 @@
+//! Who may do what in a workspace, on the in-memory storage.
+
+mod common;
+
+use axum::http::StatusCode;
+use axum::Router;
+use common::{app, delete, get, login, patch, post, put, storage};
+use serde_json::json;
+
+async fn create_workspace(app: &Router, token: &str, name: &str) -> u32 {
+    let created = post("/workspaces").token(token).json(json!({ "name": name })).send(app).await;
+    assert_eq!(created.status, StatusCode::OK);
+    created.data()["id"].as_u64().unwrap() as u32
+}
+
+async fn set_member(app: &Router, token: &str, workspace: u32, username: &str, role: &str) -> StatusCode {
+    put(&format!("/workspaces/{}/members/{}", workspace, username)).token(token).json(json!({ "role": role })).send(app).await.status
+}
+
+async fn create_link(app: &Router, token: &str, workspace: u32, code: &str) {
+    let link = json!({ "url": format!("https://example.com/{}", code), "code": code, "workspace": workspace });
+    assert_eq!(post("/shorten-link").token(token).json(link).send(app).await.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn viewers_cannot_change_links() {
+    let app = app(storage("memory").await);
+    let owner = login(&app, "olivia").await;
+    let viewer = login(&app, "victor").await;
+    let workspace = create_workspace(&app, &owner, "team").await;
+    assert_eq!(set_member(&app, &owner, workspace, "victor", "viewer").await, StatusCode::OK);
+    create_link(&app, &owner, workspace, "team-link").await;
+
+    let links = get(&format!("/get-user-links?workspace={}", workspace)).token(&viewer).send(&app).await;
+    assert_eq!(links.status, StatusCode::OK);
+    assert_eq!(links.data().as_array().unwrap().len(), 1);
+    assert_eq!(get("/links/team-link/stats").token(&viewer).send(&app).await.status, StatusCode::OK);
+
+    let edit = json!({ "url": "https://example.com/changed" });
+    assert_eq!(patch("/links/team-link").token(&viewer).json(edit).send(&app).await.status, StatusCode::FORBIDDEN);
+    assert_eq!(delete("/links/team-link").token(&viewer).send(&app).await.status, StatusCode::FORBIDDEN);
+    let link = json!({ "url": "https://example.com/", "workspace": workspace });
+    assert_eq!(post("/shorten-link").token(&viewer).json(link).send(&app).await.status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn editors_cannot_manage_members() {
+    let app = app(storage("memory").await);
+    let owner = login(&app, "oscar").await;
+    let editor = login(&app, "edith").await;
+    login(&app, "newcomer").await;
+    let workspace = create_workspace(&app, &owner, "team").await;
+    assert_eq!(set_member(&app, &owner, workspace, "edith", "editor").await, StatusCode::OK);
+    assert_eq!(set_member(&app, &owner, workspace, "newcomer", "viewer").await, StatusCode::OK);
+
+    create_link(&app, &editor, workspace, "edited-by-editor").await;
+    assert_eq!(delete("/links/edited-by-editor").token(&editor).send(&app).await.status, StatusCode::OK);
+
+    assert_eq!(set_member(&app, &editor, workspace, "newcomer", "editor").await, StatusCode::FORBIDDEN);
+    assert_eq!(set_member(&app, &editor, workspace, "edith", "owner").await, StatusCode::FORBIDDEN);
+    let removed = delete(&format!("/workspaces/{}/members/newcomer", workspace)).token(&editor).send(&app).await;
+    assert_eq!(removed.status, StatusCode::FORBIDDEN);
+    let removed = delete(&format!("/workspaces/{}/members/oscar", workspace)).token(&editor).send(&app).await;
+    assert_eq!(removed.status, StatusCode::FORBIDDEN);
+
+    let members = get(&format!("/workspaces/{}/members", workspace)).token(&owner).send(&app).await;
+    assert_eq!(members.data().as_array().unwrap().len(), 3);
+}
+
+#[tokio::test]
+async fn removed_members_lose_access() {
+    let app = app(storage("memory").await);
+    let owner = login(&app, "paula").await;
+    let member = login(&app, "quinn").await;
+    let workspace = create_workspace(&app, &owner, "team").await;
+    assert_eq!(set_member(&app, &owner, workspace, "quinn", "editor").await, StatusCode::OK);
+    create_link(&app, &member, workspace, "left-behind").await;
+
+    let removed = delete(&format!("/workspaces/{}/members/quinn", workspace)).token(&owner).send(&app).await;
+    assert_eq!(removed.status, StatusCode::OK);
+
+    let links = format!("/get-user-links?workspace={}", workspace);
+    assert_eq!(get(&links).token(&member).send(&app).await.status, StatusCode::FORBIDDEN);
+    assert_eq!(get(&format!("/workspaces/{}/members", workspace)).token(&member).send(&app).await.status, StatusCode::FORBIDDEN);
+    // the link stays with the workspace, not with the member who created it
+    assert_eq!(get("/links/left-behind/stats").token(&member).send(&app).await.status, StatusCode::FORBIDDEN);
+    let edit = json!({ "url": "https://example.com/changed" });
+    assert_eq!(patch("/links/left-behind").token(&member).json(edit).send(&app).await.status, StatusCode::FORBIDDEN);
+    assert_eq!(get("/links/left-behind/stats").token(&owner).send(&app).await.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn last_owner_stays() {
+    let app = app(storage("memory").await);
+    let owner = login(&app, "rita").await;
+    login(&app, "sam").await;
+    let workspace = create_workspace(&app, &owner, "team").await;
+    let leave = format!("/workspaces/{}/members/rita", workspace);
+
+    assert_eq!(delete(&leave).token(&owner).send(&app).await.status, StatusCode::BAD_REQUEST);
+    assert_eq!(set_member(&app, &owner, workspace, "rita", "editor").await, StatusCode::BAD_REQUEST);
+
+    // with a second owner the first one may go
+    assert_eq!(set_member(&app, &owner, workspace, "sam", "owner").await, StatusCode::OK);
+    assert_eq!(set_member(&app, &owner, workspace, "rita", "editor").await, StatusCode::OK);
+    assert_eq!(delete(&leave).token(&owner).send(&app).await.status, StatusCode::OK);
+    assert_eq!(get(&format!("/workspaces/{}/members", workspace)).token(&owner).send(&app).await.status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn moving_links_needs_editor_rights_in_both_workspaces() {
+    let app = app(storage("memory").await);
+    let owner = login(&app, "tina").await;
+    let member = login(&app, "uma").await;
+    let source = create_workspace(&app, &owner, "source").await;
+    let target = create_workspace(&app, &owner, "target").await;
+    create_link(&app, &owner, source, "moving").await;
+    let move_to = |workspace: u32| json!({ "workspace": workspace });
+
+    // viewer of the link's workspace, editor of the target
+    assert_eq!(set_member(&app, &owner, source, "uma", "viewer").await, StatusCode::OK);
+    assert_eq!(set_member(&app, &owner, target, "uma", "editor").await, StatusCode::OK);
+    assert_eq!(post("/links/moving/workspace").token(&member).json(move_to(target)).send(&app).await.status, StatusCode::FORBIDDEN);
+
+    // editor of the link's workspace, viewer of the target
+    assert_eq!(set_member(&app, &owner, source, "uma", "editor").await, StatusCode::OK);
+    assert_eq!(set_member(&app, &owner, target, "uma", "viewer").await, StatusCode::OK);
+    assert_eq!(post("/links/moving/workspace").token(&member).json(move_to(target)).send(&app).await.status, StatusCode::FORBIDDEN);
+
+    assert_eq!(set_member(&app, &owner, target, "uma", "editor").await, StatusCode::OK);
+    assert_eq!(post("/links/moving/workspace").token(&member).json(move_to(target)).send(&app).await.status, StatusCode::OK);
+    let links = get(&format!("/get-user-links?workspace={}", target)).token(&owner).send(&app).await;
+    assert_eq!(links.data().as_array().unwrap().len(), 1);
+
+    // a personal link moves only with the rights of its creator
+    let personal = json!({ "url": "https://example.com/personal", "code": "personal" });
+    assert_eq!(post("/shorten-link").token(&owner).json(personal).send(&app).await.status, StatusCode::OK);
+    assert_eq!(post("/links/personal/workspace").token(&member).json(move_to(target)).send(&app).await.status, StatusCode::FORBIDDEN);
+}